@@ -0,0 +1,518 @@
+use std::fmt;
+use std::io::Write;
+use std::ops::Range;
+
+use crate::crc32::crc32;
+use crate::families;
+use crate::md5::md5;
+use crate::tags;
+use crate::{CHUNK_SIZE, MAX_PAYLOAD_SIZE, Uf2Error, num_blocks};
+
+/// The md5 checksum stored at the end of a block's data area
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Md5Checksum {
+    /// Address of the checksummed region
+    pub addr: u32,
+    /// Length of the checksummed region
+    pub len: u32,
+    pub digest: [u8; 16],
+}
+
+/// A single 512 byte UF2 block
+///
+/// `Display` gives a one-line summary; `Debug` shows every header field, and the payload
+/// abbreviated to its length and first and last few bytes.
+#[derive(Clone)]
+pub struct UF2Block {
+    pub flags: u32,
+    pub target_addr: u32,
+    pub payload_size: u32,
+    pub block_no: u32,
+    pub num_blocks: u32,
+    /// The family id if the family flag is set, and otherwise the size of the whole file, or zero
+    /// if it is not known; [`family`](Self::family) and [`file_size`](Self::file_size) read it
+    /// according to the flag
+    pub file_size: u32,
+    pub data: [u8; MAX_PAYLOAD_SIZE],
+}
+
+impl UF2Block {
+    pub const NOT_MAIN_FLASH_FLAG: u32 = 0x0000_0001;
+    pub const FILE_CONTAINER_FLAG: u32 = 0x0000_1000;
+    pub const FAMILY_FLAG: u32 = 0x0000_2000;
+    pub const MD5_FLAG: u32 = 0x0000_4000;
+    pub const EXTENSION_TAGS_FLAG: u32 = 0x0000_8000;
+    /// Size of the md5 checksum trailer stored at the end of the data area
+    pub const MD5_TRAILER_SIZE: usize = 24;
+    /// Size of the CRC-32 stored at the end of the data area by [`set_block_crc`]
+    ///
+    /// [`set_block_crc`]: Self::set_block_crc
+    pub const CRC_TRAILER_SIZE: usize = 4;
+    pub const MAGIC_START_0: u32 = 0x0A324655;
+    pub const MAGIC_START_1: u32 = 0x9E5D5157;
+    pub const MAGIC_END: u32 = 0x0AB16F30;
+
+    pub fn new(payload_size: u32, len: u32) -> Self {
+        Self {
+            flags: 0,
+            target_addr: 0,
+            payload_size,
+            block_no: 0,
+            num_blocks: num_blocks(len, payload_size),
+            file_size: len,
+            data: [0; MAX_PAYLOAD_SIZE],
+        }
+    }
+
+    pub fn set_family(&mut self, family: u32) {
+        self.flags |= Self::FAMILY_FLAG;
+        self.file_size = family;
+    }
+
+    /// Stores the address, length and md5 digest of this block's payload in the trailer at the end
+    /// of the data area and sets the md5 checksum flag.
+    ///
+    /// The payload must not extend into the trailer.
+    pub fn set_md5(&mut self) {
+        assert!(self.payload_size as usize <= MAX_PAYLOAD_SIZE - Self::MD5_TRAILER_SIZE);
+        let digest = md5(self.payload());
+        let trailer = &mut self.data[MAX_PAYLOAD_SIZE - Self::MD5_TRAILER_SIZE..];
+        trailer[..4].copy_from_slice(&self.target_addr.to_le_bytes());
+        trailer[4..8].copy_from_slice(&self.payload_size.to_le_bytes());
+        trailer[8..].copy_from_slice(&digest);
+        self.flags |= Self::MD5_FLAG;
+    }
+
+    /// The checksum in the md5 trailer, if the md5 flag is set
+    pub fn md5_checksum(&self) -> Option<Md5Checksum> {
+        if self.flags & Self::MD5_FLAG == 0 {
+            return None;
+        }
+        let trailer = &self.data[MAX_PAYLOAD_SIZE - Self::MD5_TRAILER_SIZE..];
+        Some(Md5Checksum {
+            addr: u32::from_le_bytes(trailer[..4].try_into().unwrap()),
+            len: u32::from_le_bytes(trailer[4..8].try_into().unwrap()),
+            digest: trailer[8..].try_into().unwrap(),
+        })
+    }
+
+    /// Stores the CRC-32 of this block's payload, little-endian, in the last 4 bytes of the data
+    /// area.
+    ///
+    /// This is not part of the UF2 format, so no flag marks it; it is for receivers that expect a
+    /// checksum there. The payload must not extend into it.
+    pub fn set_block_crc(&mut self) {
+        assert!(self.payload_size as usize <= MAX_PAYLOAD_SIZE - Self::CRC_TRAILER_SIZE);
+        let crc = crc32(self.payload());
+        self.data[MAX_PAYLOAD_SIZE - Self::CRC_TRAILER_SIZE..].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    /// The value in the last 4 bytes of the data area, where [`set_block_crc`] stores a CRC-32
+    ///
+    /// [`set_block_crc`]: Self::set_block_crc
+    pub fn block_crc(&self) -> u32 {
+        u32::from_le_bytes(
+            self.data[MAX_PAYLOAD_SIZE - Self::CRC_TRAILER_SIZE..]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    /// The 512 byte serialized form of this block
+    pub fn as_chunk(&self) -> [u8; CHUNK_SIZE] {
+        let mut chunk = [0; CHUNK_SIZE];
+        let header = [
+            Self::MAGIC_START_0,
+            Self::MAGIC_START_1,
+            self.flags,
+            self.target_addr,
+            self.payload_size,
+            self.block_no,
+            self.num_blocks,
+            self.file_size,
+        ];
+        for (bytes, word) in chunk.chunks_exact_mut(4).zip(header) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        chunk[32..CHUNK_SIZE - 4].copy_from_slice(&self.data);
+        chunk[CHUNK_SIZE - 4..].copy_from_slice(&Self::MAGIC_END.to_le_bytes());
+        chunk
+    }
+
+    /// Serializes this block to `w`
+    pub fn write_to<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        w.write_all(&self.as_chunk())
+    }
+
+    /// Parses a block, checking its magic numbers and payload size
+    pub fn from_chunk(block: &[u8; CHUNK_SIZE]) -> Result<Self, Uf2Error> {
+        Self::check_magic(block)?;
+        let word = |i: usize| u32::from_le_bytes(block[i..i + 4].try_into().unwrap());
+        let payload_size = word(16);
+        if payload_size as usize > MAX_PAYLOAD_SIZE {
+            return Err(Uf2Error::PayloadTooLarge {
+                offset: 0,
+                payload_size,
+            });
+        }
+        Ok(Self {
+            flags: word(8),
+            target_addr: word(12),
+            payload_size,
+            block_no: word(20),
+            num_blocks: word(24),
+            file_size: word(28),
+            data: block[32..CHUNK_SIZE - 4].try_into().unwrap(),
+        })
+    }
+
+    /// Checks only the magic numbers of a block, without parsing the rest of it
+    pub fn check_magic(block: &[u8; CHUNK_SIZE]) -> Result<(), Uf2Error> {
+        let word = |i: usize| u32::from_le_bytes(block[i..i + 4].try_into().unwrap());
+        for (magic, offset, expected) in Self::MAGICS {
+            let found = word(offset);
+            if found != expected {
+                return Err(match Self::swapped_word_size(block) {
+                    Some(word_size) => Uf2Error::ByteSwapped {
+                        offset: 0,
+                        word_size,
+                    },
+                    None => Uf2Error::BadMagic {
+                        offset: 0,
+                        magic,
+                        expected,
+                        found,
+                    },
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The magic numbers framing a block, with their offsets in it
+    const MAGICS: [(&'static str, usize, u32); 3] = [
+        ("magic start 0", 0, Self::MAGIC_START_0),
+        ("magic start 1", 4, Self::MAGIC_START_1),
+        ("magic end", CHUNK_SIZE - 4, Self::MAGIC_END),
+    ];
+
+    /// The size in bits of the words whose bytes, once reversed, would make all three magic
+    /// numbers of `block` valid: 32 for a file written big-endian, or 16 for one whose byte pairs
+    /// were swapped
+    fn swapped_word_size(block: &[u8; CHUNK_SIZE]) -> Option<u32> {
+        let word = |i: usize| u32::from_le_bytes(block[i..i + 4].try_into().unwrap());
+        let swap = |word: u32, word_size: u32| match word_size {
+            32 => word.swap_bytes(),
+            _ => ((word & 0x00FF_00FF) << 8) | ((word >> 8) & 0x00FF_00FF),
+        };
+        [32, 16].into_iter().find(|&word_size| {
+            Self::MAGICS
+                .iter()
+                .all(|&(_, offset, expected)| swap(word(offset), word_size) == expected)
+        })
+    }
+
+    /// The family id, if the family flag is set
+    pub fn family(&self) -> Option<u32> {
+        (self.flags & Self::FAMILY_FLAG != 0).then_some(self.file_size)
+    }
+
+    /// The size of the file this block is part of, if the family flag is not set and so the field
+    /// holds one
+    pub fn file_size(&self) -> Option<u32> {
+        (self.flags & Self::FAMILY_FLAG == 0).then_some(self.file_size)
+    }
+
+    /// The addresses covered by this block's payload, cut off at the end of the 32-bit address
+    /// space for a block that claims to extend past it
+    pub fn address_range(&self) -> Range<u32> {
+        self.target_addr..self.target_addr.saturating_add(self.payload_size)
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.data[..self.payload_size as usize]
+    }
+
+    /// A copy of this block carrying only the part of its payload at the addresses in `range`,
+    /// with its md5 checksum recomputed and its extension tags moved to follow the shorter payload
+    ///
+    /// The md5 flag is cleared if the payload leaves no room for the trailer. Tags that fill the
+    /// rest of the data area are written without the tag that ends the list, where nothing follows
+    /// them that could be read as another tag, and are dropped if they still do not fit.
+    pub(crate) fn cut(&self, range: Range<u32>) -> Self {
+        let start = (range.start - self.target_addr) as usize;
+        let len = range.len();
+        let mut block = self.clone();
+        block.target_addr = range.start;
+        block.payload_size = len as u32;
+        block.data = [0; MAX_PAYLOAD_SIZE];
+        block.data[..len].copy_from_slice(&self.payload()[start..start + len]);
+        block.flags &= !(Self::EXTENSION_TAGS_FLAG | Self::MD5_FLAG);
+        let md5 =
+            self.flags & Self::MD5_FLAG != 0 && len <= MAX_PAYLOAD_SIZE - Self::MD5_TRAILER_SIZE;
+        let tags_end = if md5 {
+            MAX_PAYLOAD_SIZE - Self::MD5_TRAILER_SIZE
+        } else {
+            MAX_PAYLOAD_SIZE
+        };
+        if let Ok(tags) = tags::parse(self)
+            && !tags.is_empty()
+        {
+            let encoded = tags::encode(&tags).expect("parsed tags can be encoded again");
+            let room = tags_end.saturating_sub(len.next_multiple_of(4));
+            // Without the md5 trailer after them, tags reaching the end of the data area need no
+            // tag to end the list, and shorter ones are followed by zeros, which end it
+            let unterminated = encoded.len() - 4;
+            if encoded.len() <= room {
+                tags::write(&mut block, &encoded);
+            } else if !md5 && unterminated <= room {
+                tags::write(&mut block, &encoded[..unterminated]);
+            }
+        }
+        if md5 {
+            block.set_md5();
+        }
+        block
+    }
+}
+
+impl fmt::Display for UF2Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "block {}/{} @ 0x{:08X} ({} bytes",
+            u64::from(self.block_no) + 1,
+            self.num_blocks,
+            self.target_addr,
+            self.payload_size
+        )?;
+        match self.family().map(|id| (id, families::find_by_id(id))) {
+            Some((_, Some(family))) => write!(f, ", family {})", family.short_name),
+            Some((id, None)) => write!(f, ", family 0x{id:08X})"),
+            None => write!(f, ")"),
+        }
+    }
+}
+
+/// Payload bytes shown at each end by `UF2Block`'s `Debug`
+const DEBUG_PAYLOAD_BYTES: usize = 4;
+
+/// Prints a payload as its length and first and last few bytes
+struct PayloadSummary<'a>(&'a [u8]);
+
+impl fmt::Debug for PayloadSummary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bytes [", self.0.len())?;
+        let bytes = |f: &mut fmt::Formatter<'_>, bytes: &[u8]| {
+            bytes
+                .iter()
+                .enumerate()
+                .try_for_each(|(i, byte)| write!(f, "{}{byte:02X}", if i > 0 { " " } else { "" }))
+        };
+        if self.0.len() <= 2 * DEBUG_PAYLOAD_BYTES {
+            bytes(f, self.0)?;
+        } else {
+            bytes(f, &self.0[..DEBUG_PAYLOAD_BYTES])?;
+            write!(f, " .. ")?;
+            bytes(f, &self.0[self.0.len() - DEBUG_PAYLOAD_BYTES..])?;
+        }
+        write!(f, "]")
+    }
+}
+
+impl fmt::Debug for UF2Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UF2Block")
+            .field("flags", &format_args!("{:#010X}", self.flags))
+            .field("target_addr", &format_args!("{:#010X}", self.target_addr))
+            .field("payload_size", &format_args!("{:#X}", self.payload_size))
+            .field("block_no", &format_args!("{:#X}", self.block_no))
+            .field("num_blocks", &format_args!("{:#X}", self.num_blocks))
+            .field(
+                "file_size",
+                &match self.family() {
+                    Some(family) => format!("family {family:#010X}"),
+                    None => self.file_size.to_string(),
+                },
+            )
+            .field(
+                "payload",
+                // Clamped so that a block built with an invalid payload size can still be printed
+                &PayloadSummary(&self.data[..(self.payload_size as usize).min(MAX_PAYLOAD_SIZE)]),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_size_without_family() {
+        let block = UF2Block::new(256, 1000);
+        assert_eq!(block.file_size(), Some(1000));
+        assert_eq!(block.family(), None);
+    }
+
+    #[test]
+    fn file_size_with_family() {
+        let mut block = UF2Block::new(256, 1000);
+        block.set_family(0xE48B_FF56);
+        assert_eq!(block.file_size(), None);
+        assert_eq!(block.family(), Some(0xE48B_FF56));
+    }
+
+    /// A block with every header field set to something other than its default
+    fn sample_block() -> UF2Block {
+        let mut block = UF2Block::new(256, 1000);
+        block.set_family(0xE48B_FF56);
+        block.flags |= UF2Block::NOT_MAIN_FLASH_FLAG;
+        block.target_addr = 0x1000_0100;
+        block.block_no = 1;
+        for (i, byte) in block.data.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        block
+    }
+
+    #[test]
+    fn chunk_round_trip() {
+        let block = sample_block();
+        let parsed = UF2Block::from_chunk(&block.as_chunk()).unwrap();
+        assert_eq!(parsed.flags, block.flags);
+        assert_eq!(parsed.target_addr, block.target_addr);
+        assert_eq!(parsed.payload_size, block.payload_size);
+        assert_eq!(parsed.block_no, block.block_no);
+        assert_eq!(parsed.num_blocks, block.num_blocks);
+        assert_eq!(parsed.file_size, block.file_size);
+        assert_eq!(parsed.data, block.data);
+        assert_eq!(parsed.as_chunk(), block.as_chunk());
+    }
+
+    #[test]
+    fn from_chunk_rejects_corrupt_magic() {
+        for (_, offset, _) in UF2Block::MAGICS {
+            let mut chunk = sample_block().as_chunk();
+            chunk[offset] ^= 0xFF;
+            assert!(matches!(
+                UF2Block::from_chunk(&chunk),
+                Err(Uf2Error::BadMagic { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn from_chunk_rejects_oversized_payload() {
+        let mut block = sample_block();
+        block.payload_size = MAX_PAYLOAD_SIZE as u32 + 1;
+        assert!(matches!(
+            UF2Block::from_chunk(&block.as_chunk()),
+            Err(Uf2Error::PayloadTooLarge {
+                payload_size: 477,
+                ..
+            })
+        ));
+        block.payload_size = MAX_PAYLOAD_SIZE as u32;
+        assert!(UF2Block::from_chunk(&block.as_chunk()).is_ok());
+    }
+
+    #[test]
+    fn cut_full_payload_with_md5_flag() {
+        // Parses, but its payload runs into where the md5 trailer would be
+        let mut block = sample_block();
+        block.payload_size = MAX_PAYLOAD_SIZE as u32;
+        block.flags |= UF2Block::MD5_FLAG;
+        let start = block.target_addr;
+        let cut = block.cut(start + 4..start + MAX_PAYLOAD_SIZE as u32);
+        assert_eq!(cut.payload(), &block.payload()[4..]);
+        assert_eq!(cut.flags & UF2Block::MD5_FLAG, 0);
+
+        let cut = block.cut(start..start + 16);
+        assert_eq!(cut.md5_checksum().unwrap().len, 16);
+    }
+
+    #[test]
+    fn cut_tags_filling_data_area() {
+        let tags = [
+            tags::ExtensionTag {
+                kind: tags::DESCRIPTION,
+                data: vec![b'a'; 248],
+            },
+            tags::ExtensionTag {
+                kind: tags::VERSION,
+                data: vec![b'1'; 212],
+            },
+        ];
+        let encoded = tags::encode(&tags).unwrap();
+        let mut block = UF2Block::new(8, 8);
+        // The tags end exactly at the end of the data area, with no room for the tag ending them
+        tags::write(&mut block, &encoded[..encoded.len() - 4]);
+        assert_eq!(tags::parse(&block).unwrap(), tags);
+        let cut = block.cut(1..8);
+        assert_eq!(cut.payload_size, 7);
+        assert_eq!(tags::parse(&cut).unwrap(), tags);
+
+        // With an md5 trailer to leave room for, they no longer fit and are dropped
+        block.flags |= UF2Block::MD5_FLAG;
+        let cut = block.cut(1..8);
+        assert_eq!(cut.flags & UF2Block::EXTENSION_TAGS_FLAG, 0);
+        assert!(cut.md5_checksum().is_some());
+    }
+
+    /// Deterministic pseudo-random chunks, each the SHA-256 of the previous state repeated
+    fn random_chunks(count: usize) -> impl Iterator<Item = [u8; CHUNK_SIZE]> {
+        let mut state = [0; 32];
+        (0..count).map(move |_| {
+            let mut chunk = [0; CHUNK_SIZE];
+            for part in chunk.chunks_exact_mut(32) {
+                state = crate::sha256::sha256(&state);
+                part.copy_from_slice(&state);
+            }
+            chunk
+        })
+    }
+
+    #[test]
+    fn random_chunks_do_not_panic() {
+        for (i, mut chunk) in random_chunks(2000).enumerate() {
+            // Give most chunks valid magic numbers, so that the rest of the parser is reached
+            if i % 4 != 0 {
+                for (_, offset, magic) in UF2Block::MAGICS {
+                    chunk[offset..offset + 4].copy_from_slice(&magic.to_le_bytes());
+                }
+            }
+            // Keep some payload sizes in range, and some flags with extension tags set
+            if i % 2 == 0 {
+                let payload_size = u32::from(chunk[16]) % (MAX_PAYLOAD_SIZE as u32 + 1);
+                chunk[16..20].copy_from_slice(&payload_size.to_le_bytes());
+            }
+            if i % 3 == 0 {
+                chunk[9] |= (UF2Block::EXTENSION_TAGS_FLAG >> 8) as u8;
+            }
+            if let Ok(block) = UF2Block::from_chunk(&chunk) {
+                assert!(block.payload().len() <= MAX_PAYLOAD_SIZE);
+                let _ = tags::parse(&block);
+                let _ = block.md5_checksum();
+                let _ = format!("{block} {block:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn reader_reports_partial_block() {
+        let mut data = sample_block().as_chunk().to_vec();
+        data.extend_from_slice(&random_chunks(1).next().unwrap()[..100]);
+        let results = crate::Uf2Reader::new(data.as_slice()).collect::<Vec<_>>();
+        assert!(matches!(
+            results.as_slice(),
+            [
+                Ok(_),
+                Err(Uf2Error::TruncatedBlock {
+                    offset: 512,
+                    len: 100
+                })
+            ]
+        ));
+    }
+}
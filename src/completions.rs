@@ -0,0 +1,314 @@
+//! Shell completion scripts, generated from the command line definition so that they stay in step
+//! with it
+
+use std::io::Write;
+
+use clap::{Arg, Command, ValueEnum};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+/// An option or flag, as completions need it
+struct Flag {
+    short: Option<char>,
+    long: Option<String>,
+    help: String,
+    takes_value: bool,
+    /// Values it accepts, if they are a fixed set
+    values: Vec<String>,
+}
+
+impl Flag {
+    fn names(&self) -> impl Iterator<Item = String> {
+        let short = self.short.map(|short| format!("-{short}"));
+        let long = self.long.as_ref().map(|long| format!("--{long}"));
+        short.into_iter().chain(long)
+    }
+}
+
+/// A subcommand and its flags
+struct Subcommand {
+    names: Vec<String>,
+    about: String,
+    flags: Vec<Flag>,
+}
+
+fn first_line(text: Option<&clap::builder::StyledStr>) -> String {
+    text.map(|text| text.to_string())
+        .and_then(|text| text.lines().next().map(str::to_string))
+        .unwrap_or_default()
+}
+
+fn flags(command: &Command) -> Vec<Flag> {
+    command
+        .get_arguments()
+        .filter(|arg| !arg.is_positional() && !arg.is_hide_set())
+        .map(|arg: &Arg| Flag {
+            short: arg.get_short(),
+            long: arg.get_long().map(str::to_string),
+            help: first_line(arg.get_help()),
+            takes_value: arg.get_action().takes_values(),
+            values: arg
+                .get_possible_values()
+                .iter()
+                .filter(|value| !value.is_hide_set())
+                .map(|value| value.get_name().to_string())
+                .collect(),
+        })
+        .collect()
+}
+
+fn subcommands(command: &Command) -> Vec<Subcommand> {
+    command
+        .get_subcommands()
+        .filter(|sub| !sub.is_hide_set())
+        .map(|sub| Subcommand {
+            names: std::iter::once(sub.get_name())
+                .chain(sub.get_visible_aliases())
+                .map(str::to_string)
+                .collect(),
+            about: first_line(sub.get_about()),
+            flags: flags(sub),
+        })
+        .collect()
+}
+
+/// Writes the completion script for `shell` to `w`
+pub fn generate(shell: Shell, command: &mut Command, w: &mut impl Write) -> std::io::Result<()> {
+    // Building propagates global flags to every subcommand
+    command.build();
+    let name = command.get_name().to_string();
+    let globals = flags(command);
+    let subcommands = subcommands(command);
+    match shell {
+        Shell::Bash => bash(&name, &globals, &subcommands, w),
+        Shell::Zsh => zsh(&name, &subcommands, w),
+        Shell::Fish => fish(&name, &globals, &subcommands, w),
+        Shell::Powershell => powershell(&name, &globals, &subcommands, w),
+    }
+}
+
+fn bash(
+    name: &str,
+    globals: &[Flag],
+    subcommands: &[Subcommand],
+    w: &mut impl Write,
+) -> std::io::Result<()> {
+    let function = format!("_{}", name.replace('-', "_"));
+    let top_level = subcommands
+        .iter()
+        .flat_map(|sub| sub.names.iter().cloned())
+        .chain(globals.iter().flat_map(Flag::names))
+        .collect::<Vec<_>>();
+    writeln!(w, "{function}() {{")?;
+    writeln!(
+        w,
+        "    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\" sub=\"\" i"
+    )?;
+    writeln!(w, "    for ((i = 1; i < COMP_CWORD; i++)); do")?;
+    writeln!(w, "        if [[ \"${{COMP_WORDS[i]}}\" != -* ]]; then")?;
+    writeln!(w, "            sub=\"${{COMP_WORDS[i]}}\"")?;
+    writeln!(w, "            break")?;
+    writeln!(w, "        fi")?;
+    writeln!(w, "    done")?;
+    writeln!(w, "    local opts")?;
+    writeln!(w, "    case \"$sub\" in")?;
+    writeln!(w, "        \"\") opts=\"{}\" ;;", top_level.join(" "))?;
+    for sub in subcommands {
+        let opts = sub.flags.iter().flat_map(Flag::names).collect::<Vec<_>>();
+        writeln!(
+            w,
+            "        {}) opts=\"{}\" ;;",
+            sub.names.join("|"),
+            opts.join(" ")
+        )?;
+    }
+    writeln!(w, "    esac")?;
+    writeln!(w, "    if [[ -z \"$sub\" || \"$cur\" == -* ]]; then")?;
+    writeln!(w, "        COMPREPLY=($(compgen -W \"$opts\" -- \"$cur\"))")?;
+    writeln!(w, "    else")?;
+    writeln!(w, "        COMPREPLY=($(compgen -f -- \"$cur\"))")?;
+    writeln!(w, "    fi")?;
+    writeln!(w, "}}")?;
+    writeln!(w, "complete -o filenames -F {function} {name}")
+}
+
+/// Quotes `text` for use inside a zsh `_arguments` or `_describe` spec
+fn zsh_escape(text: &str) -> String {
+    text.replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:")
+}
+
+fn zsh(name: &str, subcommands: &[Subcommand], w: &mut impl Write) -> std::io::Result<()> {
+    writeln!(w, "#compdef {name}")?;
+    writeln!(w)?;
+    writeln!(w, "_{name}() {{")?;
+    writeln!(w, "    local -a subcommands")?;
+    writeln!(w, "    subcommands=(")?;
+    for sub in subcommands {
+        for sub_name in &sub.names {
+            writeln!(w, "        '{sub_name}:{}'", zsh_escape(&sub.about))?;
+        }
+    }
+    writeln!(w, "    )")?;
+    writeln!(w, "    if (( CURRENT == 2 )); then")?;
+    writeln!(w, "        _describe 'command' subcommands")?;
+    writeln!(w, "        return")?;
+    writeln!(w, "    fi")?;
+    writeln!(w, "    case $words[2] in")?;
+    for sub in subcommands {
+        writeln!(w, "        {})", sub.names.join("|"))?;
+        writeln!(w, "            _arguments \\")?;
+        for flag in &sub.flags {
+            let help = zsh_escape(&flag.help);
+            let values = if !flag.values.is_empty() {
+                format!(":value:({})", flag.values.join(" "))
+            } else if flag.takes_value {
+                ":value:_files".to_string()
+            } else {
+                String::new()
+            };
+            for flag_name in flag.names() {
+                writeln!(w, "                '{flag_name}[{help}]{values}' \\")?;
+            }
+        }
+        writeln!(w, "                '*:file:_files'")?;
+        writeln!(w, "            ;;")?;
+    }
+    writeln!(w, "    esac")?;
+    writeln!(w, "}}")?;
+    writeln!(w)?;
+    writeln!(w, "_{name} \"$@\"")
+}
+
+fn fish_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+fn fish(
+    name: &str,
+    globals: &[Flag],
+    subcommands: &[Subcommand],
+    w: &mut impl Write,
+) -> std::io::Result<()> {
+    let write_flag = |w: &mut dyn Write, condition: &str, flag: &Flag| {
+        write!(w, "complete -c {name} -n '{condition}'")?;
+        if let Some(short) = flag.short {
+            write!(w, " -s {short}")?;
+        }
+        if let Some(long) = &flag.long {
+            write!(w, " -l {long}")?;
+        }
+        if !flag.values.is_empty() {
+            write!(w, " -x -a '{}'", flag.values.join(" "))?;
+        } else if flag.takes_value {
+            write!(w, " -r")?;
+        }
+        writeln!(w, " -d '{}'", fish_escape(&flag.help))
+    };
+    for flag in globals {
+        write_flag(w, "__fish_use_subcommand", flag)?;
+    }
+    for sub in subcommands {
+        for sub_name in &sub.names {
+            writeln!(
+                w,
+                "complete -c {name} -n '__fish_use_subcommand' -f -a {sub_name} -d '{}'",
+                fish_escape(&sub.about)
+            )?;
+        }
+        let condition = format!("__fish_seen_subcommand_from {}", sub.names.join(" "));
+        for flag in &sub.flags {
+            write_flag(w, &condition, flag)?;
+        }
+    }
+    Ok(())
+}
+
+fn powershell_escape(text: &str) -> String {
+    text.replace('\'', "''")
+}
+
+fn powershell(
+    name: &str,
+    globals: &[Flag],
+    subcommands: &[Subcommand],
+    w: &mut impl Write,
+) -> std::io::Result<()> {
+    let write_entries = |w: &mut dyn Write, entries: &[(String, String)]| {
+        for (text, help) in entries {
+            // A completion's tooltip cannot be empty
+            let help = if help.is_empty() { text } else { help };
+            writeln!(
+                w,
+                "        @{{ Text = '{}'; Help = '{}' }}",
+                powershell_escape(text),
+                powershell_escape(help)
+            )?;
+        }
+        Ok::<_, std::io::Error>(())
+    };
+    let flag_entries = |flags: &[Flag]| {
+        flags
+            .iter()
+            .flat_map(|flag| flag.names().map(|flag_name| (flag_name, flag.help.clone())))
+            .collect::<Vec<_>>()
+    };
+    writeln!(w, "$completions = @{{")?;
+    let top_level = subcommands
+        .iter()
+        .flat_map(|sub| {
+            sub.names
+                .iter()
+                .map(|sub_name| (sub_name.clone(), sub.about.clone()))
+        })
+        .chain(flag_entries(globals))
+        .collect::<Vec<_>>();
+    writeln!(w, "    '' = @(")?;
+    write_entries(w, &top_level)?;
+    writeln!(w, "    )")?;
+    for sub in subcommands {
+        for sub_name in &sub.names {
+            writeln!(w, "    '{sub_name}' = @(")?;
+            write_entries(w, &flag_entries(&sub.flags))?;
+            writeln!(w, "    )")?;
+        }
+    }
+    writeln!(w, "}}")?;
+    writeln!(
+        w,
+        "Register-ArgumentCompleter -Native -CommandName '{name}' -ScriptBlock {{"
+    )?;
+    writeln!(
+        w,
+        "    param($wordToComplete, $commandAst, $cursorPosition)"
+    )?;
+    writeln!(w, "    $sub = ''")?;
+    writeln!(
+        w,
+        "    foreach ($element in $commandAst.CommandElements | Select-Object -Skip 1) {{"
+    )?;
+    writeln!(w, "        $text = $element.ToString()")?;
+    writeln!(
+        w,
+        "        if ($text -ne $wordToComplete -and -not $text.StartsWith('-')) {{ $sub = $text; break }}"
+    )?;
+    writeln!(w, "    }}")?;
+    writeln!(w, "    $completions[$sub] |")?;
+    writeln!(
+        w,
+        "        Where-Object {{ $_.Text -like \"$wordToComplete*\" }} |"
+    )?;
+    writeln!(
+        w,
+        "        ForEach-Object {{ [System.Management.Automation.CompletionResult]::new($_.Text, $_.Text, 'ParameterValue', $_.Help) }}"
+    )?;
+    writeln!(w, "}}")
+}
@@ -0,0 +1,170 @@
+//! UF2 file containers, whose blocks carry named files instead of flash contents
+//!
+//! Each block of a container sets the file container flag. Its target address is the offset of its
+//! payload in the file, its file size is the size of the whole file, and the file's name follows
+//! the payload in the data area as a null-terminated string. Block numbers count every block of
+//! the container, not just those of one file.
+
+use std::ops::Range;
+
+use crate::{MAX_PAYLOAD_SIZE, UF2Block, Uf2Error, renumber};
+
+/// Bytes of a file carried by each block, which leaves the rest of the data area for its name
+pub const PAYLOAD_SIZE: u32 = 256;
+/// Longest file name that fits after a full payload, leaving room for its null terminator
+pub const MAX_NAME_LEN: usize = MAX_PAYLOAD_SIZE - PAYLOAD_SIZE as usize - 1;
+
+/// A file stored in a container
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContainedFile {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// Encodes `files` as the blocks of one container, in order
+///
+/// An empty file is stored as a single block with no payload, so that it is not lost.
+pub fn pack(files: &[ContainedFile]) -> Result<Vec<UF2Block>, Uf2Error> {
+    let mut blocks = Vec::new();
+    for file in files {
+        if file.name.is_empty() || file.name.len() > MAX_NAME_LEN || file.name.contains('\0') {
+            return Err(Uf2Error::InvalidFileName {
+                name: file.name.clone(),
+            });
+        }
+        let len = u32::try_from(file.data.len()).map_err(|_| Uf2Error::TooLarge {
+            addr: 0,
+            len: file.data.len(),
+        })?;
+        let start = blocks.len();
+        for offset in (0..file.data.len()).step_by(PAYLOAD_SIZE as usize) {
+            let payload = &file.data[offset..file.data.len().min(offset + PAYLOAD_SIZE as usize)];
+            blocks.push(file_block(file, offset as u32, payload, len));
+        }
+        if blocks.len() == start {
+            blocks.push(file_block(file, 0, &[], 0));
+        }
+    }
+    if u32::try_from(blocks.len()).is_err() {
+        return Err(Uf2Error::TooLarge {
+            addr: 0,
+            len: files.iter().map(|file| file.data.len()).sum(),
+        });
+    }
+    renumber(&mut blocks);
+    Ok(blocks)
+}
+
+fn file_block(file: &ContainedFile, offset: u32, payload: &[u8], len: u32) -> UF2Block {
+    let mut block = UF2Block::new(payload.len() as u32, len);
+    block.flags = UF2Block::FILE_CONTAINER_FLAG;
+    block.target_addr = offset;
+    block.data[..payload.len()].copy_from_slice(payload);
+    let name = &mut block.data[payload.len()..payload.len() + file.name.len()];
+    name.copy_from_slice(file.name.as_bytes());
+    block
+}
+
+/// The name of the file a container block belongs to, if it has one
+pub fn file_name(block: &UF2Block) -> Option<&str> {
+    if block.flags & UF2Block::FILE_CONTAINER_FLAG == 0 {
+        return None;
+    }
+    let rest = block.data.get(block.payload_size as usize..)?;
+    let name = std::ffi::CStr::from_bytes_until_nul(rest).ok()?;
+    name.to_str().ok().filter(|name| !name.is_empty())
+}
+
+/// Reassembles the files stored in the container blocks of `blocks`, in order of each file's first
+/// block
+///
+/// Blocks without the file container flag are skipped. Where blocks of a file overlap, the later
+/// block wins.
+pub fn unpack(blocks: &[UF2Block]) -> Result<Vec<ContainedFile>, Uf2Error> {
+    let mut files: Vec<PartialFile> = Vec::new();
+    for block in blocks {
+        if block.flags & UF2Block::FILE_CONTAINER_FLAG == 0 {
+            continue;
+        }
+        let invalid = |reason: String| Uf2Error::InvalidContainer {
+            block_no: block.block_no,
+            reason,
+        };
+        let name = file_name(block)
+            .ok_or_else(|| invalid("has no null-terminated UTF-8 file name".to_string()))?;
+        let partial = match files.iter().position(|partial| partial.file.name == name) {
+            Some(i) => &mut files[i],
+            None => {
+                files.push(PartialFile {
+                    file: ContainedFile {
+                        name: name.to_string(),
+                        data: Vec::new(),
+                    },
+                    size: block.file_size,
+                    seen: Vec::new(),
+                });
+                files.last_mut().unwrap()
+            }
+        };
+        if partial.size != block.file_size {
+            return Err(invalid(format!(
+                "gives {name} a size of {} bytes, but an earlier block gives {}",
+                block.file_size, partial.size
+            )));
+        }
+        let file = &mut partial.file;
+        let range = block.address_range();
+        if range.end - range.start != block.payload_size || range.end > block.file_size {
+            return Err(invalid(format!(
+                "holds bytes {}..{} of {name}, past its end at {}",
+                block.target_addr,
+                u64::from(block.target_addr) + u64::from(block.payload_size),
+                block.file_size
+            )));
+        }
+        if file.data.len() < range.end as usize {
+            file.data.resize(range.end as usize, 0);
+        }
+        file.data[range.start as usize..range.end as usize].copy_from_slice(block.payload());
+        partial.seen.push(range);
+    }
+    files
+        .into_iter()
+        .map(|partial| {
+            let PartialFile {
+                file,
+                size,
+                mut seen,
+            } = partial;
+            seen.sort_by_key(|range| range.start);
+            let mut covered = 0;
+            for Range { start, end } in seen {
+                if start > covered {
+                    return Err(Uf2Error::IncompleteFile {
+                        name: file.name,
+                        start: covered,
+                        end: start,
+                    });
+                }
+                covered = covered.max(end);
+            }
+            if covered < size {
+                return Err(Uf2Error::IncompleteFile {
+                    name: file.name,
+                    start: covered,
+                    end: size,
+                });
+            }
+            Ok(file)
+        })
+        .collect()
+}
+
+/// A file being reassembled, along with its size and the ranges of it that blocks have filled
+///
+/// The data only grows as blocks fill it, so that a corrupt size cannot cause a huge allocation.
+struct PartialFile {
+    file: ContainedFile,
+    size: u32,
+    seen: Vec<Range<u32>>,
+}
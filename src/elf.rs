@@ -0,0 +1,300 @@
+//! Minimal ELF reader for the loadable segments of a firmware image, and the UF2 family it may
+//! name
+
+use anyhow::{Context, bail, ensure};
+
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOTE: u32 = 7;
+const SHT_NOBITS: u32 = 8;
+const SHN_ABS: u16 = 0xFFF1;
+
+/// Name of the note whose 4-byte descriptor is the family id, in the ELF's byte order
+pub const FAMILY_NOTE: &str = "UF2";
+/// Symbols giving the family id: the value of an absolute symbol, or otherwise the 4 bytes the
+/// symbol points to
+pub const FAMILY_SYMBOLS: [&str; 2] = ["__uf2_family_id", "uf2_family_id"];
+
+/// A `PT_LOAD` program header and the bytes it loads from the file
+pub struct Segment<'a> {
+    pub paddr: u64,
+    pub memsz: u64,
+    pub data: &'a [u8],
+}
+
+pub struct Elf<'a> {
+    pub entry: u64,
+    pub segments: Vec<Segment<'a>>,
+}
+
+/// Where in an ELF its family id was found
+pub enum FamilySource {
+    /// A [`FAMILY_NOTE`] note in the named section
+    Note(String),
+    /// One of the [`FAMILY_SYMBOLS`]
+    Symbol(&'static str),
+}
+
+impl std::fmt::Display for FamilySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Note(section) => write!(f, "the {FAMILY_NOTE} note in {section}"),
+            Self::Symbol(name) => write!(f, "the symbol {name}"),
+        }
+    }
+}
+
+/// A section header
+struct Section {
+    name: u32,
+    kind: u32,
+    addr: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    is_64: bool,
+    big_endian: bool,
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&self, offset: usize) -> anyhow::Result<[u8; N]> {
+        offset
+            .checked_add(N)
+            .and_then(|end| self.data.get(offset..end))
+            .map(|bytes| bytes.try_into().unwrap())
+            .with_context(|| format!("ELF file is truncated at offset {offset:#X}"))
+    }
+
+    fn u16(&self, offset: usize) -> anyhow::Result<u16> {
+        let bytes = self.bytes(offset)?;
+        Ok(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> anyhow::Result<u32> {
+        let bytes = self.bytes(offset)?;
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    /// Reads a word that is 4 bytes in ELF32 and 8 bytes in ELF64
+    fn word(&self, offset: usize) -> anyhow::Result<u64> {
+        if !self.is_64 {
+            return self.u32(offset).map(u64::from);
+        }
+        let bytes = self.bytes(offset)?;
+        Ok(if self.big_endian {
+            u64::from_be_bytes(bytes)
+        } else {
+            u64::from_le_bytes(bytes)
+        })
+    }
+}
+
+impl<'a> Reader<'a> {
+    /// The `len` bytes at `offset`
+    fn slice(&self, offset: u64, len: u64) -> Option<&'a [u8]> {
+        let offset = usize::try_from(offset).ok()?;
+        let end = offset.checked_add(usize::try_from(len).ok()?)?;
+        self.data.get(offset..end)
+    }
+
+    fn sections(&self) -> anyhow::Result<Vec<Section>> {
+        let (shoff, shentsize, shnum) = if self.is_64 {
+            (40, 58, 60)
+        } else {
+            (32, 46, 48)
+        };
+        let shoff = usize::try_from(self.word(shoff)?).context("Section offset is out of range")?;
+        let shentsize = self.u16(shentsize)? as usize;
+        let shnum = self.u16(shnum)? as usize;
+        (0..shnum)
+            .map(|i| {
+                let header = shoff + i * shentsize;
+                let name = self.u32(header)?;
+                let kind = self.u32(header + 4)?;
+                Ok(if self.is_64 {
+                    Section {
+                        name,
+                        kind,
+                        addr: self.word(header + 16)?,
+                        offset: self.word(header + 24)?,
+                        size: self.word(header + 32)?,
+                        link: self.u32(header + 40)?,
+                    }
+                } else {
+                    Section {
+                        name,
+                        kind,
+                        addr: self.word(header + 12)?,
+                        offset: self.word(header + 16)?,
+                        size: self.word(header + 20)?,
+                        link: self.u32(header + 24)?,
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// The null-terminated string at `index` in the string table `table`
+    fn string(&self, table: Option<&Section>, index: u32) -> Option<&'a str> {
+        let table = table?;
+        let bytes = self
+            .slice(table.offset, table.size)?
+            .get(index as usize..)?;
+        std::ffi::CStr::from_bytes_until_nul(bytes)
+            .ok()?
+            .to_str()
+            .ok()
+    }
+}
+
+/// Checks for the ELF magic number
+pub fn is_elf(data: &[u8]) -> bool {
+    data.starts_with(b"\x7FELF")
+}
+
+fn reader(data: &[u8]) -> anyhow::Result<Reader<'_>> {
+    ensure!(is_elf(data), "File is not an ELF file");
+    Ok(Reader {
+        data,
+        is_64: match data.get(4) {
+            Some(1) => false,
+            Some(2) => true,
+            class => bail!("Unknown ELF class {class:?}"),
+        },
+        big_endian: match data.get(5) {
+            Some(1) => false,
+            Some(2) => true,
+            encoding => bail!("Unknown ELF data encoding {encoding:?}"),
+        },
+    })
+}
+
+/// Parses the ELF header and program headers of `data`
+pub fn parse(data: &[u8]) -> anyhow::Result<Elf<'_>> {
+    let reader = reader(data)?;
+    let (phoff, phentsize, phnum) = if reader.is_64 {
+        (32, 54, 56)
+    } else {
+        (28, 42, 44)
+    };
+    let entry = reader.word(24)?;
+    let phoff = reader.word(phoff)?;
+    let phentsize = reader.u16(phentsize)? as usize;
+    let phnum = reader.u16(phnum)? as usize;
+    let phoff = usize::try_from(phoff).context("Program header offset is out of range")?;
+
+    let mut segments = Vec::new();
+    for i in 0..phnum {
+        let header = phoff + i * phentsize;
+        if reader.u32(header)? != PT_LOAD {
+            continue;
+        }
+        let (offset, paddr, filesz, memsz) = if reader.is_64 {
+            (
+                reader.word(header + 8)?,
+                reader.word(header + 24)?,
+                reader.word(header + 32)?,
+                reader.word(header + 40)?,
+            )
+        } else {
+            (
+                reader.word(header + 4)?,
+                reader.word(header + 12)?,
+                reader.word(header + 16)?,
+                reader.word(header + 20)?,
+            )
+        };
+        let data = usize::try_from(offset)
+            .ok()
+            .zip(usize::try_from(filesz).ok())
+            .and_then(|(offset, filesz)| data.get(offset..offset.checked_add(filesz)?))
+            .with_context(|| format!("Segment {i} extends past the end of the file"))?;
+        segments.push(Segment { paddr, memsz, data });
+    }
+    Ok(Elf { entry, segments })
+}
+
+/// Looks for the family id in a [`FAMILY_NOTE`] note, then in the [`FAMILY_SYMBOLS`]
+pub fn find_family(data: &[u8]) -> anyhow::Result<Option<(u32, FamilySource)>> {
+    let reader = reader(data)?;
+    let sections = reader.sections()?;
+    let shstrndx = reader.u16(if reader.is_64 { 62 } else { 50 })?;
+    let section_names = sections.get(usize::from(shstrndx));
+    for section in sections.iter().filter(|section| section.kind == SHT_NOTE) {
+        let start = usize::try_from(section.offset).context("Note offset is out of range")?;
+        let end = usize::try_from(section.size)
+            .ok()
+            .and_then(|size| start.checked_add(size))
+            .context("Note section is too large")?;
+        // Each note is its name and descriptor sizes, its type, and then its name and descriptor,
+        // each padded to 4 bytes
+        let mut note = start;
+        while note + 12 <= end {
+            let namesz = reader.u32(note)? as usize;
+            let descsz = reader.u32(note + 4)? as usize;
+            let name = note + 12;
+            let desc = name + namesz.next_multiple_of(4);
+            let name = reader
+                .slice(name as u64, namesz as u64)
+                .context("Note is truncated")?;
+            if name.strip_suffix(b"\0").unwrap_or(name) == FAMILY_NOTE.as_bytes() && descsz == 4 {
+                let section = reader
+                    .string(section_names, section.name)
+                    .unwrap_or("a note");
+                return Ok(Some((
+                    reader.u32(desc)?,
+                    FamilySource::Note(section.to_string()),
+                )));
+            }
+            note = desc + descsz.next_multiple_of(4);
+        }
+    }
+    let entsize = if reader.is_64 { 24 } else { 16 };
+    for table in sections.iter().filter(|section| section.kind == SHT_SYMTAB) {
+        let names = sections.get(table.link as usize);
+        let start = usize::try_from(table.offset).context("Symbol table offset is out of range")?;
+        for i in 0..table.size / entsize {
+            let symbol = start + i as usize * entsize as usize;
+            let name = reader.string(names, reader.u32(symbol)?);
+            let Some(&name) = FAMILY_SYMBOLS.iter().find(|&&known| Some(known) == name) else {
+                continue;
+            };
+            let (value, shndx) = if reader.is_64 {
+                (reader.word(symbol + 8)?, reader.u16(symbol + 6)?)
+            } else {
+                (reader.word(symbol + 4)?, reader.u16(symbol + 14)?)
+            };
+            let family = if shndx == SHN_ABS {
+                u32::try_from(value).ok()
+            } else {
+                sections
+                    .get(usize::from(shndx))
+                    .filter(|section| section.kind != SHT_NOBITS)
+                    .and_then(|section| {
+                        let offset = value.checked_sub(section.addr)?;
+                        if offset.checked_add(4)? > section.size {
+                            return None;
+                        }
+                        section.offset.checked_add(offset)
+                    })
+                    .and_then(|offset| reader.u32(usize::try_from(offset).ok()?).ok())
+            };
+            let family = family
+                .with_context(|| format!("Symbol {name} does not hold a 32-bit family id"))?;
+            return Ok(Some((family, FamilySource::Symbol(name))));
+        }
+    }
+    Ok(None)
+}
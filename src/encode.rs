@@ -0,0 +1,1223 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::num::NonZeroUsize;
+
+use crate::tags::{self, ExtensionTag, ImageChecksum, ImageDigest};
+use crate::{CHUNK_SIZE, MAX_PAYLOAD_SIZE, Region, UF2Block, Uf2Error, num_blocks};
+
+/// Settings controlling how a binary is laid out into UF2 blocks
+///
+/// The [`Default`] writes the input as is: pages of one byte, with no family, from address 0, and
+/// nothing added to the blocks. `clif generate` starts from the same defaults, except that a binary
+/// given a family with a known flash base and no `--base-address` is placed at that base.
+#[derive(Clone, Debug)]
+pub struct Uf2Options {
+    /// Payload sizes are kept to a multiple of this many bytes
+    pub page_size: u32,
+    /// Family id to tag every block with
+    pub family: Option<u32>,
+    /// Target address of the first block
+    pub base_address: u32,
+    /// Byte used to pad a trailing partial page; if unset, the input must be page aligned
+    pub fill: Option<u8>,
+    /// Byte the data area of every block is set to past its payload, where extension tags and
+    /// checksums do not cover it
+    pub block_fill: u8,
+    /// Reserve the end of every block for an md5 checksum of its payload
+    pub md5: bool,
+    /// Store a CRC-32 of every block's payload in the last 4 bytes of its data area, which cannot
+    /// be combined with `md5`
+    pub block_crc: bool,
+    /// Mark every block as not intended for main flash
+    pub not_main_flash: bool,
+    /// Extension tags to store after the payload of every block, which leaves less room for it
+    pub extension_tags: Vec<ExtensionTag>,
+    /// Block count to record in every block in place of the real one, for bootloaders that expect
+    /// the count of a whole image when only some of its blocks are sent
+    pub num_blocks_override: Option<u32>,
+    /// File size to record in every block without a family id in place of the real one
+    pub file_size_override: Option<u32>,
+    /// Append blocks with no payload, marked not to be written to flash, until the block count is
+    /// a multiple of this; 0 and 1 add none. The blocks count towards `num_blocks`, and the last of
+    /// them holds the image checksum
+    pub block_multiple: u32,
+    /// Number to give the first block, for writing blocks from the middle of a larger image; the
+    /// blocks after it count up from it. Usually set along with `num_blocks_override`
+    pub first_block_no: u32,
+    /// Checksum of the payloads of the whole image to store in a tag in its last block. Every
+    /// block leaves room for the tag, as the encoder cannot always tell which block is last
+    pub image_checksum: Option<ImageChecksum>,
+}
+
+/// What [`encode_bin_to_uf2`] wrote
+#[derive(Clone, Copy, Debug)]
+pub struct EncodeSummary {
+    pub num_blocks: u32,
+    /// Number of blocks with no payload added to pad `num_blocks` to a multiple of
+    /// [`Uf2Options::block_multiple`]
+    pub padding: u32,
+    /// Number of fill bytes used to pad the final page
+    pub filled: u32,
+}
+
+impl Default for Uf2Options {
+    fn default() -> Self {
+        Self {
+            page_size: 1,
+            family: None,
+            base_address: 0,
+            fill: None,
+            block_fill: 0,
+            md5: false,
+            block_crc: false,
+            not_main_flash: false,
+            extension_tags: Vec::new(),
+            num_blocks_override: None,
+            file_size_override: None,
+            block_multiple: 0,
+            first_block_no: 0,
+            image_checksum: None,
+        }
+    }
+}
+
+impl Uf2Options {
+    /// The page size, and the largest multiple of it that fits in a block
+    fn layout(&self) -> Result<(u32, u32), Uf2Error> {
+        let page_size = self.page_size;
+        let mut capacity = MAX_PAYLOAD_SIZE as u32;
+        if self.md5 && self.block_crc {
+            return Err(Uf2Error::CrcWithMd5);
+        }
+        if self.md5 {
+            capacity -= UF2Block::MD5_TRAILER_SIZE as u32;
+        }
+        if self.block_crc {
+            capacity -= UF2Block::CRC_TRAILER_SIZE as u32;
+        }
+        let reserved = self.reserved_tags();
+        if !reserved.is_empty() {
+            // Tags start on a 4-byte boundary, so the payload must end before the last one that
+            // leaves room for them
+            let len = tags::encode(&reserved)?.len() as u32;
+            let available = capacity.saturating_sub(len) & !3;
+            if page_size > available && page_size <= capacity {
+                return Err(Uf2Error::ExtensionTagsTooLarge {
+                    len,
+                    capacity,
+                    page_size,
+                });
+            }
+            capacity = available;
+        }
+        if page_size == 0 || page_size > capacity {
+            return Err(Uf2Error::InvalidPageSize {
+                page_size,
+                capacity,
+            });
+        }
+        let payload_size = page_size * (capacity / page_size);
+        Ok((page_size, payload_size))
+    }
+
+    /// The number of blocks written for `data_blocks` blocks of data, once padded to a multiple of
+    /// `block_multiple`
+    fn padded_count(&self, data_blocks: u32) -> Result<u32, Uf2Error> {
+        data_blocks
+            .checked_next_multiple_of(self.block_multiple.max(1))
+            .ok_or(Uf2Error::TooManyBlocks {
+                data_blocks,
+                multiple: self.block_multiple,
+            })
+    }
+
+    /// A block carrying the flags and family shared by every block, with no payload yet
+    fn template(&self, payload_size: u32, len: u32) -> UF2Block {
+        let mut block = UF2Block::new(payload_size, len);
+        if let Some(family) = self.family {
+            block.set_family(family);
+        }
+        if self.not_main_flash {
+            block.flags |= UF2Block::NOT_MAIN_FLASH_FLAG;
+        }
+        block
+    }
+
+    /// The payload of a full block: the largest multiple of the page size that fits in the data
+    /// area beside any checksum and extension tags
+    pub fn payload_size(&self) -> Result<u32, Uf2Error> {
+        self.layout().map(|(_, payload_size)| payload_size)
+    }
+
+    /// Adds the md5 checksum or block CRC asked for to `block`, once the rest of it is written
+    fn seal(&self, block: &mut UF2Block) {
+        if self.md5 {
+            block.set_md5();
+        }
+        if self.block_crc {
+            block.set_block_crc();
+        }
+    }
+
+    /// Whether blocks are written with a block number, block count or file size other than the
+    /// real one
+    fn overrides_counts(&self) -> bool {
+        self.num_blocks_override.is_some()
+            || self.file_size_override.is_some()
+            || self.first_block_no != 0
+    }
+
+    /// Replaces the block count and file size of `block` with their overrides, if given, and
+    /// numbers it from `first_block_no`
+    fn stamp(&self, block: &mut UF2Block) {
+        block.block_no = block.block_no.saturating_add(self.first_block_no);
+        if let Some(num_blocks) = self.num_blocks_override {
+            block.num_blocks = num_blocks;
+        }
+        if let Some(file_size) = self.file_size_override
+            && block.flags & UF2Block::FAMILY_FLAG == 0
+        {
+            block.file_size = file_size;
+        }
+    }
+
+    /// The extension tags, along with a stand-in of the size of the image checksum, if there is one
+    fn reserved_tags(&self) -> Vec<ExtensionTag> {
+        let mut tags = self.extension_tags.clone();
+        tags.extend(self.image_checksum.map(ImageChecksum::placeholder));
+        tags
+    }
+
+    /// The serialized extension tags of the last block, followed by its image checksum `tag`
+    fn encoded_tags_with(&self, tag: ExtensionTag) -> Result<Vec<u8>, Uf2Error> {
+        let mut tags = self.extension_tags.clone();
+        tags.push(tag);
+        tags::encode(&tags)
+    }
+
+    /// The serialized extension tags, if there are any
+    fn encoded_tags(&self) -> Result<Option<Vec<u8>>, Uf2Error> {
+        if self.extension_tags.is_empty() {
+            return Ok(None);
+        }
+        tags::encode(&self.extension_tags).map(Some)
+    }
+
+    /// The length of `len` bytes at `addr` once padded to a whole number of pages, which fails if
+    /// padding is needed and there is no fill byte, or if the padded data would run past the end
+    /// of the 32-bit address space
+    fn padded_len(&self, addr: u32, len: u32, page_size: u32) -> Result<u32, Uf2Error> {
+        if self.fill.is_none() && !len.is_multiple_of(page_size) {
+            return Err(Uf2Error::NotPageAligned {
+                addr,
+                len,
+                page_size,
+            });
+        }
+        len.checked_next_multiple_of(page_size)
+            .filter(|&padded| u64::from(addr) + u64::from(padded) <= 1 << 32)
+            .ok_or(Uf2Error::TooLarge {
+                addr,
+                len: len as usize,
+            })
+    }
+}
+
+/// The number of blocks [`encode_bin_to_uf2`] makes from `file_len` bytes with pages of `page_size`
+/// bytes, when the blocks carry no md5 checksums, block CRCs or extension tags
+///
+/// # Panics
+///
+/// If `page_size` is zero or does not fit in a block.
+pub fn estimate_blocks(file_len: u32, page_size: u32) -> u32 {
+    assert!(
+        page_size > 0 && page_size as usize <= MAX_PAYLOAD_SIZE,
+        "page size {page_size} does not fit in a block"
+    );
+    let payload_size = page_size * (MAX_PAYLOAD_SIZE as u32 / page_size);
+    num_blocks(file_len, payload_size)
+}
+
+/// The size in bytes of the uf2 [`encode_bin_to_uf2`] writes for `file_len` bytes with `options`,
+/// taking into account the room md5 checksums, block CRCs and extension tags leave for payload
+///
+/// A partial final page is counted as padded, as it is with a fill byte. Fails where the encoder
+/// would, if the page size or extension tags do not fit in a block.
+pub fn estimate_uf2_size(file_len: u32, options: &Uf2Options) -> Result<u64, Uf2Error> {
+    let (_, payload_size) = options.layout()?;
+    Ok(u64::from(num_blocks(file_len, payload_size)) * CHUNK_SIZE as u64)
+}
+
+/// Writes `data` as a uf2 laid out according to `options`
+pub fn encode(
+    data: &[u8],
+    output: impl Write,
+    options: &Uf2Options,
+) -> Result<EncodeSummary, Uf2Error> {
+    let len = region_len(options.base_address, data)?;
+    encode_bin_to_uf2(data, output, len, options)
+}
+
+/// Reads `input` until it ends and returns it encoded as a uf2, for callers that want the image in
+/// memory rather than in a file
+///
+/// The blocks are written with a [`Uf2Writer`], as those of an input of unknown length are, so the
+/// bytes are the same as those [`encode_bin_to_uf2`] writes for the same input.
+pub fn encode_to_vec(input: impl Read, options: &Uf2Options) -> Result<Vec<u8>, Uf2Error> {
+    let mut writer = Uf2Writer::new(std::io::Cursor::new(Vec::new()), options.clone())?;
+    writer.write_stream(options.base_address, input)?;
+    let (output, _) = writer.finish()?;
+    Ok(output.into_inner())
+}
+
+/// Encodes `len` bytes read from `input` as UF2 blocks written to `output`
+///
+/// An empty input fails with [`Uf2Error::EmptyInput`] rather than producing a file with no blocks.
+pub fn encode_bin_to_uf2(
+    input: impl Read,
+    output: impl Write,
+    len: u32,
+    options: &Uf2Options,
+) -> Result<EncodeSummary, Uf2Error> {
+    encode_bin_to_uf2_with(input, output, len, options, |_| {})
+}
+
+/// Like [`encode_bin_to_uf2`], but calls `on_block` with each block after it is written
+pub fn encode_bin_to_uf2_with(
+    input: impl Read,
+    mut output: impl Write,
+    len: u32,
+    options: &Uf2Options,
+    mut on_block: impl FnMut(&UF2Block),
+) -> Result<EncodeSummary, Uf2Error> {
+    let (page_size, payload_size) = options.layout()?;
+    if len == 0 {
+        return Err(Uf2Error::EmptyInput);
+    }
+    let padded_len = options.padded_len(options.base_address, len, page_size)?;
+    let mut block = options.template(payload_size, padded_len);
+    block.num_blocks = options.padded_count(block.num_blocks)?;
+    block.target_addr = options.base_address;
+    let mut image = ImageTag::new(options);
+    let filled = encode_stream(
+        input,
+        &mut output,
+        len,
+        &mut block,
+        options,
+        &mut image,
+        &mut on_block,
+    )?;
+    let num_blocks = block.num_blocks;
+    let padding = write_padding(
+        &mut output,
+        &mut block,
+        num_blocks,
+        options,
+        &mut image,
+        &mut on_block,
+    )?;
+    debug_assert_eq!(block.block_no, block.num_blocks);
+    Ok(EncodeSummary {
+        num_blocks: block.num_blocks,
+        padding,
+        filled,
+    })
+}
+
+/// Like [`encode_bin_to_uf2_with`], for an input whose length is not known up front: it is read
+/// until it ends, and every block claims to be one of `num_blocks`, or of that many padded to
+/// [`Uf2Options::block_multiple`].
+///
+/// The file size recorded in blocks without a family id is zero, as it is not known in time.
+/// Fails with [`Uf2Error::BlockCountMismatch`] if the input does not make exactly `num_blocks`
+/// blocks, though by then the blocks have been written.
+pub fn encode_bin_to_uf2_unsized(
+    input: impl Read,
+    mut output: impl Write,
+    num_blocks: u32,
+    options: &Uf2Options,
+    mut on_block: impl FnMut(&UF2Block),
+) -> Result<EncodeSummary, Uf2Error> {
+    let (page_size, payload_size) = options.layout()?;
+    let mut block = options.template(payload_size, 0);
+    block.num_blocks = options.padded_count(num_blocks)?;
+    block.target_addr = options.base_address;
+    let mut image = ImageTag::new(options);
+    let (len, filled) = encode_to_end(
+        input,
+        &mut output,
+        &mut block,
+        page_size,
+        options,
+        &mut image,
+        &mut on_block,
+    )?;
+    if len == 0 {
+        return Err(Uf2Error::EmptyInput);
+    }
+    if block.block_no != num_blocks {
+        return Err(Uf2Error::BlockCountMismatch {
+            expected: num_blocks,
+            found: block.block_no,
+        });
+    }
+    let num_blocks = block.num_blocks;
+    let padding = write_padding(
+        &mut output,
+        &mut block,
+        num_blocks,
+        options,
+        &mut image,
+        &mut on_block,
+    )?;
+    Ok(EncodeSummary {
+        num_blocks,
+        padding,
+        filled,
+    })
+}
+
+/// Blocks each thread of [`encode_bin_to_uf2_parallel`] encodes at a time, which bounds the memory
+/// used beyond the input itself
+const PARALLEL_BATCH: u32 = 256;
+
+/// Like [`encode_bin_to_uf2_with`], but builds the blocks of `data` on up to `jobs` threads.
+///
+/// The output is identical to what the serial encoder writes for the same input. Blocks are
+/// encoded in batches and written in order, so `on_block` still sees every block in order.
+pub fn encode_bin_to_uf2_parallel(
+    data: &[u8],
+    mut output: impl Write,
+    options: &Uf2Options,
+    jobs: NonZeroUsize,
+    mut on_block: impl FnMut(&UF2Block),
+) -> Result<EncodeSummary, Uf2Error> {
+    let (page_size, payload_size) = options.layout()?;
+    let len = region_len(options.base_address, data)?;
+    if len == 0 {
+        return Err(Uf2Error::EmptyInput);
+    }
+    let padded_len = options.padded_len(options.base_address, len, page_size)?;
+    let mut template = options.template(payload_size, padded_len);
+    let data_blocks = template.num_blocks;
+    template.num_blocks = options.padded_count(data_blocks)?;
+    // The blocks are not encoded in order, so the image checksum is taken from the input, padded
+    // to the end of its last page as the payloads are
+    let image = match options.image_checksum {
+        Some(checksum) => {
+            let mut digest = ImageDigest::new(checksum);
+            digest.update(data);
+            let padding = vec![options.fill.unwrap_or_default(); (padded_len - len) as usize];
+            digest.update(&padding);
+            ImageTag::Known(digest.finish())
+        }
+        None => ImageTag::None,
+    };
+
+    // Encodes the blocks in `range` with the serial encoder, as they would appear in its output
+    let encode_range = |range: std::ops::Range<u32>| -> Result<_, Uf2Error> {
+        let start = (range.start * payload_size) as usize;
+        let end = (range.end * payload_size).min(len) as usize;
+        let mut block = template.clone();
+        block.block_no = range.start;
+        block.target_addr = options.base_address + range.start * payload_size;
+        let mut chunks = Vec::with_capacity(range.len() * CHUNK_SIZE);
+        let mut blocks = Vec::with_capacity(range.len());
+        let filled = encode_stream(
+            &data[start..end],
+            &mut chunks,
+            (end - start) as u32,
+            &mut block,
+            options,
+            &mut image.clone(),
+            &mut |block: &UF2Block| blocks.push(block.clone()),
+        )?;
+        Ok((chunks, blocks, filled))
+    };
+
+    let batch = PARALLEL_BATCH.saturating_mul(jobs.get().try_into().unwrap_or(u32::MAX));
+    let mut filled = 0;
+    for batch_start in (0..data_blocks).step_by(batch as usize) {
+        let batch_end = batch_start.saturating_add(batch).min(data_blocks);
+        let encoded = std::thread::scope(|scope| {
+            let threads = (batch_start..batch_end)
+                .step_by(PARALLEL_BATCH as usize)
+                .map(|start| {
+                    let end = (start + PARALLEL_BATCH).min(batch_end);
+                    scope.spawn(move || encode_range(start..end))
+                })
+                .collect::<Vec<_>>();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        for result in encoded {
+            let (chunks, blocks, range_filled) = result?;
+            output.write_all(&chunks)?;
+            blocks.iter().for_each(&mut on_block);
+            filled += range_filled;
+        }
+    }
+    let mut block = template;
+    block.block_no = data_blocks;
+    // Saturating as the serial encoder does, for an image that ends at the top of the address space
+    block.target_addr = options.base_address.saturating_add(padded_len);
+    let num_blocks = block.num_blocks;
+    let padding = write_padding(
+        &mut output,
+        &mut block,
+        num_blocks,
+        options,
+        &mut image.clone(),
+        &mut on_block,
+    )?;
+    Ok(EncodeSummary {
+        num_blocks,
+        padding,
+        filled,
+    })
+}
+
+/// Encodes each region at its own address into one UF2, in address order.
+///
+/// Regions at the same address keep their order in `regions`, so the output depends only on the
+/// regions and `options`.
+///
+/// `options.base_address` is ignored, and `num_blocks` counts the blocks of every region. Fails
+/// with [`Uf2Error::EmptyInput`] if the regions hold no data at all.
+pub fn encode_regions(
+    regions: &[Region],
+    output: impl Write,
+    options: &Uf2Options,
+    on_block: impl FnMut(&UF2Block),
+) -> Result<EncodeSummary, Uf2Error> {
+    let regions = regions
+        .iter()
+        .map(|region| (options.family, region))
+        .collect::<Vec<_>>();
+    encode_family_regions(&regions, output, options, on_block)
+}
+
+/// Like [`encode_regions`], but tags the blocks of each region with that region's family instead
+/// of `options.family`
+pub fn encode_family_regions(
+    regions: &[(Option<u32>, &Region)],
+    output: impl Write,
+    options: &Uf2Options,
+    on_block: impl FnMut(&UF2Block),
+) -> Result<EncodeSummary, Uf2Error> {
+    let mut order = regions.to_vec();
+    order.sort_by_key(|(_, region)| region.addr);
+    encode_family_regions_in_order(&order, output, options, on_block)
+}
+
+/// Like [`encode_family_regions`], but writes the regions in the order given rather than in
+/// address order, such as to put metadata after the image it describes
+pub fn encode_family_regions_in_order(
+    regions: &[(Option<u32>, &Region)],
+    mut output: impl Write,
+    options: &Uf2Options,
+    mut on_block: impl FnMut(&UF2Block),
+) -> Result<EncodeSummary, Uf2Error> {
+    let (page_size, payload_size) = options.layout()?;
+    let mut total_len: u32 = 0;
+    let mut total_blocks: u32 = 0;
+    for (_, region) in regions {
+        let len = region_len(region.addr, &region.data)?;
+        let len = options.padded_len(region.addr, len, page_size)?;
+        total_len = total_len.saturating_add(len);
+        total_blocks += num_blocks(len, payload_size);
+    }
+    if total_blocks == 0 {
+        return Err(Uf2Error::EmptyInput);
+    }
+    let total_blocks = options.padded_count(total_blocks)?;
+    let mut block_no = 0;
+    let mut filled = 0;
+    let mut image = ImageTag::new(options);
+    let mut last = None;
+    for &(family, region) in regions {
+        let mut block = Uf2Options {
+            family,
+            ..options.clone()
+        }
+        .template(payload_size, total_len);
+        block.num_blocks = total_blocks;
+        block.block_no = block_no;
+        block.target_addr = region.addr;
+        filled += encode_stream(
+            region.data.as_slice(),
+            &mut output,
+            region.data.len() as u32,
+            &mut block,
+            options,
+            &mut image,
+            &mut on_block,
+        )?;
+        block_no = block.block_no;
+        last = Some(block);
+    }
+    // Padding takes the family of the last region
+    let mut padding = 0;
+    if let Some(mut block) = last {
+        padding = write_padding(
+            &mut output,
+            &mut block,
+            total_blocks,
+            options,
+            &mut image,
+            &mut on_block,
+        )?;
+        block_no = block.block_no;
+    }
+    debug_assert_eq!(block_no, total_blocks);
+    Ok(EncodeSummary {
+        num_blocks: total_blocks,
+        padding,
+        filled,
+    })
+}
+
+/// Writes regions as UF2 blocks one at a time, for assembling an image from several pieces.
+///
+/// `num_blocks` (and the total length, when there is no family id) is not known until every
+/// region has been written, so the blocks are written with a placeholder and [`finish`] seeks back
+/// to patch them. The output must therefore be seekable; it is written from its position when the
+/// writer is created.
+///
+/// [`finish`]: Uf2Writer::finish
+pub struct Uf2Writer<W> {
+    output: W,
+    options: Uf2Options,
+    page_size: u32,
+    payload_size: u32,
+    start: u64,
+    block_no: u32,
+    len: u32,
+    filled: u32,
+    image: ImageTag,
+    /// The last block written, kept when its image checksum is to be added once it is known
+    last: Option<UF2Block>,
+    /// Where the last block written ends, for padding blocks to follow
+    end_addr: u32,
+}
+
+impl<W: Write + Seek> Uf2Writer<W> {
+    /// Creates a writer using the page size, family, fill and flags of `options`; its
+    /// `base_address` is ignored in favour of each region's address
+    pub fn new(mut output: W, options: Uf2Options) -> Result<Self, Uf2Error> {
+        let (page_size, payload_size) = options.layout()?;
+        let start = output.stream_position()?;
+        let image = ImageTag::new(&options);
+        Ok(Self {
+            output,
+            options,
+            page_size,
+            payload_size,
+            start,
+            block_no: 0,
+            len: 0,
+            filled: 0,
+            image,
+            last: None,
+            end_addr: 0,
+        })
+    }
+
+    /// Writes `data` as blocks starting at `addr`, after the blocks already written
+    pub fn write_region(&mut self, addr: u32, data: &[u8]) -> Result<(), Uf2Error> {
+        self.write_region_with(addr, data, |_| {})
+    }
+
+    /// Like [`write_region`](Self::write_region), but calls `on_block` with each block after it
+    /// is written; `num_blocks` is still the placeholder at that point
+    pub fn write_region_with(
+        &mut self,
+        addr: u32,
+        data: &[u8],
+        mut on_block: impl FnMut(&UF2Block),
+    ) -> Result<(), Uf2Error> {
+        let len = region_len(addr, data)?;
+        let padded_len = self.options.padded_len(addr, len, self.page_size)?;
+        let mut block = self.options.template(self.payload_size, 0);
+        block.num_blocks = 0;
+        block.block_no = self.block_no;
+        block.target_addr = addr;
+        let keep_last = self.options.image_checksum.is_some();
+        let last = &mut self.last;
+        self.filled += encode_stream(
+            data,
+            &mut self.output,
+            len,
+            &mut block,
+            &self.options,
+            &mut self.image,
+            &mut |block: &UF2Block| {
+                if keep_last {
+                    *last = Some(block.clone());
+                }
+                on_block(block);
+            },
+        )?;
+        self.block_no = block.block_no;
+        self.end_addr = block.target_addr;
+        self.len = self.len.saturating_add(padded_len);
+        Ok(())
+    }
+
+    /// Writes everything `input` holds, until it ends, as blocks starting at `addr`
+    pub fn write_stream(&mut self, addr: u32, input: impl Read) -> Result<(), Uf2Error> {
+        self.write_stream_with(addr, input, |_| {})
+    }
+
+    /// Like [`write_stream`](Self::write_stream), but calls `on_block` with each block after it
+    /// is written
+    pub fn write_stream_with(
+        &mut self,
+        addr: u32,
+        input: impl Read,
+        mut on_block: impl FnMut(&UF2Block),
+    ) -> Result<(), Uf2Error> {
+        let mut block = self.options.template(self.payload_size, 0);
+        block.num_blocks = 0;
+        block.block_no = self.block_no;
+        block.target_addr = addr;
+        let keep_last = self.options.image_checksum.is_some();
+        let last = &mut self.last;
+        let (len, filled) = encode_to_end(
+            input,
+            &mut self.output,
+            &mut block,
+            self.page_size,
+            &self.options,
+            &mut self.image,
+            &mut |block: &UF2Block| {
+                if keep_last {
+                    *last = Some(block.clone());
+                }
+                on_block(block);
+            },
+        )?;
+        self.filled += filled;
+        self.block_no = block.block_no;
+        self.end_addr = block.target_addr;
+        self.len = self.len.saturating_add(len + filled);
+        Ok(())
+    }
+
+    /// Writes any padding blocks, then patches `num_blocks` into every block written, or the
+    /// override given in the options, along with the image checksum of the last block, and returns
+    /// the output, positioned after the last block
+    ///
+    /// Fails with [`Uf2Error::EmptyInput`] if no data was written.
+    pub fn finish(mut self) -> Result<(W, EncodeSummary), Uf2Error> {
+        if self.block_no == 0 {
+            return Err(Uf2Error::EmptyInput);
+        }
+        const NUM_BLOCKS_OFFSET: u64 = 24;
+        const FILE_SIZE_OFFSET: u64 = 28;
+        let padded = self.options.padded_count(self.block_no)?;
+        let padding = padded - self.block_no;
+        if padding > 0 {
+            let mut block = self.options.template(self.payload_size, 0);
+            block.num_blocks = 0;
+            block.block_no = self.block_no;
+            block.target_addr = self.end_addr;
+            let keep_last = self.options.image_checksum.is_some();
+            let last = &mut self.last;
+            write_padding(
+                &mut self.output,
+                &mut block,
+                padded,
+                &self.options,
+                &mut self.image,
+                &mut |block: &UF2Block| {
+                    if keep_last {
+                        *last = Some(block.clone());
+                    }
+                },
+            )?;
+            self.block_no = padded;
+        }
+        let num_blocks = self.options.num_blocks_override.unwrap_or(self.block_no);
+        let len = self.options.file_size_override.unwrap_or(self.len);
+        if let Some(tag) = self.image.tag()
+            && let Some(mut block) = self.last.take()
+        {
+            tags::write(&mut block, &self.options.encoded_tags_with(tag)?);
+            self.options.seal(&mut block);
+            let block_start = self.start + u64::from(self.block_no - 1) * CHUNK_SIZE as u64;
+            self.output.seek(SeekFrom::Start(block_start))?;
+            block.write_to(&mut self.output)?;
+        }
+        for block_no in 0..self.block_no {
+            let block_start = self.start + u64::from(block_no) * CHUNK_SIZE as u64;
+            self.output
+                .seek(SeekFrom::Start(block_start + NUM_BLOCKS_OFFSET))?;
+            self.output.write_all(&num_blocks.to_le_bytes())?;
+            if self.options.family.is_none() {
+                self.output
+                    .seek(SeekFrom::Start(block_start + FILE_SIZE_OFFSET))?;
+                self.output.write_all(&len.to_le_bytes())?;
+            }
+        }
+        let end = self.start + u64::from(self.block_no) * CHUNK_SIZE as u64;
+        self.output.seek(SeekFrom::Start(end))?;
+        self.output.flush()?;
+        Ok((
+            self.output,
+            EncodeSummary {
+                num_blocks: self.block_no,
+                padding,
+                filled: self.filled,
+            },
+        ))
+    }
+}
+
+fn region_len(addr: u32, data: &[u8]) -> Result<u32, Uf2Error> {
+    data.len().try_into().map_err(|_| Uf2Error::TooLarge {
+        addr,
+        len: data.len(),
+    })
+}
+
+/// Like [`encode_stream`], but reads `input` a block at a time until it ends, returning the number
+/// of bytes read as well as the number of fill bytes
+fn encode_to_end(
+    mut input: impl Read,
+    mut output: impl Write,
+    block: &mut UF2Block,
+    page_size: u32,
+    options: &Uf2Options,
+    image: &mut ImageTag,
+    on_block: &mut impl FnMut(&UF2Block),
+) -> Result<(u32, u32), Uf2Error> {
+    let addr = block.target_addr;
+    let payload_size = block.payload_size;
+    let mut buf = vec![0; payload_size as usize];
+    let mut len: u32 = 0;
+    let mut filled = 0;
+    loop {
+        let read = read_full(&mut input, &mut buf)?;
+        if read == 0 {
+            break;
+        }
+        let total = len.checked_add(read as u32).ok_or(Uf2Error::TooLarge {
+            addr,
+            len: len as usize + read,
+        })?;
+        // Every read before the last fills a block, which is a whole number of pages, so this
+        // checks the final page of the input as well as that it fits in the address space
+        options.padded_len(addr, total, page_size)?;
+        filled += encode_stream(
+            &buf[..read],
+            &mut output,
+            read as u32,
+            block,
+            options,
+            image,
+            on_block,
+        )?;
+        len = total;
+        if read < buf.len() {
+            break;
+        }
+    }
+    Ok((len, filled))
+}
+
+/// Where the image checksum stored in the last block comes from
+#[derive(Clone)]
+enum ImageTag {
+    None,
+    /// Computed from the payloads as they are encoded
+    Running(ImageDigest),
+    /// Computed beforehand, for blocks that are not encoded in order
+    Known(ExtensionTag),
+}
+
+impl ImageTag {
+    fn new(options: &Uf2Options) -> Self {
+        match options.image_checksum {
+            Some(checksum) => Self::Running(ImageDigest::new(checksum)),
+            None => Self::None,
+        }
+    }
+
+    fn update(&mut self, payload: &[u8]) {
+        if let Self::Running(digest) = self {
+            digest.update(payload);
+        }
+    }
+
+    /// The tag holding the checksum of the payloads encoded so far
+    fn tag(&self) -> Option<ExtensionTag> {
+        match self {
+            Self::None => None,
+            Self::Running(digest) => Some(digest.clone().finish()),
+            Self::Known(tag) => Some(tag.clone()),
+        }
+    }
+}
+
+/// Reads into `buf` until it is full or the input ends, returning the number of bytes read
+fn read_full(mut input: impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match input.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+/// Writes `len` bytes from `input` as blocks starting from the address and block number in
+/// `block`, leaving `block` positioned after the last block written.
+///
+/// `block` is reused for every block written, so each one has the rest of its data area after the
+/// payload reset to the block fill byte; a shorter final block carries nothing of the block before
+/// it. The payloads are fed to `image`, and the last block of the image, if it is among them and
+/// the image has a known number of blocks, gets its checksum. Returns the number of fill bytes
+/// added to pad the final page.
+fn encode_stream(
+    mut input: impl Read,
+    mut output: impl Write,
+    mut len: u32,
+    block: &mut UF2Block,
+    options: &Uf2Options,
+    image: &mut ImageTag,
+    on_block: &mut impl FnMut(&UF2Block),
+) -> Result<u32, Uf2Error> {
+    let payload_size = block.payload_size;
+    let page_size = options.page_size;
+    let tags = options.encoded_tags()?;
+    let mut filled = 0;
+    while len > 0 {
+        if len < payload_size {
+            block.payload_size = len;
+        }
+        len -= block.payload_size;
+        input.read_exact(&mut block.data[..block.payload_size as usize])?;
+
+        if let Some(fill) = options.fill
+            && !block.payload_size.is_multiple_of(page_size)
+        {
+            assert_eq!(len, 0);
+            let curr_size = block.payload_size;
+            let next_size = curr_size.next_multiple_of(page_size);
+            assert!(next_size <= payload_size);
+            block.data[curr_size as usize..next_size as usize].fill(fill);
+            block.payload_size = next_size;
+            filled = next_size - curr_size;
+        }
+        image.update(&block.data[..block.payload_size as usize]);
+        write_block(
+            &mut output,
+            block,
+            options,
+            tags.as_deref(),
+            image,
+            on_block,
+        )?;
+    }
+    Ok(filled)
+}
+
+/// Writes blocks with no payload after `block`, marked not to be written to flash, until the one
+/// numbered `end`, so that the block count is the multiple of [`Uf2Options::block_multiple`].
+/// Returns the number of blocks written
+fn write_padding(
+    mut output: impl Write,
+    block: &mut UF2Block,
+    end: u32,
+    options: &Uf2Options,
+    image: &mut ImageTag,
+    on_block: &mut impl FnMut(&UF2Block),
+) -> Result<u32, Uf2Error> {
+    let tags = options.encoded_tags()?;
+    let start = block.block_no;
+    block.payload_size = 0;
+    block.flags |= UF2Block::NOT_MAIN_FLASH_FLAG;
+    while block.block_no < end {
+        write_block(
+            &mut output,
+            block,
+            options,
+            tags.as_deref(),
+            image,
+            on_block,
+        )?;
+    }
+    Ok(end.saturating_sub(start))
+}
+
+/// Fills in the rest of `block` once its payload is in place, writes it, and moves `block` on to
+/// the next block
+///
+/// `tags` are the encoded extension tags; the last block of the image gets its checksum as well.
+fn write_block(
+    mut output: impl Write,
+    block: &mut UF2Block,
+    options: &Uf2Options,
+    tags: Option<&[u8]>,
+    image: &ImageTag,
+    on_block: &mut impl FnMut(&UF2Block),
+) -> Result<(), Uf2Error> {
+    // The block is reused, so this also clears what a longer payload before it left behind
+    block.data[block.payload_size as usize..].fill(options.block_fill);
+    let is_last = block.num_blocks != 0 && block.block_no + 1 == block.num_blocks;
+    if is_last && let Some(tag) = image.tag() {
+        tags::write(block, &options.encoded_tags_with(tag)?);
+    } else if let Some(tags) = tags {
+        tags::write(block, tags);
+    }
+    options.seal(block);
+
+    // The real count and size stay in `block`, as the callers check them once it is written
+    if options.overrides_counts() {
+        let mut stamped = block.clone();
+        options.stamp(&mut stamped);
+        stamped.write_to(&mut output)?;
+        on_block(&stamped);
+    } else {
+        block.write_to(&mut output)?;
+        on_block(block);
+    }
+    block.block_no += 1;
+    // Only padding follows a block that ends at the top of the address space
+    block.target_addr = block.target_addr.saturating_add(block.payload_size);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_blocks;
+
+    /// Encodes `data` with `options` and parses the blocks back
+    fn encode_blocks(data: &[u8], options: &Uf2Options) -> Vec<UF2Block> {
+        let mut output = Vec::new();
+        encode(data, &mut output, options).unwrap();
+        read_blocks(output.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn short_last_block_has_clean_tail() {
+        let data = vec![0xAB; MAX_PAYLOAD_SIZE + 10];
+        let blocks = encode_blocks(&data, &Uf2Options::default());
+        let [first, last] = blocks.as_slice() else {
+            panic!("expected 2 blocks, got {}", blocks.len());
+        };
+        assert_eq!(first.payload_size as usize, MAX_PAYLOAD_SIZE);
+        assert_eq!(last.payload(), &data[MAX_PAYLOAD_SIZE..]);
+        assert!(last.data[10..].iter().all(|&byte| byte == 0));
+    }
+
+    fn with_page_size(page_size: u32) -> Uf2Options {
+        Uf2Options {
+            page_size,
+            ..Uf2Options::default()
+        }
+    }
+
+    #[test]
+    fn layout_fits_whole_pages() {
+        assert_eq!(with_page_size(256).layout().unwrap(), (256, 256));
+        assert_eq!(with_page_size(100).layout().unwrap(), (100, 400));
+        assert_eq!(with_page_size(476).layout().unwrap(), (476, 476));
+    }
+
+    #[test]
+    fn layout_rejects_invalid_page_size() {
+        for page_size in [0, 477, 512] {
+            assert!(matches!(
+                with_page_size(page_size).layout(),
+                Err(Uf2Error::InvalidPageSize { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn partial_page_is_padded_with_fill() {
+        let data = (0..1000).map(|i| i as u8).collect::<Vec<_>>();
+        let options = Uf2Options {
+            page_size: 256,
+            fill: Some(0xFF),
+            ..Uf2Options::default()
+        };
+        let mut output = Vec::new();
+        let summary = encode(&data, &mut output, &options).unwrap();
+        assert_eq!(summary.filled, 24);
+        let blocks = read_blocks(output.as_slice()).unwrap();
+        assert_eq!(blocks.len(), 4);
+        for block in &blocks {
+            assert_eq!(block.payload_size, 256);
+            assert_eq!(block.num_blocks, 4);
+            assert_eq!(block.file_size(), Some(1024));
+        }
+        let last = blocks[3].payload();
+        assert_eq!(&last[..232], &data[768..]);
+        assert!(last[232..].iter().all(|&byte| byte == 0xFF));
+    }
+
+    #[test]
+    fn partial_page_without_fill_is_an_error() {
+        let result = encode(&[0; 1000], std::io::sink(), &with_page_size(256));
+        assert!(matches!(
+            result,
+            Err(Uf2Error::NotPageAligned {
+                addr: 0,
+                len: 1000,
+                page_size: 256
+            })
+        ));
+    }
+
+    fn is_too_large<T>(result: Result<T, Uf2Error>) -> bool {
+        matches!(
+            result,
+            Err(Uf2Error::TooLarge {
+                addr: 0xFFFF_FF00,
+                ..
+            })
+        )
+    }
+
+    #[test]
+    fn image_past_4_gib_is_an_error() {
+        let options = Uf2Options {
+            base_address: 0xFFFF_FF00,
+            ..Uf2Options::default()
+        };
+        let data = [0; 1024];
+        let jobs = NonZeroUsize::new(2).unwrap();
+        let region = Region {
+            addr: 0xFFFF_FF00,
+            data: data.to_vec(),
+        };
+        let sink = std::io::sink;
+        assert!(is_too_large(encode(&data, sink(), &options)));
+        assert!(is_too_large(encode_bin_to_uf2_parallel(
+            &data,
+            sink(),
+            &options,
+            jobs,
+            |_| {}
+        )));
+        assert!(is_too_large(encode_bin_to_uf2_unsized(
+            &data[..],
+            sink(),
+            3,
+            &options,
+            |_| {}
+        )));
+        assert!(is_too_large(encode_to_vec(&data[..], &options)));
+        assert!(is_too_large(encode_regions(
+            &[region],
+            sink(),
+            &options,
+            |_| {}
+        )));
+    }
+
+    #[test]
+    fn image_ending_at_4_gib_is_encoded() {
+        let options = Uf2Options {
+            page_size: 256,
+            base_address: 0xFFFF_FF00,
+            block_multiple: 2,
+            ..Uf2Options::default()
+        };
+        let data = [0xCD; 256];
+        let mut serial = Vec::new();
+        encode(&data, &mut serial, &options).unwrap();
+        let jobs = NonZeroUsize::new(2).unwrap();
+        let mut parallel = Vec::new();
+        encode_bin_to_uf2_parallel(&data, &mut parallel, &options, jobs, |_| {}).unwrap();
+        assert!(serial == parallel);
+        let blocks = read_blocks(serial.as_slice()).unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].target_addr, 0xFFFF_FF00);
+        assert_eq!(blocks[1].payload_size, 0);
+    }
+
+    #[test]
+    fn empty_input_is_an_error() {
+        let mut output = Vec::new();
+        let result = encode(&[], &mut output, &Uf2Options::default());
+        assert!(matches!(result, Err(Uf2Error::EmptyInput)));
+        assert!(output.is_empty());
+        let result = encode_to_vec(&[][..], &Uf2Options::default());
+        assert!(matches!(result, Err(Uf2Error::EmptyInput)));
+    }
+
+    #[test]
+    fn every_block_claims_the_number_written() {
+        for len in [255, 256, 257, 1000, 1024, 1025] {
+            let options = Uf2Options {
+                page_size: 256,
+                fill: Some(0xFF),
+                ..Uf2Options::default()
+            };
+            let blocks = encode_blocks(&vec![1; len], &options);
+            assert_eq!(blocks.len() as u32, num_blocks(len as u32, 256));
+            for (block_no, block) in blocks.iter().enumerate() {
+                assert_eq!(block.block_no, block_no as u32);
+                assert_eq!(block.num_blocks, blocks.len() as u32, "{len} bytes");
+            }
+        }
+    }
+
+    #[test]
+    fn estimates_match_encoder() {
+        let tag = ExtensionTag {
+            kind: tags::VERSION,
+            data: b"1.2.3".to_vec(),
+        };
+        for page_size in [1, 4, 64, 256, 476] {
+            for len in [1, 255, 256, 476, 477, 1000, 4096, 10_000] {
+                let data = vec![0x5A; len as usize];
+                let plain = Uf2Options {
+                    page_size,
+                    fill: Some(0xFF),
+                    ..Uf2Options::default()
+                };
+                let mut output = Vec::new();
+                encode_bin_to_uf2(data.as_slice(), &mut output, len, &plain).unwrap();
+                assert_eq!(
+                    estimate_blocks(len, page_size) as usize * CHUNK_SIZE,
+                    output.len(),
+                    "estimate_blocks, page size {page_size}, {len} bytes"
+                );
+                let variants = [
+                    plain.clone(),
+                    Uf2Options {
+                        md5: true,
+                        ..plain.clone()
+                    },
+                    Uf2Options {
+                        block_crc: true,
+                        extension_tags: vec![tag.clone()],
+                        ..plain.clone()
+                    },
+                ];
+                for options in variants {
+                    let mut output = Vec::new();
+                    let encoded = encode_bin_to_uf2(data.as_slice(), &mut output, len, &options);
+                    match estimate_uf2_size(len, &options) {
+                        Ok(size) => {
+                            encoded.unwrap();
+                            assert_eq!(size, output.len() as u64, "{options:?}, {len} bytes");
+                        }
+                        Err(_) => assert!(encoded.is_err(), "{options:?}, {len} bytes"),
+                    }
+                }
+            }
+        }
+    }
+}
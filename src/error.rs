@@ -0,0 +1,219 @@
+use std::fmt;
+
+use crate::{CHUNK_SIZE, MAX_PAYLOAD_SIZE};
+
+/// Errors produced while reading or writing UF2 data
+#[derive(Debug)]
+pub enum Uf2Error {
+    /// One of the magic numbers framing the block at `offset` did not have its expected value
+    BadMagic {
+        offset: u64,
+        magic: &'static str,
+        expected: u32,
+        found: u32,
+    },
+    /// The magic numbers of the block at `offset` would be valid with the bytes of each
+    /// `word_size`-bit word reversed, so the file was probably byte-swapped on the way
+    ByteSwapped { offset: u64, word_size: u32 },
+    /// The block at `offset` claims more payload than fits in its data area
+    PayloadTooLarge { offset: u64, payload_size: u32 },
+    /// The input ended `len` bytes into the block starting at `offset`
+    TruncatedBlock { offset: u64, len: usize },
+    /// An input of `len` bytes is not a whole number of blocks
+    NotBlockAligned { len: u64 },
+    /// The page size is zero or does not fit in the space available in a block
+    InvalidPageSize { page_size: u32, capacity: u32 },
+    /// Data to be encoded at `addr` is not a whole number of pages, and no fill byte was given
+    NotPageAligned { addr: u32, len: u32, page_size: u32 },
+    /// There is no data to encode, so no blocks would be written
+    EmptyInput,
+    /// Data to be encoded at `addr` is larger than a uf2 can describe
+    TooLarge { addr: u32, len: usize },
+    /// An input of unknown length made `found` blocks instead of the `expected` number
+    BlockCountMismatch { expected: u32, found: u32 },
+    /// Padding `data_blocks` blocks to a multiple of `multiple` makes more blocks than fit in 32
+    /// bits
+    TooManyBlocks { data_blocks: u32, multiple: u32 },
+    /// Moving block `block_no` to `addr` would put some of it outside the 32-bit address space
+    AddressOutOfRange { block_no: u32, addr: i64 },
+    /// A file name is empty, contains a null byte or is too long to store in a file container
+    InvalidFileName { name: String },
+    /// Block `block_no` of a file container is malformed
+    InvalidContainer { block_no: u32, reason: String },
+    /// No block of a file container holds bytes `start..end` of the file `name`
+    IncompleteFile { name: String, start: u32, end: u32 },
+    /// An extension tag's type does not fit in 24 bits, or its `len` bytes of data do not fit in
+    /// one tag
+    InvalidExtensionTag { kind: u32, len: usize },
+    /// The extension tags of block `block_no` are malformed
+    InvalidExtensionTags { block_no: u32, reason: String },
+    /// Extension tags of `len` bytes leave no room in the `capacity` bytes of a block for a page
+    ExtensionTagsTooLarge {
+        len: u32,
+        capacity: u32,
+        page_size: u32,
+    },
+    /// A block CRC and an md5 checksum were both asked for, but both are stored at the end of the
+    /// data area
+    CrcWithMd5,
+    /// An Intel HEX file is malformed at `line`
+    InvalidHex { line: usize, reason: String },
+    /// Reading or writing failed
+    Io(std::io::Error),
+}
+
+impl Uf2Error {
+    /// Records the offset of the block an error was found in
+    pub fn at_offset(mut self, block_offset: u64) -> Self {
+        if let Self::BadMagic { offset, .. }
+        | Self::ByteSwapped { offset, .. }
+        | Self::PayloadTooLarge { offset, .. } = &mut self
+        {
+            *offset = block_offset;
+        }
+        self
+    }
+}
+
+impl fmt::Display for Uf2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic {
+                offset,
+                magic,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Invalid UF2 block at offset {offset:#X}: incorrect {magic} \
+                (expected 0x{expected:08X}, found 0x{found:08X})"
+            ),
+            Self::ByteSwapped { offset, word_size } => write!(
+                f,
+                "Invalid UF2 block at offset {offset:#X}: the file appears byte-swapped, as its \
+                magic numbers are valid with the bytes of each {word_size}-bit word reversed"
+            ),
+            Self::PayloadTooLarge {
+                offset,
+                payload_size,
+            } => write!(
+                f,
+                "Invalid UF2 block at offset {offset:#X}: payload size {payload_size} exceeds \
+                max payload size {MAX_PAYLOAD_SIZE}"
+            ),
+            Self::TruncatedBlock { offset, len } => write!(
+                f,
+                "Input ends {len} bytes into the block at offset {offset:#X}; \
+                its length must be a multiple of {CHUNK_SIZE}"
+            ),
+            Self::NotBlockAligned { len } => {
+                let partial = len % CHUNK_SIZE as u64;
+                write!(
+                    f,
+                    "Input of len {len} is not a whole number of blocks: it ends with a partial \
+                    block of {partial} bytes at offset {:#X}. Must be a multiple of {CHUNK_SIZE}",
+                    len - partial
+                )
+            }
+            Self::InvalidPageSize { page_size: 0, .. } => write!(f, "Page size must be at least 1"),
+            Self::InvalidPageSize {
+                page_size,
+                capacity,
+            } => write!(
+                f,
+                "Page size {page_size} does not fit in the {capacity} bytes available per block"
+            ),
+            Self::NotPageAligned {
+                addr,
+                len,
+                page_size,
+            } => write!(
+                f,
+                "Cannot write binary of len: {len} at 0x{addr:08X} to device with page size: \
+                {page_size}"
+            ),
+            Self::EmptyInput => write!(f, "Input is empty; a uf2 must contain at least one block"),
+            Self::TooLarge { addr, len } => {
+                write!(f, "{len} bytes at 0x{addr:08X} is too large for a uf2")
+            }
+            Self::BlockCountMismatch { expected, found } => write!(
+                f,
+                "Input made {found} blocks, but every block was written claiming there are \
+                {expected}"
+            ),
+            Self::TooManyBlocks {
+                data_blocks,
+                multiple,
+            } => write!(
+                f,
+                "Padding {data_blocks} blocks to a multiple of {multiple} would make more blocks \
+                than a uf2 can number"
+            ),
+            Self::AddressOutOfRange { block_no, addr } => {
+                let sign = if *addr < 0 { "-" } else { "" };
+                write!(
+                    f,
+                    "Block {block_no} would not fit in the 32-bit address space at {sign}{:#X}",
+                    addr.unsigned_abs()
+                )
+            }
+            Self::InvalidFileName { name } => write!(
+                f,
+                "Cannot store a file named {name:?}: names must be 1 to {} bytes with no null \
+                bytes",
+                crate::container::MAX_NAME_LEN
+            ),
+            Self::InvalidContainer { block_no, reason } => {
+                write!(f, "Container block {block_no} {reason}")
+            }
+            Self::IncompleteFile { name, start, end } => {
+                write!(f, "{name} is missing bytes {start}..{end}")
+            }
+            Self::InvalidExtensionTag { kind, len } if *kind > 0xFF_FFFF => {
+                write!(
+                    f,
+                    "Extension tag type {kind:#X} does not fit in 24 bits ({len} bytes)"
+                )
+            }
+            Self::InvalidExtensionTag { kind, len } => write!(
+                f,
+                "Extension tag {kind:#08X} holds {len} bytes, but a tag can hold at most {}",
+                crate::tags::MAX_DATA_LEN
+            ),
+            Self::InvalidExtensionTags { block_no, reason } => {
+                write!(f, "Block {block_no} has malformed extension tags: {reason}")
+            }
+            Self::ExtensionTagsTooLarge {
+                len,
+                capacity,
+                page_size,
+            } => write!(
+                f,
+                "Extension tags of {len} bytes leave no room in the {capacity} bytes available per \
+                block for a page of {page_size} bytes"
+            ),
+            Self::CrcWithMd5 => write!(
+                f,
+                "A block CRC and an md5 checksum are both stored at the end of the data area, so \
+                only one can be used"
+            ),
+            Self::InvalidHex { line, reason } => write!(f, "Line {line}: {reason}"),
+            Self::Io(_) => write!(f, "I/O error"),
+        }
+    }
+}
+
+impl std::error::Error for Uf2Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Uf2Error {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
@@ -0,0 +1,90 @@
+//! Exit codes, so that scripts can tell an invalid image from a missing file
+
+use std::fmt;
+
+use clif::Uf2Error;
+
+/// The status the process exits with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitCode {
+    Success = 0,
+    /// A failure not covered by a more specific code
+    Failure = 1,
+    /// The command line is malformed or asks for something contradictory; clap exits with this code
+    /// for the errors it finds
+    Usage = 2,
+    /// An input is not a valid uf2, or `validate` or `verify` found a problem with it
+    Invalid = 3,
+    /// Reading or writing a file failed
+    Io = 4,
+}
+
+/// Listed at the end of `--help`
+pub const HELP: &str = "\
+Exit status:
+  0  success
+  1  failure
+  2  usage error
+  3  invalid uf2: validate found problems, verify found a mismatch, or an input could not be parsed
+  4  I/O error, such as a missing input file";
+
+impl ExitCode {
+    /// The code for a command that failed with `err`, from the first cause in its chain that has a
+    /// specific one
+    pub fn of(err: &anyhow::Error) -> Self {
+        for cause in err.chain() {
+            if cause.is::<UsageError>() {
+                return Self::Usage;
+            }
+            if cause.is::<InvalidError>() {
+                return Self::Invalid;
+            }
+            if cause.is::<std::io::Error>() {
+                return Self::Io;
+            }
+            if let Some(err) = cause.downcast_ref::<Uf2Error>() {
+                match err {
+                    Uf2Error::BadMagic { .. }
+                    | Uf2Error::ByteSwapped { .. }
+                    | Uf2Error::PayloadTooLarge { .. }
+                    | Uf2Error::TruncatedBlock { .. }
+                    | Uf2Error::NotBlockAligned { .. } => return Self::Invalid,
+                    // Its source is the io::Error, which the next cause checks
+                    Uf2Error::Io(_) => {}
+                    _ => return Self::Failure,
+                }
+            }
+        }
+        Self::Failure
+    }
+}
+
+impl From<ExitCode> for std::process::ExitCode {
+    fn from(code: ExitCode) -> Self {
+        Self::from(code as u8)
+    }
+}
+
+/// An error in how the command was invoked, such as options that cannot be used together
+#[derive(Debug)]
+pub struct UsageError(pub String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+/// A check of a uf2 failed
+#[derive(Debug)]
+pub struct InvalidError(pub String);
+
+impl fmt::Display for InvalidError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for InvalidError {}
@@ -1,16 +1,33 @@
 use crate::parse_multibase_u32;
 use std::collections::HashMap;
-use std::sync::LazyLock;
+use std::sync::{LazyLock, OnceLock};
 
 #[derive(Clone, Copy)]
 pub struct Family {
     pub id: u32,
     pub short_name: &'static str,
     pub description: &'static str,
+    /// Where the family's flash conventionally starts, which generate places binary input at when
+    /// it is given no base address
+    pub flash_base: Option<u32>,
 }
 
 macro_rules! families {
-    ( $( { "id": $id:expr, "short_name": $sn:expr, "description": $desc:expr } ),* $(,)? ) => {
+    (@flash_base) => {
+        None
+    };
+    (@flash_base $base:expr) => {
+        match parse_multibase_u32($base) {
+            Ok(val) => Some(val),
+            Err(_) => panic!("Unparseable flash_base value"),
+        }
+    };
+    ( $( {
+        "id": $id:expr,
+        "short_name": $sn:expr,
+        "description": $desc:expr
+        $(, "flash_base": $base:expr)?
+    } ),* $(,)? ) => {
         &[
             $(
                 Family {
@@ -20,6 +37,7 @@ macro_rules! families {
                     },
                     short_name: $sn,
                     description: $desc,
+                    flash_base: families!(@flash_base $($base)?),
                 },
             )*
         ]
@@ -29,8 +47,53 @@ macro_rules! families {
 pub static FAMILY_MAP: LazyLock<HashMap<u32, Family>> =
     LazyLock::new(|| FAMILIES.iter().map(|family| (family.id, *family)).collect());
 
-// https://github.com/microsoft/uf2/blob/master/utils/uf2families.json
-pub const FAMILIES: &'static [Family] = families![
+static CUSTOM: OnceLock<Vec<Family>> = OnceLock::new();
+
+/// Adds families, such as those of a private SDK, that are looked up before the built-in ones and
+/// replace any with the same id or name.
+///
+/// This can only be done once, and should be done before any lookup; if families were already
+/// registered, `families` is handed back.
+pub fn register(families: Vec<Family>) -> Result<(), Vec<Family>> {
+    CUSTOM.set(families)
+}
+
+fn custom() -> &'static [Family] {
+    CUSTOM.get().map_or(&[], Vec::as_slice)
+}
+
+/// Looks up a known family by its id
+pub fn find_by_id(id: u32) -> Option<&'static Family> {
+    custom()
+        .iter()
+        .find(|family| family.id == id)
+        .or_else(|| FAMILY_MAP.get(&id))
+}
+
+/// Looks up a known family by its short name, ignoring ASCII case
+pub fn find_by_name(name: &str) -> Option<&'static Family> {
+    let matches = |family: &&Family| family.short_name.eq_ignore_ascii_case(name);
+    custom()
+        .iter()
+        .find(matches)
+        .or_else(|| FAMILIES.iter().find(matches))
+}
+
+/// Every known family: the registered ones, then the built-in ones they do not replace
+pub fn all() -> impl Iterator<Item = &'static Family> {
+    let replaced = |family: &&Family| {
+        custom().iter().any(|other| {
+            other.id == family.id || other.short_name.eq_ignore_ascii_case(family.short_name)
+        })
+    };
+    custom()
+        .iter()
+        .chain(FAMILIES.iter().filter(move |family| !replaced(family)))
+}
+
+// https://github.com/microsoft/uf2/blob/master/utils/uf2families.json, with the flash base added
+// for the families whose flash starts at a fixed address
+pub const FAMILIES: &[Family] = families![
     {
         "id": "0x16573617",
         "short_name": "ATMEGA32",
@@ -44,7 +107,8 @@ pub const FAMILIES: &'static [Family] = families![
     {
         "id": "0x1b57745f",
         "short_name": "NRF52",
-        "description": "Nordic NRF52"
+        "description": "Nordic NRF52",
+        "flash_base": "0x00000000"
     },
     {
         "id": "0x1c5f21b0",
@@ -54,17 +118,20 @@ pub const FAMILIES: &'static [Family] = families![
     {
         "id": "0x1e1f432d",
         "short_name": "STM32L1",
-        "description": "ST STM32L1xx"
+        "description": "ST STM32L1xx",
+        "flash_base": "0x08000000"
     },
     {
         "id": "0x202e3a91",
         "short_name": "STM32L0",
-        "description": "ST STM32L0xx"
+        "description": "ST STM32L0xx",
+        "flash_base": "0x08000000"
     },
     {
         "id": "0x21460ff0",
         "short_name": "STM32WL",
-        "description": "ST STM32WLxx"
+        "description": "ST STM32WLxx",
+        "flash_base": "0x08000000"
     },
     {
         "id": "0x22e0d6fc",
@@ -79,7 +146,8 @@ pub const FAMILIES: &'static [Family] = families![
     {
         "id": "0x300f5633",
         "short_name": "STM32G0",
-        "description": "ST STM32G0xx"
+        "description": "ST STM32G0xx",
+        "flash_base": "0x08000000"
     },
     {
         "id": "0x31d228c6",
@@ -94,12 +162,14 @@ pub const FAMILIES: &'static [Family] = families![
     {
         "id": "0x04240bdf",
         "short_name": "STM32L5",
-        "description": "ST STM32L5xx"
+        "description": "ST STM32L5xx",
+        "flash_base": "0x08000000"
     },
     {
         "id": "0x4c71240a",
         "short_name": "STM32G4",
-        "description": "ST STM32G4xx"
+        "description": "ST STM32G4xx",
+        "flash_base": "0x08000000"
     },
     {
         "id": "0x4fb2d5bd",
@@ -114,7 +184,8 @@ pub const FAMILIES: &'static [Family] = families![
     {
         "id": "0x53b80f00",
         "short_name": "STM32F7",
-        "description": "ST STM32F7xx"
+        "description": "ST STM32F7xx",
+        "flash_base": "0x08000000"
     },
     {
         "id": "0x55114460",
@@ -124,7 +195,8 @@ pub const FAMILIES: &'static [Family] = families![
     {
         "id": "0x57755a57",
         "short_name": "STM32F4",
-        "description": "ST STM32F4xx"
+        "description": "ST STM32F4xx",
+        "flash_base": "0x08000000"
     },
     {
         "id": "0x5a18069b",
@@ -134,22 +206,26 @@ pub const FAMILIES: &'static [Family] = families![
     {
         "id": "0x5d1a0a2e",
         "short_name": "STM32F2",
-        "description": "ST STM32F2xx"
+        "description": "ST STM32F2xx",
+        "flash_base": "0x08000000"
     },
     {
         "id": "0x5ee21072",
         "short_name": "STM32F1",
-        "description": "ST STM32F103"
+        "description": "ST STM32F103",
+        "flash_base": "0x08000000"
     },
     {
         "id": "0x621e937a",
         "short_name": "NRF52833",
-        "description": "Nordic NRF52833"
+        "description": "Nordic NRF52833",
+        "flash_base": "0x00000000"
     },
     {
         "id": "0x647824b6",
         "short_name": "STM32F0",
-        "description": "ST STM32F0xx"
+        "description": "ST STM32F0xx",
+        "flash_base": "0x08000000"
     },
     {
         "id": "0x675a40b0",
@@ -169,27 +245,32 @@ pub const FAMILIES: &'static [Family] = families![
     {
         "id": "0x6b846188",
         "short_name": "STM32F3",
-        "description": "ST STM32F3xx"
+        "description": "ST STM32F3xx",
+        "flash_base": "0x08000000"
     },
     {
         "id": "0x6d0922fa",
         "short_name": "STM32F407",
-        "description": "ST STM32F407"
+        "description": "ST STM32F407",
+        "flash_base": "0x08000000"
     },
     {
         "id": "0x4e8f1c5d",
         "short_name": "STM32H5",
-        "description": "ST STM32H5xx"
+        "description": "ST STM32H5xx",
+        "flash_base": "0x08000000"
     },
     {
         "id": "0x6db66082",
         "short_name": "STM32H7",
-        "description": "ST STM32H7xx"
+        "description": "ST STM32H7xx",
+        "flash_base": "0x08000000"
     },
     {
         "id": "0x70d16653",
         "short_name": "STM32WB",
-        "description": "ST STM32WBxx"
+        "description": "ST STM32WBxx",
+        "flash_base": "0x08000000"
     },
     {
         "id": "0x7b3ef230",
@@ -209,7 +290,8 @@ pub const FAMILIES: &'static [Family] = families![
     {
         "id": "0x8fb060fe",
         "short_name": "STM32F407VG",
-        "description": "ST STM32F407VG"
+        "description": "ST STM32F407VG",
+        "flash_base": "0x08000000"
     },
     {
         "id": "0x9fffd543",
@@ -219,12 +301,14 @@ pub const FAMILIES: &'static [Family] = families![
     {
         "id": "0xada52840",
         "short_name": "NRF52840",
-        "description": "Nordic NRF52840"
+        "description": "Nordic NRF52840",
+        "flash_base": "0x00000000"
     },
     {
         "id": "0x820d9a5f",
         "short_name": "NRF52820",
-        "description": "Nordic NRF52820_xxAA"
+        "description": "Nordic NRF52820_xxAA",
+        "flash_base": "0x00000000"
     },
     {
         "id": "0xbfdd4eee",
@@ -294,7 +378,8 @@ pub const FAMILIES: &'static [Family] = families![
     {
         "id": "0xe48bff56",
         "short_name": "RP2040",
-        "description": "Raspberry Pi RP2040"
+        "description": "Raspberry Pi RP2040",
+        "flash_base": "0x10000000"
     },
     {
         "id": "0xe48bff57",
@@ -309,12 +394,14 @@ pub const FAMILIES: &'static [Family] = families![
     {
         "id": "0xe48bff59",
         "short_name": "RP2350_ARM_S",
-        "description": "Raspberry Pi RP2350, Secure Arm image"
+        "description": "Raspberry Pi RP2350, Secure Arm image",
+        "flash_base": "0x10000000"
     },
     {
         "id": "0xe48bff5a",
         "short_name": "RP2350_RISCV",
-        "description": "Raspberry Pi RP2350, RISC-V image"
+        "description": "Raspberry Pi RP2350, RISC-V image",
+        "flash_base": "0x10000000"
     },
     {
         "id": "0xe48bff5b",
@@ -324,7 +411,8 @@ pub const FAMILIES: &'static [Family] = families![
     {
         "id": "0x00ff6919",
         "short_name": "STM32L4",
-        "description": "ST STM32L4xx"
+        "description": "ST STM32L4xx",
+        "flash_base": "0x08000000"
     },
     {
         "id": "0x9af03e33",
@@ -359,22 +447,26 @@ pub const FAMILIES: &'static [Family] = families![
     {
         "id": "0x2dc309c5",
         "short_name": "STM32F411xE",
-        "description": "ST STM32F411xE"
+        "description": "ST STM32F411xE",
+        "flash_base": "0x08000000"
     },
     {
         "id": "0x06d1097b",
         "short_name": "STM32F411xC",
-        "description": "ST STM32F411xC"
+        "description": "ST STM32F411xC",
+        "flash_base": "0x08000000"
     },
     {
         "id": "0x72721d4e",
         "short_name": "NRF52832xxAA",
-        "description": "Nordic NRF52832xxAA"
+        "description": "Nordic NRF52832xxAA",
+        "flash_base": "0x00000000"
     },
     {
         "id": "0x6f752678",
         "short_name": "NRF52832xxAB",
-        "description": "Nordic NRF52832xxAB"
+        "description": "Nordic NRF52832xxAB",
+        "flash_base": "0x00000000"
     },
     {
         "id": "0xa0c97b8e",
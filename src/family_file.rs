@@ -0,0 +1,259 @@
+//! Custom family databases for `--family-file`, in the format of `uf2families.json`:
+//!
+//! ```json
+//! [
+//!     {
+//!         "id": "0x12345678",
+//!         "short_name": "MYBOARD",
+//!         "description": "My board"
+//!     }
+//! ]
+//! ```
+//!
+//! `name` is accepted in place of `short_name`, an id may also be a plain number, and the
+//! description may be left out. A `flash_base`, given like the id, is where generate places binary
+//! input for the family when it is given no base address.
+
+use anyhow::{Context, bail, ensure};
+use clif::families::Family;
+use clif::parse_multibase_u32;
+
+/// A parsed JSON value; numbers are kept as their text until it is known what they should be, and
+/// the value of a boolean is never needed
+enum Value {
+    Null,
+    Bool,
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn describe(&self) -> &'static str {
+        match self {
+            Self::Null => "null",
+            Self::Bool => "a boolean",
+            Self::Number(_) => "a number",
+            Self::String(_) => "a string",
+            Self::Array(_) => "an array",
+            Self::Object(_) => "an object",
+        }
+    }
+}
+
+/// Reads the families in `text`, leaking their names so that they live as long as the built-in
+/// ones
+pub fn parse(text: &str) -> anyhow::Result<Vec<Family>> {
+    let mut parser = Parser { text, pos: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    ensure!(
+        parser.pos == text.len(),
+        "Unexpected text after the JSON value at {}",
+        parser.location()
+    );
+    let Value::Array(entries) = value else {
+        bail!("Expected an array of families, found {}", value.describe());
+    };
+    entries
+        .into_iter()
+        .enumerate()
+        .map(|(i, entry)| family(entry).with_context(|| format!("Family {}", i + 1)))
+        .collect()
+}
+
+fn family(entry: Value) -> anyhow::Result<Family> {
+    let Value::Object(fields) = entry else {
+        bail!("expected an object, found {}", entry.describe());
+    };
+    let mut id = None;
+    let mut short_name = None;
+    let mut description = None;
+    let mut flash_base = None;
+    for (key, value) in fields {
+        match (key.as_str(), value) {
+            ("id", Value::String(text) | Value::Number(text)) => {
+                id =
+                    Some(parse_multibase_u32(&text).with_context(|| format!("invalid id {text}"))?);
+            }
+            ("short_name" | "name", Value::String(name)) => short_name = Some(name),
+            ("description", Value::String(text)) => description = Some(text),
+            ("flash_base", Value::String(text) | Value::Number(text)) => {
+                flash_base = Some(
+                    parse_multibase_u32(&text)
+                        .with_context(|| format!("invalid flash_base {text}"))?,
+                );
+            }
+            ("id" | "short_name" | "name" | "description" | "flash_base", value) => {
+                bail!("{key} cannot be {}", value.describe())
+            }
+            // Other tools' databases may carry more details, which are not needed here
+            _ => {}
+        }
+    }
+    let short_name = short_name.context("missing short_name")?;
+    ensure!(!short_name.is_empty(), "short_name is empty");
+    Ok(Family {
+        id: id.context("missing id")?,
+        short_name: short_name.leak(),
+        description: description.unwrap_or_default().leak(),
+        flash_base,
+    })
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    /// The line and column of the current position, for error messages
+    fn location(&self) -> String {
+        let before = &self.text[..self.pos];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+        format!("line {line}, column {column}")
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn expect(&mut self, c: char) -> anyhow::Result<()> {
+        self.skip_whitespace();
+        ensure!(
+            self.peek() == Some(c),
+            "Expected '{c}' at {}",
+            self.location()
+        );
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> anyhow::Result<Value> {
+        self.skip_whitespace();
+        let rest = &self.text[self.pos..];
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Value::String),
+            Some('-' | '0'..='9') => {
+                let len = rest
+                    .find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E'))
+                    .unwrap_or(rest.len());
+                self.pos += len;
+                Ok(Value::Number(rest[..len].to_string()))
+            }
+            _ => {
+                for (word, value) in [
+                    ("null", Value::Null),
+                    ("true", Value::Bool),
+                    ("false", Value::Bool),
+                ] {
+                    if rest.starts_with(word) {
+                        self.pos += word.len();
+                        return Ok(value);
+                    }
+                }
+                bail!("Expected a JSON value at {}", self.location())
+            }
+        }
+    }
+
+    fn object(&mut self) -> anyhow::Result<Value> {
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(':')?;
+            fields.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(fields));
+                }
+                _ => bail!("Expected ',' or '}}' at {}", self.location()),
+            }
+        }
+    }
+
+    fn array(&mut self) -> anyhow::Result<Value> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                }
+                _ => bail!("Expected ',' or ']' at {}", self.location()),
+            }
+        }
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        ensure!(
+            self.peek() == Some('"'),
+            "Expected a string at {}",
+            self.location()
+        );
+        self.pos += 1;
+        let mut string = String::new();
+        loop {
+            let c = self.peek().context("Unterminated string")?;
+            self.pos += c.len_utf8();
+            match c {
+                '"' => return Ok(string),
+                '\\' => {
+                    let escape = self.peek().context("Unterminated string")?;
+                    self.pos += escape.len_utf8();
+                    string.push(match escape {
+                        '"' => '"',
+                        '\\' => '\\',
+                        '/' => '/',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'u' => {
+                            let hex = self
+                                .text
+                                .get(self.pos..self.pos + 4)
+                                .context("Truncated \\u escape")?;
+                            self.pos += 4;
+                            u32::from_str_radix(hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .with_context(|| format!("Unsupported escape \\u{hex}"))?
+                        }
+                        c => bail!("Unsupported escape \\{c} at {}", self.location()),
+                    });
+                }
+                c => string.push(c),
+            }
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! Decompression of gzip ([RFC 1952](https://www.rfc-editor.org/rfc/rfc1952)) files, whose
+//! contents are DEFLATE ([RFC 1951](https://www.rfc-editor.org/rfc/rfc1951)) compressed
+
+use anyhow::{Context, bail, ensure};
+use clif::crc32::crc32;
+
+const MAGIC: [u8; 2] = [0x1F, 0x8B];
+const METHOD_DEFLATE: u8 = 8;
+
+const FLAG_HCRC: u8 = 0x02;
+const FLAG_EXTRA: u8 = 0x04;
+const FLAG_NAME: u8 = 0x08;
+const FLAG_COMMENT: u8 = 0x10;
+const FLAG_RESERVED: u8 = 0xE0;
+
+const MAX_BITS: usize = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which the code length code lengths of a dynamic block are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Checks for the gzip magic number
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Decompresses every member of the gzip file `data`, checking each one's CRC and length
+pub fn decompress(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    ensure!(is_gzip(data), "File is not a gzip file");
+    let mut output = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        rest = decompress_member(rest, &mut output)?;
+    }
+    Ok(output)
+}
+
+/// Decompresses the member at the start of `data` onto the end of `output`, returning the data
+/// after it
+fn decompress_member<'a>(data: &'a [u8], output: &mut Vec<u8>) -> anyhow::Result<&'a [u8]> {
+    const TRUNCATED: &str = "gzip file is truncated";
+    ensure!(
+        is_gzip(data),
+        "Unexpected data after the end of the gzip stream"
+    );
+    let header = data.get(..10).context(TRUNCATED)?;
+    ensure!(
+        header[2] == METHOD_DEFLATE,
+        "Unsupported gzip compression method {}",
+        header[2]
+    );
+    let flags = header[3];
+    ensure!(
+        flags & FLAG_RESERVED == 0,
+        "gzip header has reserved flags set ({flags:#04X})"
+    );
+    let mut pos = 10;
+    if flags & FLAG_EXTRA != 0 {
+        let len = data.get(pos..pos + 2).context(TRUNCATED)?;
+        pos += 2 + usize::from(u16::from_le_bytes([len[0], len[1]]));
+    }
+    for flag in [FLAG_NAME, FLAG_COMMENT] {
+        if flags & flag != 0 {
+            let len = data
+                .get(pos..)
+                .and_then(|rest| rest.iter().position(|&byte| byte == 0))
+                .context(TRUNCATED)?;
+            pos += len + 1;
+        }
+    }
+    if flags & FLAG_HCRC != 0 {
+        pos += 2;
+    }
+    let compressed = data.get(pos..).context(TRUNCATED)?;
+
+    let start = output.len();
+    let mut inflater = Inflater {
+        input: BitReader::new(compressed),
+        output,
+    };
+    inflater.inflate()?;
+    let end = pos + inflater.input.pos;
+    let trailer = data.get(end..end + 8).context(TRUNCATED)?;
+    let expected_crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+    let expected_len = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+    let member = &output[start..];
+    ensure!(
+        crc32(member) == expected_crc,
+        "gzip data is corrupt: CRC mismatch"
+    );
+    ensure!(
+        member.len() as u32 == expected_len,
+        "gzip data is corrupt: expected {expected_len} bytes, found {}",
+        member.len()
+    );
+    Ok(&data[end + 8..])
+}
+
+/// Reads bits least significant first, as DEFLATE stores them
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// Index of the next byte to load
+    pos: usize,
+    bits: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    fn bits(&mut self, n: u32) -> anyhow::Result<u32> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).context("gzip file is truncated")?;
+            self.bits |= u32::from(byte) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = self.bits & ((1 << n) - 1);
+        self.bits >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Discards the rest of the current byte
+    fn align(&mut self) {
+        self.bits = 0;
+        self.count = 0;
+    }
+}
+
+/// A canonical Huffman code, as the number of codes of each length and the symbols in code order
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> anyhow::Result<Self> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            counts[usize::from(len)] += 1;
+        }
+        counts[0] = 0;
+        // Codes may be incomplete (a single distance code is allowed), but not oversubscribed
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            ensure!(
+                left >= 0,
+                "gzip data is corrupt: invalid Huffman code lengths"
+            );
+        }
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[usize::from(offsets[usize::from(len)])] = symbol as u16;
+                offsets[usize::from(len)] += 1;
+            }
+        }
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode(&self, input: &mut BitReader) -> anyhow::Result<u16> {
+        let mut code: u32 = 0;
+        let mut first: u32 = 0;
+        let mut index: u32 = 0;
+        for &count in &self.counts[1..] {
+            code |= input.bits(1)?;
+            let count = u32::from(count);
+            if code < first + count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        bail!("gzip data is corrupt: invalid Huffman code")
+    }
+}
+
+struct Inflater<'a, 'b> {
+    input: BitReader<'a>,
+    output: &'b mut Vec<u8>,
+}
+
+impl Inflater<'_, '_> {
+    fn inflate(&mut self) -> anyhow::Result<()> {
+        loop {
+            let last = self.input.bits(1)? == 1;
+            match self.input.bits(2)? {
+                0 => self.stored()?,
+                1 => self.fixed()?,
+                2 => self.dynamic()?,
+                _ => bail!("gzip data is corrupt: invalid block type"),
+            }
+            if last {
+                // The trailer starts at the next byte
+                self.input.align();
+                return Ok(());
+            }
+        }
+    }
+
+    fn stored(&mut self) -> anyhow::Result<()> {
+        self.input.align();
+        let len = self.input.bits(16)?;
+        let nlen = self.input.bits(16)?;
+        ensure!(
+            len == !nlen & 0xFFFF,
+            "gzip data is corrupt: stored block length mismatch"
+        );
+        let start = self.input.pos;
+        let data = start
+            .checked_add(len as usize)
+            .and_then(|end| self.input.data.get(start..end))
+            .context("gzip file is truncated")?;
+        self.output.extend_from_slice(data);
+        self.input.pos += len as usize;
+        Ok(())
+    }
+
+    fn fixed(&mut self) -> anyhow::Result<()> {
+        let mut lengths = [0u8; 288];
+        lengths[..144].fill(8);
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        lengths[280..].fill(8);
+        let lit = Huffman::new(&lengths)?;
+        let dist = Huffman::new(&[5; 30])?;
+        self.codes(&lit, &dist)
+    }
+
+    fn dynamic(&mut self) -> anyhow::Result<()> {
+        let nlen = self.input.bits(5)? as usize + 257;
+        let ndist = self.input.bits(5)? as usize + 1;
+        let ncode = self.input.bits(4)? as usize + 4;
+        ensure!(
+            nlen <= 286 && ndist <= 30,
+            "gzip data is corrupt: too many length or distance codes"
+        );
+        let mut code_lengths = [0u8; 19];
+        for &i in &CODE_LENGTH_ORDER[..ncode] {
+            code_lengths[i] = self.input.bits(3)? as u8;
+        }
+        let code_lengths = Huffman::new(&code_lengths)?;
+
+        let mut lengths = vec![0u8; nlen + ndist];
+        let mut i = 0;
+        while i < lengths.len() {
+            let symbol = code_lengths.decode(&mut self.input)?;
+            let (len, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 => {
+                    ensure!(
+                        i > 0,
+                        "gzip data is corrupt: repeat with no previous length"
+                    );
+                    (lengths[i - 1], 3 + self.input.bits(2)?)
+                }
+                17 => (0, 3 + self.input.bits(3)?),
+                _ => (0, 11 + self.input.bits(7)?),
+            };
+            let end = i + repeat as usize;
+            ensure!(
+                end <= lengths.len(),
+                "gzip data is corrupt: too many code lengths"
+            );
+            lengths[i..end].fill(len);
+            i = end;
+        }
+        ensure!(
+            lengths[256] != 0,
+            "gzip data is corrupt: no end of block code"
+        );
+        let lit = Huffman::new(&lengths[..nlen])?;
+        let dist = Huffman::new(&lengths[nlen..])?;
+        self.codes(&lit, &dist)
+    }
+
+    /// Decodes literals and back references until the end of the block
+    fn codes(&mut self, lit: &Huffman, dist: &Huffman) -> anyhow::Result<()> {
+        loop {
+            let symbol = lit.decode(&mut self.input)?;
+            let symbol = match symbol {
+                0..=255 => {
+                    self.output.push(symbol as u8);
+                    continue;
+                }
+                256 => return Ok(()),
+                _ => usize::from(symbol - 257),
+            };
+            ensure!(
+                symbol < LENGTH_BASE.len(),
+                "gzip data is corrupt: invalid length code"
+            );
+            let len = usize::from(LENGTH_BASE[symbol])
+                + self.input.bits(u32::from(LENGTH_EXTRA[symbol]))? as usize;
+            let symbol = usize::from(dist.decode(&mut self.input)?);
+            ensure!(
+                symbol < DIST_BASE.len(),
+                "gzip data is corrupt: invalid distance code"
+            );
+            let distance = usize::from(DIST_BASE[symbol])
+                + self.input.bits(u32::from(DIST_EXTRA[symbol]))? as usize;
+            ensure!(
+                distance <= self.output.len(),
+                "gzip data is corrupt: distance is past the start of the output"
+            );
+            let start = self.output.len() - distance;
+            // The source may overlap the bytes being written, so copy one at a time
+            for i in start..start + len {
+                self.output.push(self.output[i]);
+            }
+        }
+    }
+}
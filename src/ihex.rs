@@ -0,0 +1,254 @@
+//! Reading and writing of [Intel HEX](https://en.wikipedia.org/wiki/Intel_HEX) files
+
+use std::io::{self, Read, Write};
+
+use crate::{Region, Uf2Error};
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+/// Parses Intel HEX records into contiguous regions, in the order they appear
+///
+/// Reading stops at the end-of-file record. Start address records are ignored.
+pub fn parse_hex(mut input: impl Read) -> Result<Vec<Region>, Uf2Error> {
+    let mut text = String::new();
+    input.read_to_string(&mut text)?;
+    let mut regions: Vec<Region> = Vec::new();
+    let mut base: u32 = 0;
+    for (i, line) in text.lines().enumerate() {
+        let invalid = |reason: String| Uf2Error::InvalidHex {
+            line: i + 1,
+            reason,
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let hex = line
+            .strip_prefix(':')
+            .ok_or_else(|| invalid("record does not start with ':'".to_string()))?;
+        if !hex.len().is_multiple_of(2) || hex.len() < 10 {
+            return Err(invalid("record is malformed".to_string()));
+        }
+        // Checked before slicing it into pairs of digits, which must fall on char boundaries
+        if !hex.is_ascii() {
+            return Err(invalid("record contains invalid hex digits".to_string()));
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid("record contains invalid hex digits".to_string()))?;
+        let len = bytes[0] as usize;
+        if bytes.len() != len + 5 {
+            return Err(invalid(format!(
+                "record length {len} does not match its contents"
+            )));
+        }
+        let checksum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        if checksum != 0 {
+            return Err(invalid(format!(
+                "bad checksum (expected 0x{:02X}, found 0x{:02X})",
+                bytes[len + 4].wrapping_sub(checksum),
+                bytes[len + 4]
+            )));
+        }
+        let addr = u16::from_be_bytes([bytes[1], bytes[2]]);
+        let payload = &bytes[4..4 + len];
+        match bytes[3] {
+            DATA => {
+                let addr = base + u32::from(addr);
+                if u64::from(addr) + len as u64 > 1 << 32 {
+                    return Err(invalid(format!(
+                        "{len} bytes at 0x{addr:08X} run past the end of the 32-bit address space"
+                    )));
+                }
+                match regions.last_mut() {
+                    Some(region) if region.end() == u64::from(addr) => {
+                        region.data.extend_from_slice(payload);
+                    }
+                    _ => regions.push(Region {
+                        addr,
+                        data: payload.to_vec(),
+                    }),
+                }
+            }
+            END_OF_FILE => break,
+            EXTENDED_SEGMENT_ADDRESS => {
+                if len != 2 {
+                    return Err(invalid(
+                        "segment address record must have 2 bytes".to_string(),
+                    ));
+                }
+                base = u32::from(u16::from_be_bytes([payload[0], payload[1]])) << 4;
+            }
+            EXTENDED_LINEAR_ADDRESS => {
+                if len != 2 {
+                    return Err(invalid(
+                        "linear address record must have 2 bytes".to_string(),
+                    ));
+                }
+                base = u32::from(u16::from_be_bytes([payload[0], payload[1]])) << 16;
+            }
+            START_SEGMENT_ADDRESS | START_LINEAR_ADDRESS => {}
+            record_type => {
+                return Err(invalid(format!("unknown record type 0x{record_type:02X}")));
+            }
+        }
+    }
+    Ok(regions)
+}
+
+/// Writes `regions` as Intel HEX data records of at most `record_size` bytes, followed by an
+/// end-of-file record, returning the number of data records written
+///
+/// Extended linear address records are emitted whenever the upper 16 bits of the address change,
+/// and no record crosses a 64 KiB boundary.
+pub fn write_hex(
+    regions: &[Region],
+    record_size: u8,
+    output: &mut impl Write,
+) -> io::Result<usize> {
+    let mut upper = 0;
+    let mut records = 0;
+    for region in regions {
+        let mut addr = region.addr;
+        let mut data = region.data.as_slice();
+        while !data.is_empty() {
+            if addr >> 16 != upper {
+                upper = addr >> 16;
+                write_record(
+                    output,
+                    EXTENDED_LINEAR_ADDRESS,
+                    0,
+                    &(upper as u16).to_be_bytes(),
+                )?;
+            }
+            let to_boundary = 0x1_0000 - (addr & 0xFFFF) as usize;
+            let len = data.len().min(record_size.into()).min(to_boundary);
+            let (record, rest) = data.split_at(len);
+            write_record(output, DATA, addr as u16, record)?;
+            records += 1;
+            addr = addr.wrapping_add(len as u32);
+            data = rest;
+        }
+    }
+    write_record(output, END_OF_FILE, 0, &[])?;
+    Ok(records)
+}
+
+/// Writes one Intel HEX record, computing its checksum
+fn write_record(
+    w: &mut impl Write,
+    record_type: u8,
+    addr: u16,
+    data: &[u8],
+) -> std::io::Result<()> {
+    let [addr_hi, addr_lo] = addr.to_be_bytes();
+    let mut sum = (data.len() as u8)
+        .wrapping_add(addr_hi)
+        .wrapping_add(addr_lo)
+        .wrapping_add(record_type);
+    write!(w, ":{:02X}{addr:04X}{record_type:02X}", data.len())?;
+    for byte in data {
+        sum = sum.wrapping_add(*byte);
+        write!(w, "{byte:02X}")?;
+    }
+    writeln!(w, "{:02X}", sum.wrapping_neg())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Data running across the 64 KiB boundary at 0x10000, then a separate region at 0x20010
+    const MULTI_SEGMENT: &str = "\
+:08FFF8000001020304050607E5
+:020000040001F9
+:0800000008090A0B0C0D0E0F9C
+:020000040002F8
+:04001000AABBCCDDDE
+:00000001FF
+";
+
+    fn multi_segment_regions() -> Vec<Region> {
+        vec![
+            Region {
+                addr: 0xFFF8,
+                data: (0..16).collect(),
+            },
+            Region {
+                addr: 0x2_0010,
+                data: vec![0xAA, 0xBB, 0xCC, 0xDD],
+            },
+        ]
+    }
+
+    #[test]
+    fn parse_across_64k() {
+        let regions = parse_hex(MULTI_SEGMENT.as_bytes()).unwrap();
+        assert_eq!(regions, multi_segment_regions());
+    }
+
+    #[test]
+    fn write_across_64k() {
+        let mut output = Vec::new();
+        let records = write_hex(&multi_segment_regions(), 16, &mut output).unwrap();
+        assert_eq!(records, 3);
+        assert_eq!(String::from_utf8(output).unwrap(), MULTI_SEGMENT);
+    }
+
+    #[test]
+    fn rejects_non_ascii_record() {
+        let result = parse_hex(":0\u{e9}00000000000\n".as_bytes());
+        assert!(matches!(result, Err(Uf2Error::InvalidHex { line: 1, .. })));
+    }
+
+    #[test]
+    fn rejects_data_past_4_gib() {
+        let hex = ":02000004FFFFFC\n:10FFF800000102030405060708090A0B0C0D0E0F81\n:00000001FF\n";
+        assert!(matches!(
+            parse_hex(hex.as_bytes()),
+            Err(Uf2Error::InvalidHex { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn accepts_data_ending_at_4_gib() {
+        let hex = "\
+:02000004FFFFFC
+:10FFF000000102030405060708090A0B0C0D0E0F89
+:0400000000010203F6
+:00000001FF
+";
+        let regions = parse_hex(hex.as_bytes()).unwrap();
+        assert_eq!(
+            regions,
+            [
+                Region {
+                    addr: 0xFFFF_FFF0,
+                    data: (0..16).collect(),
+                },
+                Region {
+                    addr: 0xFFFF_0000,
+                    data: (0..4).collect(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trip_across_64k() {
+        let regions = vec![Region {
+            addr: 0x1_FF00,
+            data: (0..=255).cycle().take(1000).collect(),
+        }];
+        let mut output = Vec::new();
+        write_hex(&regions, 32, &mut output).unwrap();
+        assert_eq!(parse_hex(output.as_slice()).unwrap(), regions);
+    }
+}
@@ -0,0 +1,453 @@
+//! Reading and writing of [UF2](https://github.com/microsoft/uf2) files
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::num::ParseIntError;
+use std::ops::Range;
+
+mod block;
+pub mod container;
+pub mod crc32;
+mod encode;
+mod error;
+pub mod families;
+pub mod ihex;
+pub mod md5;
+mod reader;
+pub mod sha256;
+pub mod tags;
+
+pub use block::{Md5Checksum, UF2Block};
+pub use encode::{
+    EncodeSummary, Uf2Options, Uf2Writer, encode, encode_bin_to_uf2, encode_bin_to_uf2_parallel,
+    encode_bin_to_uf2_unsized, encode_bin_to_uf2_with, encode_family_regions,
+    encode_family_regions_in_order, encode_regions, encode_to_vec, estimate_blocks,
+    estimate_uf2_size,
+};
+pub use error::Uf2Error;
+pub use reader::Uf2Reader;
+
+pub const CHUNK_SIZE: usize = 512;
+pub const MAX_PAYLOAD_SIZE: usize = 476;
+
+pub const fn split_radix(s: &str) -> (&str, u32) {
+    let Some((prefix, val)) = s.split_at_checked(2) else {
+        return (s, 10);
+    };
+    if prefix.eq_ignore_ascii_case("0x") {
+        (val, 16)
+    } else if prefix.eq_ignore_ascii_case("0o") {
+        (val, 8)
+    } else if prefix.eq_ignore_ascii_case("0b") {
+        (val, 2)
+    } else {
+        (s, 10)
+    }
+}
+
+pub const fn parse_multibase_u32(s: &str) -> Result<u32, ParseIntError> {
+    let (val, radix) = split_radix(s);
+    u32::from_str_radix(val, radix)
+}
+
+pub const fn parse_multibase_u8(s: &str) -> Result<u8, ParseIntError> {
+    let (val, radix) = split_radix(s);
+    u8::from_str_radix(val, radix)
+}
+
+/// Number of blocks needed to carry `len` bytes at `payload_size` bytes per block.
+///
+/// An empty input needs no blocks.
+pub const fn num_blocks(len: u32, payload_size: u32) -> u32 {
+    if len == 0 {
+        0
+    } else {
+        len.div_ceil(payload_size)
+    }
+}
+
+/// Reads the next block-sized chunk into `buf`.
+///
+/// Returns `Ok(false)` at a clean end of input and an error if the input ends partway through a
+/// chunk.
+pub fn read_chunk(input: &mut impl Read, buf: &mut [u8; CHUNK_SIZE]) -> std::io::Result<bool> {
+    let mut filled = 0;
+    while filled < CHUNK_SIZE {
+        match input.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("input ends {filled} bytes into a {CHUNK_SIZE} byte block"),
+                ));
+            }
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+/// Reads and parses every block from `input`
+pub fn read_blocks(input: impl Read) -> Result<Vec<UF2Block>, Uf2Error> {
+    Uf2Reader::new(input).collect()
+}
+
+/// A contiguous run of bytes starting at `addr`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub addr: u32,
+    pub data: Vec<u8>,
+}
+
+impl Region {
+    /// The addresses the region covers, cut off at the end of the 32-bit address space for a
+    /// region that reaches it
+    pub fn address_range(&self) -> Range<u32> {
+        self.addr..self.addr.saturating_add(self.data.len() as u32)
+    }
+
+    /// The address just past the end of the region, which can be 2^32
+    pub fn end(&self) -> u64 {
+        u64::from(self.addr) + self.data.len() as u64
+    }
+}
+
+/// Joins the payloads of `blocks` into contiguous regions, in address order.
+///
+/// Where blocks overlap, the block that comes later in `blocks` wins. A payload running past the
+/// end of the 32-bit address space is cut off there.
+pub fn coalesce(blocks: &[UF2Block]) -> Vec<Region> {
+    // Span ends are kept as u64 so that a block ending at 2^32 keeps its last byte
+    let span_end = |block: &UF2Block| {
+        (u64::from(block.target_addr) + u64::from(block.payload_size)).min(1 << 32)
+    };
+    let mut spans = blocks
+        .iter()
+        .map(|block| (block.target_addr, span_end(block)))
+        .collect::<Vec<_>>();
+    spans.sort_by_key(|&(start, _)| start);
+    let mut regions: Vec<Region> = Vec::new();
+    for (start, end) in spans {
+        match regions.last_mut() {
+            Some(region) if u64::from(start) <= region.end() => {
+                let len = (end - u64::from(region.addr)) as usize;
+                if len > region.data.len() {
+                    region.data.resize(len, 0);
+                }
+            }
+            _ => regions.push(Region {
+                addr: start,
+                data: vec![0; (end - u64::from(start)) as usize],
+            }),
+        }
+    }
+    for block in blocks {
+        let i = regions.partition_point(|region| region.addr <= block.target_addr) - 1;
+        let region = &mut regions[i];
+        let start = (block.target_addr - region.addr) as usize;
+        let len = (span_end(block) - u64::from(block.target_addr)) as usize;
+        region.data[start..start + len].copy_from_slice(&block.payload()[..len]);
+    }
+    regions
+}
+
+/// The regions of each family, with the family's id or `None` for blocks without one
+pub type FamilyRegions = Vec<(Option<u32>, Vec<Region>)>;
+
+/// Joins the payloads of the blocks of each family into contiguous regions, as [`coalesce`] does
+/// for the blocks of one family.
+///
+/// Families are listed in order of their first block, each with its regions in address order.
+/// Blocks with no payload cover no memory, so they make no region.
+pub fn family_regions(blocks: &[UF2Block]) -> FamilyRegions {
+    let mut families = Vec::new();
+    for block in blocks {
+        if !families.contains(&block.family()) {
+            families.push(block.family());
+        }
+    }
+    families
+        .into_iter()
+        .map(|family| {
+            let group = blocks
+                .iter()
+                .filter(|block| block.family() == family)
+                .cloned()
+                .collect::<Vec<_>>();
+            let mut regions = coalesce(&group);
+            regions.retain(|region| !region.data.is_empty());
+            (family, regions)
+        })
+        .collect()
+}
+
+/// Reads every block from `input` and joins them into the regions of each family, as
+/// [`family_regions`] does
+pub fn regions(input: impl Read) -> Result<FamilyRegions, Uf2Error> {
+    read_blocks(input).map(|blocks| family_regions(&blocks))
+}
+
+/// Two blocks of the same family whose payloads target overlapping addresses
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Overlap {
+    /// Index of the earlier of the two blocks
+    pub first: usize,
+    /// Index of the later of the two blocks
+    pub second: usize,
+    /// The addresses both blocks write to
+    pub range: Range<u32>,
+}
+
+/// Finds every pair of blocks in `blocks` that share a family and write to overlapping addresses
+pub fn find_overlaps(blocks: &[UF2Block]) -> Vec<Overlap> {
+    let mut order = (0..blocks.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| (blocks[i].family(), blocks[i].target_addr));
+    let mut overlaps = Vec::new();
+    for (n, &i) in order.iter().enumerate() {
+        let a = &blocks[i];
+        for &j in &order[n + 1..] {
+            let b = &blocks[j];
+            if b.family() != a.family() || b.target_addr >= a.address_range().end {
+                break;
+            }
+            let range = b.target_addr..u32::min(a.address_range().end, b.address_range().end);
+            if !range.is_empty() {
+                overlaps.push(Overlap {
+                    first: i.min(j),
+                    second: i.max(j),
+                    range,
+                });
+            }
+        }
+    }
+    overlaps.sort_by_key(|overlap| (overlap.first, overlap.second));
+    overlaps
+}
+
+/// Resolves overlaps between blocks of the same family in favour of the later block, so that every
+/// address is written by one block
+///
+/// An earlier block is cut down to the parts of its payload that no later block writes, which may
+/// split it in two, and dropped if nothing is left. The blocks that remain keep their order, with
+/// the parts of a cut block where the block was.
+pub fn overlay(blocks: &[UF2Block]) -> Vec<UF2Block> {
+    let mut kept: Vec<UF2Block> = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        let range = block.address_range();
+        let mut next = Vec::with_capacity(kept.len() + 1);
+        for earlier in kept {
+            let earlier_range = earlier.address_range();
+            if range.is_empty()
+                || earlier.family() != block.family()
+                || earlier_range.end <= range.start
+                || range.end <= earlier_range.start
+            {
+                next.push(earlier);
+                continue;
+            }
+            if earlier_range.start < range.start {
+                next.push(earlier.cut(earlier_range.start..range.start));
+            }
+            if range.end < earlier_range.end {
+                next.push(earlier.cut(range.end..earlier_range.end));
+            }
+        }
+        next.push(block.clone());
+        kept = next;
+    }
+    kept
+}
+
+/// The parts of `blocks` at addresses within any of `ranges`, in order
+///
+/// A block that straddles the edge of a range is cut down to the part inside it, which may split
+/// it in two where it spans a gap between ranges; blocks wholly inside a range are kept as they
+/// are. Overlapping ranges count once.
+pub fn clip(blocks: &[UF2Block], ranges: &[Range<u32>]) -> Vec<UF2Block> {
+    let mut sorted = ranges
+        .iter()
+        .filter(|range| !range.is_empty())
+        .cloned()
+        .collect::<Vec<_>>();
+    sorted.sort_by_key(|range| range.start);
+    let mut union: Vec<Range<u32>> = Vec::new();
+    for range in sorted {
+        match union.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => union.push(range),
+        }
+    }
+    let mut clipped = Vec::new();
+    for block in blocks {
+        let block_range = block.address_range();
+        for range in &union {
+            let kept = block_range.start.max(range.start)..block_range.end.min(range.end);
+            if kept.is_empty() {
+                continue;
+            }
+            if kept == block_range {
+                clipped.push(block.clone());
+            } else {
+                clipped.push(block.cut(kept));
+            }
+        }
+    }
+    clipped
+}
+
+/// A block whose md5 checksum does not match the data it describes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Md5Mismatch {
+    /// Index of the block carrying the checksum
+    pub block: usize,
+    pub checksum: Md5Checksum,
+    /// Digest of the described region, or `None` if the blocks do not cover all of it
+    pub computed: Option<[u8; 16]>,
+}
+
+/// Checks the md5 checksum of every block that has one against the region it describes, as
+/// assembled from the payloads of the blocks of the same family
+pub fn check_md5(blocks: &[UF2Block]) -> Vec<Md5Mismatch> {
+    let mut regions: HashMap<Option<u32>, Vec<Region>> = HashMap::new();
+    let mut mismatches = Vec::new();
+    for (i, block) in blocks.iter().enumerate() {
+        let Some(checksum) = block.md5_checksum() else {
+            continue;
+        };
+        let computed = if checksum.addr == block.target_addr && checksum.len == block.payload_size {
+            Some(md5::md5(block.payload()))
+        } else {
+            let family = block.family();
+            let regions = regions.entry(family).or_insert_with(|| {
+                let group = blocks
+                    .iter()
+                    .filter(|other| other.family() == family)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                coalesce(&group)
+            });
+            checksum
+                .addr
+                .checked_add(checksum.len)
+                .and_then(|end| {
+                    regions.iter().find(|region| {
+                        let range = region.address_range();
+                        range.start <= checksum.addr && end <= range.end
+                    })
+                })
+                .map(|region| {
+                    let start = (checksum.addr - region.addr) as usize;
+                    md5::md5(&region.data[start..start + checksum.len as usize])
+                })
+        };
+        if computed != Some(checksum.digest) {
+            mismatches.push(Md5Mismatch {
+                block: i,
+                checksum,
+                computed,
+            });
+        }
+    }
+    mismatches
+}
+
+/// Shifts every block so that the lowest target address becomes `base`, keeping the blocks'
+/// relative layout
+pub fn rebase(blocks: &mut [UF2Block], base: u32) -> Result<(), Uf2Error> {
+    let Some(lowest) = blocks.iter().map(|block| block.target_addr).min() else {
+        return Ok(());
+    };
+    relocate(blocks, i64::from(base) - i64::from(lowest))
+}
+
+/// Shifts the target address of every block by `delta`
+///
+/// Nothing is moved if any block would end up outside the 32-bit address space.
+pub fn relocate(blocks: &mut [UF2Block], delta: i64) -> Result<(), Uf2Error> {
+    for block in blocks.iter() {
+        let addr = i64::from(block.target_addr) + delta;
+        let fits =
+            u32::try_from(addr).is_ok_and(|addr| addr.checked_add(block.payload_size).is_some());
+        if !fits {
+            return Err(Uf2Error::AddressOutOfRange {
+                block_no: block.block_no,
+                addr,
+            });
+        }
+    }
+    for block in blocks {
+        block.target_addr = (i64::from(block.target_addr) + delta) as u32;
+    }
+    Ok(())
+}
+
+/// Rewrites `block_no` and `num_blocks` so that `blocks` form one sequence in their current order
+pub fn renumber(blocks: &mut [UF2Block]) {
+    let num_blocks = blocks.len() as u32;
+    for (block_no, block) in blocks.iter_mut().enumerate() {
+        block.block_no = block_no as u32;
+        block.num_blocks = num_blocks;
+    }
+}
+
+/// Serializes every block in `blocks` to `output`
+pub fn write_blocks<'a>(
+    blocks: impl IntoIterator<Item = &'a UF2Block>,
+    mut output: impl Write,
+) -> std::io::Result<()> {
+    for block in blocks {
+        block.write_to(&mut output)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn num_blocks_exact_multiple() {
+        assert_eq!(num_blocks(256, 256), 1);
+        assert_eq!(num_blocks(1024, 256), 4);
+        assert_eq!(num_blocks(476 * 3, 476), 3);
+    }
+
+    #[test]
+    fn num_blocks_off_by_one() {
+        assert_eq!(num_blocks(255, 256), 1);
+        assert_eq!(num_blocks(257, 256), 2);
+        assert_eq!(num_blocks(1000, 256), 4);
+        assert_eq!(num_blocks(1023, 256), 4);
+        assert_eq!(num_blocks(1025, 256), 5);
+    }
+
+    #[test]
+    fn num_blocks_empty() {
+        assert_eq!(num_blocks(0, 256), 0);
+    }
+
+    #[test]
+    fn coalesce_keeps_last_byte_at_4_gib() {
+        let blocks = [0xFFFF_FFE0, 0xFFFF_FFF0].map(|addr| {
+            let mut block = UF2Block::new(16, 2);
+            block.target_addr = addr;
+            block.data[..16].fill(addr as u8);
+            block
+        });
+        let regions = coalesce(&blocks);
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].addr, 0xFFFF_FFE0);
+        assert_eq!(regions[0].data.len(), 32);
+        assert_eq!(regions[0].data[31], 0xF0);
+    }
+
+    #[test]
+    fn coalesce_cuts_payload_past_4_gib() {
+        let mut block = UF2Block::new(16, 1);
+        block.target_addr = 0xFFFF_FFF8;
+        let regions = coalesce(&[block]);
+        assert_eq!(regions[0].data.len(), 8);
+    }
+}
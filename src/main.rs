@@ -1,28 +1,105 @@
-use arrayvec::ArrayVec;
-use clap::{Args, Parser};
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand, ValueEnum};
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Read, Write};
-use std::num::ParseIntError;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::num::NonZeroUsize;
 use std::ops::Range;
 
-use anyhow::{Context as _, anyhow, ensure};
+use anyhow::{Context as _, anyhow, bail, ensure};
 
-use crate::families::{FAMILIES, FAMILY_MAP, Family};
+use clif::container::{self, ContainedFile};
+use clif::crc32::crc32;
+use clif::families::{self, Family};
+use clif::ihex;
+use clif::sha256::{Sha256, sha256};
+use clif::tags::{self, ExtensionTag, ImageChecksum, ImageDigest};
+use clif::{
+    CHUNK_SIZE, EncodeSummary, MAX_PAYLOAD_SIZE, Md5Checksum, Md5Mismatch, Region, UF2Block,
+    Uf2Error, Uf2Options, Uf2Reader, Uf2Writer, check_md5, clip, coalesce,
+    encode_bin_to_uf2_parallel, encode_bin_to_uf2_unsized, encode_bin_to_uf2_with,
+    encode_family_regions, encode_family_regions_in_order, family_regions, find_overlaps,
+    num_blocks, overlay, parse_multibase_u8, parse_multibase_u32, read_blocks, read_chunk, rebase,
+    renumber, write_blocks,
+};
 
-mod families;
+use crate::exit::{ExitCode, InvalidError, UsageError};
+use crate::json::Json;
+use crate::log::Level;
+use crate::mmap::Mmap;
+use crate::progress::Progress;
+use crate::symbols::Symbols;
 
-const CHUNK_SIZE: usize = 512;
-const MAX_PAYLOAD_SIZE: usize = 476;
+mod completions;
+mod elf;
+mod exit;
+mod family_file;
+mod gzip;
+mod json;
+mod manifest;
+#[macro_use]
+mod log;
+mod mmap;
+mod progress;
+mod symbols;
 
 /// Simple tool for working with uf2 files
 #[derive(Parser)]
+#[command(after_help = exit::HELP)]
+struct Cli {
+    #[command(subcommand)]
+    command: ClifArgs,
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    /// Print more detail to stderr; repeat for per-block detail
+    verbose: u8,
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "verbose")]
+    /// Only print errors to stderr
+    quiet: u8,
+    #[arg(long, global = true)]
+    /// For generate, combine, merge, repack, pack and concat-bin: print what would be written
+    /// instead of writing it
+    dry_run: bool,
+    #[arg(long, global = true)]
+    /// For generate, combine and merge: report progress on stderr as lines of JSON of the form
+    /// {"written":N,"total":M}, even with --quiet or when stderr is not a terminal
+    progress_json: bool,
+    #[arg(long, global = true, value_name = "PATH")]
+    /// JSON file of extra families, in the format of uf2families.json; they replace built-in
+    /// families with the same id or name
+    family_file: Option<std::path::PathBuf>,
+}
+
+#[derive(Subcommand)]
 enum ClifArgs {
     Combine(CombineArgs),
+    Merge(MergeArgs),
     Generate(GenerateArgs),
-    Read(ReadArgs),
+    /// Generate a uf2 from an Intel HEX file; the same as generate --input-format hex
+    FromHex(GenerateArgs),
+    #[command(visible_alias = "inspect", alias = "read")]
+    Info(InfoArgs),
+    DumpBlock(DumpBlockArgs),
+    Count(CountArgs),
+    IsUf2(IsUf2Args),
+    Checksum(ChecksumArgs),
     Extract(ExtractArgs),
+    ToHex(ToHexArgs),
+    Verify(VerifyArgs),
+    Validate(ValidateArgs),
+    Diff(DiffArgs),
+    Relocate(RelocateArgs),
+    Repack(RepackArgs),
+    Split(SplitArgs),
+    SplitByFamily(SplitByFamilyArgs),
+    Join(JoinArgs),
+    Pack(PackArgs),
+    Unpack(UnpackArgs),
+    #[command(visible_alias = "cat")]
+    ConcatBin(ConcatBinArgs),
     /// List known family names
+    #[command(visible_alias = "families")]
     ListFamilies,
+    /// Print a shell completion script to stdout
+    #[command(hide = true)]
+    Completions(CompletionsArgs),
 }
 
 /// Combine multiple uf2 files into one
@@ -30,32 +107,401 @@ enum ClifArgs {
 struct CombineArgs {
     #[arg(short, long)]
     output: String,
+    /// Input uf2 files, optionally as PATH@ADDRESS to move the file's lowest block to ADDRESS.
+    /// Binary files, told apart by having no uf2 magic, need PATH@ADDRESS[:FAMILY] and are split
+    /// into 256-byte blocks at ADDRESS
     inputs: Vec<String>,
+    #[arg(short, long)]
+    /// Add the inputs' blocks after those already in the output file, renumbering them all into
+    /// one image; the output is extended rather than overwritten, so --force is not needed
+    append: bool,
+    #[arg(long)]
+    /// Warn instead of failing when blocks of the same family overlap
+    allow_overlap: bool,
+    #[arg(long)]
+    /// Fail instead of warning when the inputs are for different families
+    strict_family: bool,
+    #[arg(long)]
+    /// Fail instead of warning when the inputs are for different families or carry different
+    /// payload sizes
+    strict: bool,
+    #[arg(long, value_enum, default_value_t = OnError::Abort)]
+    /// What to do with an input that is not a valid uf2 or cannot be placed; inputs that cannot be
+    /// read at all always abort
+    on_error: OnError,
+    #[arg(long)]
+    /// Also write the SHA-256 of the output to OUTPUT.sha256, in sha256sum format
+    sha256: bool,
+    #[arg(short, long, default_value_t = 1)]
+    /// Read the inputs on this many threads; 0 uses every CPU
+    jobs: usize,
+    #[arg(long, default_value_t = DEFAULT_BUFFER_SIZE, value_parser=parse_buffer_size)]
+    /// Size in bytes of the read and write buffers: a multiple of 512, at most 64 MiB
+    buffer_size: usize,
+    #[arg(long)]
+    /// Overwrite the output file if it already exists
+    force: bool,
 }
 
-/// Generate a uf2 from an arbitrary binary file
+/// Merge the blocks of multiple uf2 files into one well-formed uf2
 #[derive(Args)]
-struct GenerateArgs {
+struct MergeArgs {
     #[arg(short, long)]
-    input: String,
+    output: String,
+    /// Input uf2 files, optionally as PATH@ADDRESS to move the file's lowest block to ADDRESS.
+    /// Binary files, told apart by having no uf2 magic, need PATH@ADDRESS[:FAMILY] and are split
+    /// into 256-byte blocks at ADDRESS
+    inputs: Vec<String>,
+    #[arg(long, value_enum, default_value_t = OverlapPolicy::Error)]
+    /// What to do when blocks of the same family overlap
+    on_overlap: OverlapPolicy,
+    #[arg(long, conflicts_with = "on_overlap")]
+    /// Warn instead of failing when blocks of the same family overlap; the same as
+    /// --on-overlap warn
+    allow_overlap: bool,
+    #[arg(long)]
+    /// Fail instead of warning when the inputs are for different families
+    strict_family: bool,
+    #[arg(long, value_enum, default_value_t = OnError::Abort)]
+    /// What to do with an input that is not a valid uf2 or cannot be placed; inputs that cannot be
+    /// read at all always abort
+    on_error: OnError,
+    #[arg(long, value_parser=parse_alignment)]
+    /// Require every region to start on an N-byte boundary, and pad its end up to the next one
+    align: Option<u32>,
+    #[arg(long, default_value_t = 0xFF, value_parser=parse_multibase_u8)]
+    /// Byte used to pad regions to the --align boundary
+    fill: u8,
+    #[arg(long, value_parser=parse_multibase_u32)]
+    /// End of the target's flash: fail if any block would write at or past this address
+    max_address: Option<u32>,
+    #[arg(short, long, default_value_t = 1)]
+    /// Read the inputs on this many threads; 0 uses every CPU
+    jobs: usize,
+    #[arg(long)]
+    /// Overwrite the output file if it already exists
+    force: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OnError {
+    /// Fail
+    Abort,
+    /// Report the input and leave it out, then fail once the output is written
+    Skip,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OverlapPolicy {
+    /// Fail
+    Error,
+    /// Warn, and keep every block
+    Warn,
+    /// Let later inputs replace the overlapping part of earlier ones, cutting down the earlier
+    /// blocks so that one block writes each address
+    Replace,
+}
+
+/// Generate a uf2 from an arbitrary binary file, which may be gzipped
+#[derive(Args, Clone)]
+struct GenerateArgs {
+    #[arg(short, long, required_unless_present = "region")]
+    input: Option<String>,
     #[arg(short, long)]
     output: String,
-    #[arg(short, long, default_value_t = 1, value_parser=parse_multibase_u32)]
+    #[arg(short, long, default_value_t = Uf2Options::default().page_size,
+        value_parser=parse_multibase_u32)]
+    /// Flash page size; each block carries the largest multiple of it that fits in 476 bytes
     page_size: u32,
+    #[arg(long)]
+    /// Use the divisor of --page-size that is a multiple of 4 and fills the most of each block,
+    /// for targets that can program part of a page at a time
+    optimize_page_size: bool,
     #[arg(short, long, value_parser=parse_family)]
+    /// Family to tag every block with: a name from list-families, or an id in hex, with or without
+    /// 0x, or in decimal if it has only decimal digits
     family: Option<u32>,
-    #[arg(short, long, default_value_t = 0, value_parser=parse_multibase_u32)]
-    target_addr_start: u32,
-    #[arg(short, long, value_parser=parse_multibase_u8)]
+    #[arg(long, requires = "family")]
+    /// Fail instead of warning when the family id is not a known family
+    strict_family: bool,
+    #[arg(short, long, alias = "target-addr-start", value_parser=parse_multibase_u32)]
+    /// Target address of the first block; subsequent blocks follow contiguously.
+    /// For ELF input, moves the lowest segment here [default: where the --family's flash starts,
+    /// such as 0x10000000 for rp2040, if known, else 0; or the ELF's physical addresses]
+    base_address: Option<u32>,
+    #[arg(long, conflicts_with = "base_address")]
+    /// Move the lowest ELF segment to the ELF's entry point address
+    entry_base: bool,
+    #[arg(long, value_enum)]
+    /// Format of the input file [default: detected from its extension]
+    input_format: Option<InputFormat>,
+    #[arg(long)]
+    /// Zero-fill the part of ELF segments that is not stored in the file, such as .bss
+    zero_bss: bool,
+    #[arg(long)]
+    /// Pad a partial final page with the fill byte instead of failing, updating the file size to
+    /// match
+    pad: bool,
+    #[arg(long, value_parser=parse_multibase_u8)]
+    /// Byte to pad with, matching the erased state of the target's flash [default: 0xFF]; giving
+    /// it implies --pad. --align pads with it too
     fill: Option<u8>,
+    #[arg(long, value_name = "BYTE", default_value_t = Uf2Options::default().block_fill,
+        value_parser=parse_multibase_u8)]
+    /// Byte to set the unused end of each block's data area to, after its payload and any
+    /// extension tags
+    block_fill: u8,
+    #[arg(long)]
+    /// Append an md5 checksum of each block's payload to the block
+    md5: bool,
+    #[arg(long, conflicts_with = "md5")]
+    /// Store a CRC-32 of each block's payload, little-endian, in the last 4 bytes of its data area,
+    /// leaving 4 fewer bytes for the payload. Not part of the UF2 format, but some receivers check
+    /// it; verify --block-crc does too
+    block_crc: bool,
+    #[arg(long)]
+    /// Mark blocks as "not main flash" so they are transferred but not written to flash
+    not_main_flash: bool,
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+    /// Extension tag to store after the payload of every block, leaving less room for it: version,
+    /// description, page-size, device-type, sha2 (in hex), or a 24-bit tag type with a text value;
+    /// may be repeated
+    tags: Vec<ExtensionTag>,
+    #[arg(long, value_name = "ALGORITHM")]
+    /// Store a checksum of the payloads of every block, in block order, as an extension tag in the
+    /// last block, which verify checks. Every block leaves room for it
+    image_checksum: Option<ChecksumAlgo>,
+    #[arg(short, long, value_name = "PATH@ADDRESS[:FAMILY]")]
+    /// Additional binary to place at ADDRESS, tagged with FAMILY instead of --family if given;
+    /// may be repeated
+    region: Vec<String>,
+    #[arg(long, value_name = "PATH@ADDRESS")]
+    /// File of metadata, such as a serial number or build id, to place at ADDRESS with the main
+    /// input's family, written after every other block whatever its address; may be repeated
+    trailer: Vec<String>,
+    #[arg(long)]
+    /// Warn instead of failing when regions overlap
+    allow_overlap: bool,
+    #[arg(long, value_parser=parse_alignment)]
+    /// Require every region to start on an N-byte boundary, and pad its end up to the next one with
+    /// the fill byte [default fill: 0xFF]
+    align: Option<u32>,
+    #[arg(long, value_name = "N", value_parser=parse_alignment)]
+    /// Fail before writing anything if the base address or the start of any region is not a
+    /// multiple of N, such as the target's flash sector size
+    require_alignment: Option<u32>,
+    #[arg(long, value_parser=parse_multibase_u32)]
+    /// End of the target's flash: fail if any block would write at or past this address
+    max_address: Option<u32>,
+    #[arg(long)]
+    /// Also write the SHA-256 of the output to OUTPUT.sha256, in sha256sum format
+    sha256: bool,
+    #[arg(long, value_name = "PATH")]
+    /// Also write a JSON manifest of how the output was made to PATH: its inputs, layout and
+    /// options, block count, size and SHA-256
+    report: Option<String>,
+    #[arg(long)]
+    /// Once the output is written, read it back and check that it holds exactly the input
+    verify_after: bool,
+    #[arg(long)]
+    /// Keep running, and regenerate the output, overwriting it, whenever an input file changes;
+    /// stop with Ctrl-C
+    watch: bool,
+    #[arg(skip)]
+    /// Set for the runs made by --watch, whose input a build may truncate while it is being read,
+    /// which a memory map would turn into a SIGBUS
+    watched: bool,
+    #[arg(short, long, default_value_t = 1)]
+    /// Encode binary input on this many threads, reading it into memory first; 0 uses every CPU.
+    /// With 1, the input is streamed
+    jobs: usize,
+    #[arg(long, value_parser=parse_multibase_u32)]
+    /// Number of bytes the input holds. Without this or --num-blocks, input of unknown size, such
+    /// as stdin or a named pipe, is streamed and the block count patched in at the end, which needs
+    /// an output file rather than a pipe. For a regular file, it must match the file's length
+    file_size: Option<u32>,
+    #[arg(long, value_parser=parse_multibase_u32, conflicts_with = "file_size")]
+    /// Number of blocks the input makes, for streaming it to a pipe when its size is not known
+    num_blocks: Option<u32>,
+    #[arg(long, value_name = "N", value_parser=parse_multibase_u32)]
+    /// Record N as the block count of every block instead of the number written, for bootloaders
+    /// that expect the count of a whole image when only some of its blocks are sent
+    stamp_num_blocks: Option<u32>,
+    #[arg(long, value_name = "N", value_parser=parse_multibase_u32, conflicts_with = "family")]
+    /// Record N as the file size of every block without a family instead of the bytes written
+    stamp_file_size: Option<u32>,
+    #[arg(long, value_name = "M", value_parser=parse_multibase_u32, requires = "total_blocks")]
+    /// Number the first block M and count up from there, to write blocks M onwards of a larger
+    /// image for a resumable or chunked transfer
+    start_block: Option<u32>,
+    #[arg(long, value_name = "T", value_parser=parse_multibase_u32,
+        conflicts_with_all = ["stamp_num_blocks", "image_checksum"])]
+    /// Record T, the block count of the whole image, in every block, failing if the blocks written
+    /// from --start-block on do not fit in it
+    total_blocks: Option<u32>,
+    #[arg(long, value_name = "N", value_parser=parse_multibase_u32,
+        conflicts_with_all = ["stamp_num_blocks", "total_blocks"])]
+    /// Append blocks with no payload, marked not main flash so that bootloaders skip them, until
+    /// the block count is a multiple of N, for transports that send a fixed number of blocks at a
+    /// time. They count towards the block count of every block
+    align_output: Option<u32>,
+    #[arg(long, default_value_t = DEFAULT_BUFFER_SIZE, value_parser=parse_buffer_size)]
+    /// Size in bytes of the read and write buffers: a multiple of 512, at most 64 MiB
+    buffer_size: usize,
+    #[arg(long)]
+    /// Overwrite the output file if it already exists
+    force: bool,
+}
+
+impl GenerateArgs {
+    /// The library options the flags ask for; the rest of the flags are handled by generate itself
+    fn options(&self) -> Uf2Options {
+        Uf2Options {
+            page_size: self.page_size,
+            family: self.family,
+            base_address: self.base_address.unwrap_or_default(),
+            fill: self.fill.or(self.pad.then_some(0xFF)),
+            block_fill: self.block_fill,
+            md5: self.md5,
+            block_crc: self.block_crc,
+            not_main_flash: self.not_main_flash,
+            extension_tags: self.tags.clone(),
+            num_blocks_override: self.total_blocks.or(self.stamp_num_blocks),
+            file_size_override: self.stamp_file_size,
+            block_multiple: self.align_output.unwrap_or_default(),
+            first_block_no: self.start_block.unwrap_or_default(),
+            image_checksum: self.image_checksum.map(ChecksumAlgo::image_checksum),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum InputFormat {
+    /// Raw binary
+    Bin,
+    /// Intel HEX
+    Hex,
+    /// ELF executable; each loadable segment is placed at its physical address
+    Elf,
+}
+
+impl InputFormat {
+    /// Detects the format from the extension of `path`, looking past a `.gz` extension
+    fn detect(path: &str) -> Self {
+        let path = std::path::Path::new(path);
+        let path = if is_gz_path(path.as_os_str().to_str().unwrap_or_default()) {
+            std::path::Path::new(path.file_stem().unwrap_or_default())
+        } else {
+            path
+        };
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        match extension {
+            Some(extension)
+                if extension.eq_ignore_ascii_case("hex")
+                    || extension.eq_ignore_ascii_case("ihex") =>
+            {
+                Self::Hex
+            }
+            Some(extension)
+                if extension.eq_ignore_ascii_case("elf")
+                    || extension.eq_ignore_ascii_case("axf") =>
+            {
+                Self::Elf
+            }
+            _ => Self::Bin,
+        }
+    }
 }
 
-/// Read uf2 block metadata
+/// Print the header fields of every block in a uf2 file, or a table summarizing several files
 #[derive(Args)]
-struct ReadArgs {
-    input: String,
+struct InfoArgs {
+    #[arg(required = true)]
+    /// Files to describe, or - for stdin; given more than one, prints one row per file, sorted by
+    /// name
+    inputs: Vec<String>,
     #[arg(short, long)]
-    verbose: bool,
+    /// Only print the memory map, not every block
+    summary: bool,
+    #[arg(long)]
+    /// Print a JSON summary of the file instead of every block
+    json: bool,
+    #[arg(long, conflicts_with_all = ["summary", "json"])]
+    /// Only print the extension tags, each distinct tag once
+    tags: bool,
+    #[arg(long, conflicts_with_all = ["summary", "json", "tags"])]
+    /// Only print the family ids the blocks are tagged with, each once with its name, failing if
+    /// no block has one
+    families_only: bool,
+    #[arg(long)]
+    /// Print blocks in address order, one family at a time, rather than in the order they are
+    /// stored
+    sort: bool,
+    #[arg(long, value_name = "N", conflicts_with_all = ["summary", "json", "tags", "families_only"])]
+    /// Only print the first N and last N blocks, eliding those in between; the summary still
+    /// covers every block
+    limit: Option<usize>,
+}
+
+/// Print a single block's header and a hex dump of its payload
+#[derive(Args)]
+struct DumpBlockArgs {
+    /// File to read, or - for stdin
+    input: String,
+    #[arg(value_name = "N")]
+    /// The block's position in the file, counting from 0, so that it starts at byte N * 512
+    block: u32,
+    #[arg(long)]
+    /// Dump the blocks whose block number is N instead, wherever they are in the file
+    by_block_no: bool,
+}
+
+/// Print the number of blocks in a uf2 file
+#[derive(Args)]
+struct CountArgs {
+    input: String,
+    #[arg(long)]
+    /// Print the total number of payload bytes instead
+    bytes: bool,
+}
+
+/// Check whether a file is a uf2 from its first 8 bytes, exiting with 0 if it is and 3 if not
+#[derive(Args)]
+struct IsUf2Args {
+    input: String,
+    #[arg(long)]
+    /// Also print yes or no
+    print: bool,
+}
+
+/// Print a checksum of each contiguous region of a uf2 file, to compare builds without diffing them
+#[derive(Args)]
+struct ChecksumArgs {
+    input: String,
+    #[arg(long, value_enum, default_value_t = ChecksumAlgo::Crc32)]
+    algo: ChecksumAlgo,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ChecksumAlgo {
+    Crc32,
+    Sha256,
+}
+
+impl ChecksumAlgo {
+    fn image_checksum(self) -> ImageChecksum {
+        match self {
+            Self::Crc32 => ImageChecksum::Crc32,
+            Self::Sha256 => ImageChecksum::Sha256,
+        }
+    }
+
+    fn digest(self, data: &[u8]) -> String {
+        match self {
+            Self::Crc32 => format!("{:08x}", crc32(data)),
+            Self::Sha256 => hex_digest(&sha256(data)),
+        }
+    }
 }
 
 /// Extract binary data from uf2 files
@@ -66,37 +512,245 @@ struct ExtractArgs {
     #[arg(short, long)]
     output: String,
     #[arg(short, long, value_parser=parse_multibase_u32)]
-    start_addr: u32,
+    /// First address to extract (defaults to the lowest address in the uf2)
+    start_addr: Option<u32>,
+    #[arg(long, value_parser=parse_multibase_u32, conflicts_with = "start_addr")]
+    /// Address that the start of the output corresponds to, with fill up to the first block; unlike
+    /// --start-addr, fails if any block is below it rather than leaving that data out
+    image_base: Option<u32>,
     #[arg(short, long, value_parser=parse_multibase_u32)]
-    end_addr: u32,
-    #[arg(short, long, default_value_t = 0, value_parser=parse_multibase_u8)]
+    /// Address to stop extracting at (defaults to the end of the highest block in the uf2)
+    end_addr: Option<u32>,
+    #[arg(short, long, alias = "fill-value", default_value_t = 0xFF, value_parser=parse_multibase_u8)]
     /// Default value used if uf2 does not specify a value for this address
-    fill_value: u8,
+    fill: u8,
+    #[arg(long, value_name = "BYTE", num_args = 0..=1, default_missing_value = "0xFF", value_parser=parse_multibase_u8)]
+    /// Strip a run of BYTE [default: 0xFF] from the end of the output, such as page padding;
+    /// the same bytes elsewhere in the output are kept
+    trim: Option<u8>,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Bin)]
+    /// Format to write the extracted data in
+    output_format: OutputFormat,
+    #[arg(long = "range", value_name = "START..END", value_parser = parse_addr_range)]
+    /// Only extract the data at addresses in START..END, cutting blocks that cross its edges; may
+    /// be repeated to extract several ranges
+    ranges: Vec<Range<u32>>,
+    #[arg(long = "no-sort", action = ArgAction::SetFalse)]
+    /// Take blocks in block number order instead of address order, so that where blocks overlap
+    /// the one numbered later wins rather than the one at the higher address
+    sort: bool,
+    #[arg(long)]
+    /// Overwrite the output file if it already exists
+    force: bool,
 }
 
-pub const fn split_radix(s: &str) -> (&str, u32) {
-    let Some((prefix, val)) = s.split_at_checked(2) else {
-        return (s, 10);
-    };
-    if prefix.eq_ignore_ascii_case("0x") {
-        (val, 16)
-    } else if prefix.eq_ignore_ascii_case("0o") {
-        (val, 8)
-    } else if prefix.eq_ignore_ascii_case("0b") {
-        (val, 2)
-    } else {
-        (s, 10)
-    }
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Raw binary, with gaps between blocks filled
+    Bin,
+    /// Intel HEX; gaps are left out
+    Hex,
+    /// A record for each contiguous region, leaving gaps out: its address and length as 32-bit
+    /// little-endian integers, then its data
+    Sparse,
+    /// A renumbered uf2 with one block sequence per family, re-encoded from the extracted data
+    Uf2,
+}
+
+/// Convert a uf2 file to Intel HEX
+#[derive(Args)]
+struct ToHexArgs {
+    input: String,
+    #[arg(short, long)]
+    output: String,
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u8).range(1..=255))]
+    /// Maximum number of data bytes per record
+    record_size: u8,
+    #[arg(long)]
+    /// Overwrite the output file if it already exists
+    force: bool,
+}
+
+/// Check that a uf2 file encodes exactly the contents of a binary file
+#[derive(Args)]
+struct VerifyArgs {
+    #[arg(short, long)]
+    uf2: String,
+    #[arg(short, long)]
+    bin: String,
+    #[arg(long)]
+    /// Also check the CRC-32 of each block's payload stored by generate --block-crc
+    block_crc: bool,
+    #[arg(long, value_name = "ADDRESS", value_parser=parse_multibase_u32,
+        conflicts_with = "block_crc")]
+    /// Treat --bin as a dump of the device's memory starting at ADDRESS: compare it only where the
+    /// uf2 writes, ignoring the rest, and list every address range that differs
+    dump_base: Option<u32>,
+    #[arg(long, value_name = "BYTE", default_value_t = 0xFF, value_parser=parse_multibase_u8)]
+    /// Byte that generate --pad filled the final page with; data past the end of --bin is accepted
+    /// as padding if it is all this byte
+    fill: u8,
+}
+
+/// Move the blocks of a uf2 file to a different address
+#[derive(Args)]
+struct RelocateArgs {
+    input: String,
+    #[arg(short, long)]
+    output: String,
+    #[arg(short, long, allow_hyphen_values = true, value_parser=parse_delta,
+        required_unless_present = "new_base", conflicts_with = "new_base")]
+    /// Signed amount to add to every block's target address, e.g. -0x1000
+    delta: Option<i64>,
+    #[arg(short, long, value_parser=parse_multibase_u32)]
+    /// Address to move the lowest block to, keeping the blocks' relative layout
+    new_base: Option<u32>,
+    #[arg(long)]
+    /// Overwrite the output file if it already exists
+    force: bool,
+}
+
+/// Put the blocks of a uf2 file in address order and renumber them, leaving their contents as is
+#[derive(Args)]
+struct RepackArgs {
+    input: String,
+    #[arg(short, long)]
+    /// Where to write the repacked uf2 [default: replace the input]
+    output: Option<String>,
+    #[arg(long)]
+    /// Clear the family flag of every block, and record the total payload size in its file size
+    /// field in place of the family id, for bootloaders that expect plain uf2
+    no_family: bool,
+    #[arg(long)]
+    /// Clear the md5 checksum and extension tags flags of every block, and zero the rest of its
+    /// data area after the payload where the checksum and tags were, for bootloaders that reject
+    /// either flag
+    strip_extensions: bool,
+    #[arg(long)]
+    /// Overwrite the output file if it already exists
+    force: bool,
+}
+
+/// Split a uf2 file into parts of at most a given number of blocks
+#[derive(Args)]
+struct SplitArgs {
+    input: String,
+    #[arg(short, long)]
+    /// Output name; parts are written as NAME.000.uf2, NAME.001.uf2, ...
+    output: String,
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..))]
+    /// Maximum number of blocks per part
+    max_blocks: u32,
+    #[arg(long)]
+    /// Overwrite output files if they already exist
+    force: bool,
+}
+
+/// Split a uf2 file into one file per family, each numbered as an image of its own
+#[derive(Args)]
+struct SplitByFamilyArgs {
+    input: String,
+    #[arg(short, long)]
+    /// Output name; each family is written to NAME.FAMILY.uf2, with the family's short name from
+    /// list-families, its id in hex if it is unknown, or no-family for blocks without one
+    output: String,
+    #[arg(long)]
+    /// Overwrite output files if they already exist
+    force: bool,
+}
+
+/// Build a uf2 from the binaries and addresses listed in a manifest file
+#[derive(Args)]
+struct JoinArgs {
+    /// Manifest listing each region's file, address and optional family, as [[region]] tables
+    manifest: String,
+    #[arg(short, long)]
+    output: String,
+    #[arg(long, value_name = "PATH")]
+    /// GNU ld map file, nm output or NAME = ADDRESS list to look up addresses given as symbol
+    /// names; replaces the manifest's symbols key
+    symbols: Option<String>,
+    #[arg(long)]
+    /// Warn instead of failing when regions of the same family overlap
+    allow_overlap: bool,
+    #[arg(long)]
+    /// Overwrite the output file if it already exists
+    force: bool,
+}
+
+/// Bundle files into a uf2 file container, whose blocks carry named files instead of flash contents
+#[derive(Args)]
+struct PackArgs {
+    #[arg(short, long)]
+    output: String,
+    #[arg(required = true)]
+    /// Files to store, as NAME=PATH, or as PATH to store a file under its own file name
+    files: Vec<String>,
+    #[arg(long)]
+    /// Overwrite the output file if it already exists
+    force: bool,
+}
+
+/// Write out the files stored in a uf2 file container
+#[derive(Args)]
+struct UnpackArgs {
+    input: String,
+    #[arg(short, long, default_value = ".")]
+    /// Directory to write the files to; subdirectories in file names are created as needed
+    output: String,
+    #[arg(long)]
+    /// Overwrite files that already exist
+    force: bool,
+}
+
+/// Stitch binary files into one flat binary, each at its own address, with the gaps between them
+/// filled
+#[derive(Args)]
+struct ConcatBinArgs {
+    #[arg(short, long)]
+    output: String,
+    #[arg(required = true, value_name = "PATH@ADDRESS")]
+    /// Binary files to place, each at ADDRESS, which may be a symbol name with --symbols; the
+    /// output starts at the lowest address
+    inputs: Vec<String>,
+    #[arg(long, value_name = "PATH")]
+    /// GNU ld map file, nm output or NAME = ADDRESS list to look up addresses given as symbol names
+    symbols: Option<String>,
+    #[arg(short, long, default_value_t = 0xFF, value_parser=parse_multibase_u8)]
+    /// Byte to fill the gaps between inputs with
+    fill: u8,
+    #[arg(long)]
+    /// Warn instead of failing when inputs overlap; later inputs overwrite earlier ones
+    allow_overlap: bool,
+    #[arg(long)]
+    /// Overwrite the output file if it already exists
+    force: bool,
+}
+
+/// Check that a uf2 file conforms to the spec, listing every problem found
+#[derive(Args)]
+struct ValidateArgs {
+    input: String,
+    #[arg(long)]
+    /// Only check that the file is whole blocks with valid magic numbers, in one pass that keeps no
+    /// blocks in memory, skipping the block numbering, family and checksum checks
+    magic_only: bool,
 }
 
-pub const fn parse_multibase_u32(s: &str) -> Result<u32, ParseIntError> {
-    let (val, radix) = split_radix(s);
-    u32::from_str_radix(val, radix)
+/// Show which address ranges differ between two uf2 files
+#[derive(Args)]
+struct DiffArgs {
+    old: String,
+    new: String,
+    #[arg(long)]
+    /// Print the differences as JSON
+    json: bool,
 }
 
-pub const fn parse_multibase_u8(s: &str) -> Result<u8, ParseIntError> {
-    let (val, radix) = split_radix(s);
-    u8::from_str_radix(val, radix)
+#[derive(Args)]
+struct CompletionsArgs {
+    #[arg(value_enum)]
+    shell: completions::Shell,
 }
 
 fn get_exec_name() -> Option<String> {
@@ -106,102 +760,154 @@ fn get_exec_name() -> Option<String> {
         .and_then(|s| s.into_string().ok())
 }
 
-pub fn parse_family(s: &str) -> Result<u32, anyhow::Error> {
-    if let Ok(val) = parse_multibase_u32(s) {
-        return Ok(val);
+/// Parses a family name, or an id in hex with or without an `0x` prefix, or in decimal if it has
+/// only decimal digits
+fn parse_family(s: &str) -> Result<u32, anyhow::Error> {
+    let (digits, radix) = clif::split_radix(s);
+    if radix != 10 {
+        return u32::from_str_radix(digits, radix)
+            .map_err(|err| anyhow!("Invalid family id {s}: {err}"));
+    }
+    if !s.is_empty() && s.bytes().all(|byte| byte.is_ascii_digit()) {
+        return s
+            .parse()
+            .with_context(|| format!("Family id {s} does not fit in 32 bits"));
     }
-    for Family { short_name, id, .. } in FAMILIES {
-        if *short_name == s {
-            return Ok(*id);
+    let is_hex = !s.is_empty() && s.bytes().all(|byte| byte.is_ascii_hexdigit());
+    match (families::find_by_name(s), is_hex) {
+        (Some(family), true) => bail!(
+            "{s} could be the family {} or the id 0x{s}; write 0x{s} for the id, or {:#010X} for \
+            the family",
+            family.short_name,
+            family.id
+        ),
+        (Some(family), false) => return Ok(family.id),
+        (None, true) => {
+            return u32::from_str_radix(s, 16)
+                .with_context(|| format!("Family id 0x{s} does not fit in 32 bits"));
         }
+        (None, false) => {}
     }
+    let names = families::all()
+        .map(|family| family.short_name)
+        .collect::<Vec<_>>()
+        .join(", ");
     Err(anyhow!(
-        "Unexpected family {s}, use {} list-families to see all known family names",
+        "Unexpected family {s}, expected an id in hex or decimal, or one of: {names} \
+        (use {} list-families to see their descriptions)",
         get_exec_name().unwrap_or("clif".to_string())
     ))
 }
 
-#[derive(Clone, Debug)]
-struct UF2Block {
-    flags: u32,
-    target_addr: u32,
-    payload_size: u32,
-    block_no: u32,
-    num_blocks: u32,
-    file_size: u32,
-    data: [u8; MAX_PAYLOAD_SIZE],
+fn parse_tag(s: &str) -> Result<ExtensionTag, anyhow::Error> {
+    let (key, value) = s
+        .split_once('=')
+        .context("expected KEY=VALUE, such as version=1.2.0")?;
+    let (kind, data) = match key {
+        "version" => (tags::VERSION, value.as_bytes().to_vec()),
+        "description" => (tags::DESCRIPTION, value.as_bytes().to_vec()),
+        "page-size" => (
+            tags::PAGE_SIZE,
+            parse_multibase_u32(value)?.to_le_bytes().to_vec(),
+        ),
+        "device-type" => {
+            let (digits, radix) = clif::split_radix(value);
+            let id = u64::from_str_radix(digits, radix)?;
+            // An id that fits in 32 bits is stored as one
+            let data = match u32::try_from(id) {
+                Ok(id) => id.to_le_bytes().to_vec(),
+                Err(_) => id.to_le_bytes().to_vec(),
+            };
+            (tags::DEVICE_TYPE, data)
+        }
+        "sha2" => {
+            ensure!(
+                value.len().is_multiple_of(2),
+                "sha2 must be an even number of hex digits"
+            );
+            let digest = (0..value.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&value[i..i + 2], 16))
+                .collect::<Result<Vec<_>, _>>()
+                .context("sha2 must be hex digits")?;
+            (tags::SHA2, digest)
+        }
+        _ => {
+            let kind = parse_multibase_u32(key).map_err(|_| {
+                anyhow!(
+                    "unknown tag {key}, expected version, description, page-size, device-type, \
+                    sha2 or a tag type"
+                )
+            })?;
+            ensure!(
+                kind <= 0xFF_FFFF,
+                "tag type {kind:#X} does not fit in 24 bits"
+            );
+            (kind, value.as_bytes().to_vec())
+        }
+    };
+    ensure!(
+        data.len() <= tags::MAX_DATA_LEN,
+        "{key} is {} bytes, but a tag can hold at most {}",
+        data.len(),
+        tags::MAX_DATA_LEN
+    );
+    Ok(ExtensionTag { kind, data })
 }
 
-impl UF2Block {
-    const FAMILY_FLAG: u32 = 0x0000_2000;
-    const MAGIC_START_0: u32 = 0x0A324655;
-    const MAGIC_START_1: u32 = 0x9E5D5157;
-    const MAGIC_END: u32 = 0x0AB16F30;
+fn parse_alignment(s: &str) -> Result<u32, anyhow::Error> {
+    let align = parse_multibase_u32(s)?;
+    ensure!(align > 0, "alignment must be at least 1");
+    Ok(align)
+}
 
-    pub fn new(payload_size: u32, len: u32) -> Self {
-        Self {
-            flags: 0,
-            target_addr: 0,
-            payload_size,
-            block_no: 0,
-            num_blocks: (len + payload_size - 1) / payload_size,
-            file_size: len,
-            data: [0; MAX_PAYLOAD_SIZE],
-        }
-    }
-
-    pub fn set_family(&mut self, family: u32) {
-        self.flags |= Self::FAMILY_FLAG;
-        self.file_size = family;
-    }
-
-    pub fn as_chunk(&self) -> [u8; CHUNK_SIZE] {
-        let mut vec = ArrayVec::new();
-        vec.extend(Self::MAGIC_START_0.to_le_bytes());
-        vec.extend(Self::MAGIC_START_1.to_le_bytes());
-        vec.extend(self.flags.to_le_bytes());
-        vec.extend(self.target_addr.to_le_bytes());
-        vec.extend(self.payload_size.to_le_bytes());
-        vec.extend(self.block_no.to_le_bytes());
-        vec.extend(self.num_blocks.to_le_bytes());
-        vec.extend(self.file_size.to_le_bytes());
-        vec.extend(self.data);
-        vec.extend(Self::MAGIC_END.to_le_bytes());
-        vec.into_inner().unwrap()
-    }
-
-    pub fn read(block: &[u8; CHUNK_SIZE]) -> anyhow::Result<Self> {
-        let magic_start_0 = u32::from_le_bytes(block[..4].try_into().unwrap());
-        let magic_start_1 = u32::from_le_bytes(block[4..8].try_into().unwrap());
-        let magic_end = u32::from_le_bytes(block[CHUNK_SIZE - 4..].try_into().unwrap());
-        ensure!(
-            magic_start_0 == Self::MAGIC_START_0,
-            "Incorrect magic start 0 (expected 0x{:08X}, found 0x{magic_start_0:08X})",
-            Self::MAGIC_START_0
-        );
-        ensure!(
-            magic_start_1 == Self::MAGIC_START_1,
-            "Incorrect magic start 1 (expected 0x{:08X}, found 0x{magic_start_1:08X})",
-            Self::MAGIC_START_1
-        );
-        ensure!(
-            magic_end == Self::MAGIC_END,
-            "Incorrect magic end (expected 0x{:08X}, found 0x{magic_end:08X})",
-            Self::MAGIC_END
-        );
-        Ok(Self {
-            flags: u32::from_le_bytes(block[8..12].try_into().unwrap()),
-            target_addr: u32::from_le_bytes(block[12..16].try_into().unwrap()),
-            payload_size: u32::from_le_bytes(block[16..20].try_into().unwrap()),
-            block_no: u32::from_le_bytes(block[20..24].try_into().unwrap()),
-            num_blocks: u32::from_le_bytes(block[24..28].try_into().unwrap()),
-            file_size: u32::from_le_bytes(block[28..32].try_into().unwrap()),
-            data: block[32..CHUNK_SIZE - 4].try_into().unwrap(),
-        })
-    }
+fn parse_buffer_size(s: &str) -> Result<usize, anyhow::Error> {
+    let size = parse_multibase_u32(s)? as usize;
+    ensure!(
+        size > 0 && size.is_multiple_of(CHUNK_SIZE),
+        "buffer size must be a positive multiple of {CHUNK_SIZE}"
+    );
+    ensure!(
+        size <= MAX_BUFFER_SIZE,
+        "buffer size must be at most {MAX_BUFFER_SIZE}"
+    );
+    Ok(size)
+}
 
-    fn payload(&self) -> &[u8] {
-        &self.data[..self.payload_size as usize]
+fn parse_addr_range(s: &str) -> Result<Range<u32>, anyhow::Error> {
+    let (start, end) = s
+        .split_once("..")
+        .context("expected a range of the form START..END")?;
+    let start = parse_multibase_u32(start).with_context(|| format!("invalid start {start}"))?;
+    let end = parse_multibase_u32(end).with_context(|| format!("invalid end {end}"))?;
+    ensure!(start < end, "range 0x{start:08X}..0x{end:08X} is empty");
+    Ok(start..end)
+}
+
+fn parse_delta(s: &str) -> Result<i64, anyhow::Error> {
+    let (negative, magnitude) = match s.strip_prefix('-') {
+        Some(magnitude) => (true, magnitude),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let magnitude = i64::from(parse_multibase_u32(magnitude)?);
+    Ok(if negative { -magnitude } else { magnitude })
+}
+
+fn hex_digest(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn format_md5_mismatch(mismatch: &Md5Mismatch) -> String {
+    let Md5Checksum { addr, len, digest } = mismatch.checksum;
+    match mismatch.computed {
+        Some(computed) => format!(
+            "md5 checksum of 0x{addr:08X}..+{len} is {}, but the block expects {}",
+            hex_digest(&computed),
+            hex_digest(&digest)
+        ),
+        None => {
+            format!("md5 checksum covers 0x{addr:08X}..+{len}, which the file does not contain")
+        }
     }
 }
 
@@ -210,46 +916,50 @@ fn display_block(
     w: &mut impl std::io::Write,
     verbose: bool,
 ) -> std::io::Result<()> {
-    let not_main_flash = block.flags & 0x0000_0001 != 0;
-    let file_container = block.flags & 0x0000_1000 != 0;
-    let family_id = block.flags & 0x0000_2000 != 0;
-    let md5_checksum = block.flags & 0x0000_4000 != 0;
-    let extension_tags = block.flags & 0x0000_8000 != 0;
-
-    let mut flags = ArrayVec::<_, 5>::new();
-    if not_main_flash {
-        let _ = flags.push("not main flash");
-    }
-    if file_container {
-        let _ = flags.push("file container");
-    }
-    if family_id {
-        let _ = flags.push("family id");
-    }
-    if md5_checksum {
-        let _ = flags.push("md5 checksum");
-    }
-    if extension_tags {
-        let _ = flags.push("extension tags");
+    display_header(&block, w, verbose)?;
+    if verbose {
+        writeln!(
+            w,
+            "block data (hex): {:02X?}",
+            &block.data[..block.payload_size as usize]
+        )?;
     }
-    let flags = flags.join(", ");
-    writeln!(w, "flags: 0x{:08X} ({flags})", block.flags)?;
+    writeln!(w)?;
+    Ok(())
+}
+
+/// Prints the fields of `block` symbolically, with its file name, md5 checksum and extension tags
+/// if it has them
+fn display_header(
+    block: &UF2Block,
+    w: &mut impl std::io::Write,
+    verbose: bool,
+) -> std::io::Result<()> {
+    let file_container = block.flags & UF2Block::FILE_CONTAINER_FLAG != 0;
+    let extension_tags = block.flags & UF2Block::EXTENSION_TAGS_FLAG != 0;
+
+    writeln!(w, "{block}")?;
+    writeln!(
+        w,
+        "flags: 0x{:08X} ({})",
+        block.flags,
+        format_flags(block.flags)
+    )?;
     writeln!(w, "target address: 0x{:08X}", block.target_addr)?;
     writeln!(w, "payload size: {}", block.payload_size)?;
     writeln!(w, "block number: {}", block.block_no)?;
     writeln!(w, "number of blocks: {}", block.num_blocks)?;
-    if family_id {
-        write!(w, "family id: 0x{:08X}", block.file_size)?;
-        if let Some(family) = FAMILY_MAP.get(&block.file_size) {
-            write!(w, " ({}", family.short_name)?;
-            if verbose {
-                write!(w, ": {}", family.description)?;
-            }
-            write!(w, ")")?;
+    if let Some(family) = block.family() {
+        write!(w, "family: {}", format_family(Some(family)))?;
+        if verbose && let Some(family) = families::find_by_id(family) {
+            write!(w, " - {}", family.description)?;
         }
         writeln!(w)?;
-    } else {
-        writeln!(w, "file size: {}", block.file_size)?;
+    }
+    match block.file_size() {
+        Some(0) => writeln!(w, "file size: unknown (0)")?,
+        Some(file_size) => writeln!(w, "file size: {file_size}")?,
+        None => {}
     }
     if file_container {
         'file_container: {
@@ -278,73 +988,39 @@ fn display_block(
             }
         }
     }
-    if md5_checksum {
-        let md5_data = &block.data[MAX_PAYLOAD_SIZE - 24..];
-        let region_start = u32::from_le_bytes(md5_data[..4].try_into().unwrap());
-        let region_length = u32::from_le_bytes(md5_data[4..8].try_into().unwrap());
-        let checksum_0 = u64::from_le_bytes(md5_data[8..16].try_into().unwrap());
-        let checksum_1 = u64::from_le_bytes(md5_data[16..24].try_into().unwrap());
-        writeln!(w, "md5 checksum region start: {region_start}")?;
-        writeln!(w, "md5 checksum region length: {region_length}")?;
-        writeln!(w, "md5 checksum: {checksum_0:08X}{checksum_1:08X}")?;
+    if let Some(checksum) = block.md5_checksum() {
+        writeln!(w, "md5 checksum region start: 0x{:08X}", checksum.addr)?;
+        writeln!(w, "md5 checksum region length: {}", checksum.len)?;
+        writeln!(w, "md5 checksum: {}", hex_digest(&checksum.digest))?;
     }
     if extension_tags {
-        'extension_tags: {
-            writeln!(w, "extension tags: ")?;
-            let mut head = (block.payload_size as usize).next_multiple_of(size_of::<u32>());
-            while head <= MAX_PAYLOAD_SIZE - 4 {
-                let rem = &block.data[head..];
-                let ext_len = rem[0] as usize;
-                if ext_len > rem.len() {
-                    if verbose {
-                        write!(
-                            w,
-                            "Extension length {ext_len} exceeds length of the data buffer"
-                        )?;
-                    }
-                    break 'extension_tags;
-                }
-                let ext_type = u32::from_le_bytes(rem[..4].try_into().unwrap()) >> 8;
-
-                if ext_len == 0 && ext_type == 0 {
-                    break 'extension_tags;
+        writeln!(w, "extension tags: ")?;
+        match tags::parse(block) {
+            Ok(tags) => {
+                for tag in &tags {
+                    write!(w, "    ")?;
+                    write_extension(w, tag)?;
                 }
-
-                let ext_data = &rem[..ext_len];
-                write!(w, "    ")?;
-                write_extension(w, ext_type, ext_data)?;
-                head = (head + ext_len).next_multiple_of(size_of::<u32>());
             }
+            Err(err) if verbose => writeln!(w, "    {err}")?,
+            Err(_) => {}
         }
     }
-    if verbose {
-        writeln!(
-            w,
-            "block data (hex): {:02X?}",
-            &block.data[..block.payload_size as usize]
-        )?;
-    }
-    writeln!(w)?;
     Ok(())
 }
 
-fn write_extension(
-    w: &mut impl std::io::Write,
-    ext_type: u32,
-    ext_data: &[u8],
-) -> std::io::Result<()> {
-    match ext_type {
-        0x9F_C7_BC => {
-            writeln!(w, "version: {}", String::from_utf8_lossy(ext_data))
-        }
-        0x65_0D_9D => {
+fn write_extension(w: &mut impl std::io::Write, tag: &ExtensionTag) -> std::io::Result<()> {
+    let ext_data = tag.data.as_slice();
+    match tag.kind {
+        tags::VERSION | tags::DESCRIPTION => {
             writeln!(
                 w,
-                "device description: {}",
+                "{}: {}",
+                tag.name().unwrap(),
                 String::from_utf8_lossy(ext_data)
             )
         }
-        0x0B_E9_F7 => {
+        tags::PAGE_SIZE => {
             write!(w, "target device page size: ")?;
             if let Ok(buf) = ext_data.try_into() {
                 writeln!(w, "{}", u32::from_le_bytes(buf))
@@ -352,10 +1028,10 @@ fn write_extension(
                 writeln!(w, "{ext_data:?}")
             }
         }
-        0xB4_6D_B0 => {
-            writeln!(w, "SHA-2 firmware checksum: {ext_data:?}")
+        tags::SHA2 => {
+            writeln!(w, "SHA-2 firmware checksum: {}", hex_digest(ext_data))
         }
-        0xC8_A7_29 => {
+        tags::DEVICE_TYPE => {
             write!(w, "device type identifier: ")?;
             if let Ok(buf) = ext_data.try_into() {
                 writeln!(w, "0x{:016X}", u64::from_le_bytes(buf))
@@ -365,187 +1041,4093 @@ fn write_extension(
                 writeln!(w, "{ext_data:?}")
             }
         }
-        _ => {
-            writeln!(w, "extension type {ext_type:#06X}: {ext_data:?}")
+        tags::IMAGE_CRC32 if ext_data.len() == 4 => writeln!(
+            w,
+            "image CRC-32: {}",
+            format_image_checksum(ImageChecksum::Crc32, ext_data)
+        ),
+        kind => {
+            writeln!(w, "extension type {kind:#06X}: {ext_data:?}")
         }
     }
 }
 
-fn combine(args: CombineArgs) -> anyhow::Result<()> {
-    let mut output =
-        BufWriter::new(File::create(&args.output).context("Failed to create output file")?);
-    for file in &args.inputs {
-        let mut input = BufReader::new(
-            File::open(&file).with_context(|| format!("Failed to open input file {file}"))?,
-        );
-        std::io::copy(&mut input, &mut output).with_context(|| {
-            format!("Failed to copy data from input file {file} to output file")
-        })?;
-    }
-    println!(
-        "Wrote {} by combining {} input files",
-        args.output,
-        args.inputs.len()
-    );
-    Ok(())
+/// Path that refers to stdin or stdout rather than a file
+const STDIO_PATH: &str = "-";
+
+/// Capacity of the buffers std uses by default, kept by commands without `--buffer-size`
+const STD_BUFFER_SIZE: usize = 8 * 1024;
+/// Default `--buffer-size`: with blocks copied 512 bytes at a time, a larger buffer makes for far
+/// fewer syscalls on big images
+const DEFAULT_BUFFER_SIZE: usize = 256 * 1024;
+const MAX_BUFFER_SIZE: usize = 64 * 1024 * 1024;
+
+fn open_input(path: &str) -> std::io::Result<Box<dyn Read>> {
+    open_input_buffered(path, STD_BUFFER_SIZE)
 }
 
-fn generate(mut args: GenerateArgs) -> anyhow::Result<()> {
-    let mut input = BufReader::new(File::open(args.input).context("Failed to open input file")?);
-    let mut len: u32 = input
-        .get_ref()
-        .metadata()
-        .context("Failed to get input file metadata")?
-        .len()
-        .try_into()?;
-    if args.page_size > MAX_PAYLOAD_SIZE as u32 {
-        args.page_size = 1;
-    }
-    if args.fill.is_none() {
-        ensure!(
-            len.is_multiple_of(args.page_size),
-            "Cannot write binary of len: {len} to device with page size: {} (use fill arg to pad to page size)",
-            args.page_size
-        );
+/// Like [`open_input`], reading files through a buffer of `capacity` bytes
+fn open_input_buffered(path: &str, capacity: usize) -> std::io::Result<Box<dyn Read>> {
+    if path == STDIO_PATH {
+        Ok(Box::new(std::io::stdin().lock()))
+    } else {
+        Ok(Box::new(BufReader::with_capacity(
+            capacity,
+            File::open(path)?,
+        )))
     }
-    let payload_size = args.page_size * (MAX_PAYLOAD_SIZE as u32 / args.page_size);
-    let mut block = UF2Block::new(payload_size, len);
-    if let Some(family) = args.family {
-        block.set_family(family);
+}
+
+/// Opens `path` for reading and determines its length, decompressing it if it is gzipped.
+///
+/// A file whose metadata gives no length is measured by seeking to its end, and which method was
+/// used is logged under `-v`. Stdin, named pipes and other inputs that cannot be measured that way
+/// are passed through with no length with `stream_stdin`, and otherwise read into memory in full.
+/// With no length, generate takes the size from `--file-size` or streams the input, patching the
+/// block count into the output file once it ends; [`encode_unsized`] fails with a usage error
+/// naming `--file-size` when that output is stdout, which cannot be patched. The decompressed
+/// length of a gzip file is only known once it has been decompressed, so gzipped input is always
+/// read into memory.
+/// Regular files are mapped into memory with `map`, so that each block's payload is copied straight
+/// from the page cache, and other files, or files that cannot be mapped, are read through a buffer.
+fn open_sized_input(
+    path: &str,
+    stream_stdin: bool,
+    map: bool,
+    buffer_size: usize,
+) -> anyhow::Result<(Box<dyn Read>, Option<u64>)> {
+    let into_memory = |buf: Vec<u8>| -> (Box<dyn Read>, Option<u64>) {
+        let len = buf.len() as u64;
+        (Box::new(std::io::Cursor::new(buf)), Some(len))
+    };
+    if path == STDIO_PATH {
+        let mut input = BufReader::with_capacity(buffer_size, std::io::stdin());
+        let start = input.fill_buf().context("Failed to read stdin")?;
+        // An empty input is read as such, so that it fails before the output is created
+        if stream_stdin && !start.is_empty() && !gzip::is_gzip(start) {
+            return Ok((Box::new(input), None));
+        }
+        let mut buf = Vec::new();
+        input
+            .read_to_end(&mut buf)
+            .context("Failed to read stdin")?;
+        return Ok(into_memory(decompress_input(path, buf)?));
     }
-    block.target_addr = args.target_addr_start;
-    let mut output =
-        BufWriter::new(File::create(&args.output).context("Failed to create output file")?);
-    while len > 0 {
-        if len < payload_size {
-            block.payload_size = len;
+    let mut file = File::open(path).context("Failed to open input file")?;
+    let metadata = file
+        .metadata()
+        .context("Failed to get input file metadata")?;
+    // Block devices and many files under /proc report a length of 0, but a block device can be
+    // measured by seeking to its end; named pipes, and whatever else cannot, are streamed like
+    // stdin
+    let len = if metadata.is_file() && metadata.len() > 0 {
+        info!("{path} holds {} bytes, from its metadata", metadata.len());
+        Some(metadata.len())
+    } else {
+        match seek_len(&mut file).with_context(|| format!("Failed to rewind {path}"))? {
+            Some(len) => {
+                info!("{path} holds {len} bytes, found by seeking to its end");
+                Some(len)
+            }
+            None => {
+                info!("{path} has no known length, so it is read until it ends");
+                None
+            }
         }
-        len -= block.payload_size;
+    };
+    if map
+        && metadata.is_file()
+        && !is_gz_path(path)
+        && let Some(len) = len
+        && let Some(map) = Mmap::map(&file, len)
+        && !gzip::is_gzip(map.as_ref())
+    {
+        info!("Mapped {path} into memory");
+        return Ok((Box::new(map.into_reader()), Some(len)));
+    }
+    let mut input = BufReader::with_capacity(buffer_size, file);
+    let start = input.fill_buf().context("Failed to read input file")?;
+    if is_gz_path(path)
+        || gzip::is_gzip(start)
+        || (len.is_none() && (!stream_stdin || start.is_empty()))
+    {
+        let mut buf = Vec::new();
         input
-            .read_exact(&mut block.data[..block.payload_size as usize])
-            .context("Failed to read UF2 Block data from input")?;
+            .read_to_end(&mut buf)
+            .context("Failed to read input file")?;
+        return Ok(into_memory(decompress_input(path, buf)?));
+    }
+    Ok((Box::new(input), len))
+}
 
-        if let Some(fill) = args.fill
-            && !block.payload_size.is_multiple_of(args.page_size)
-        {
-            assert_eq!(len, 0);
-            let curr_size = block.payload_size;
-            let next_size = curr_size.next_multiple_of(args.page_size);
-            assert!(next_size < MAX_PAYLOAD_SIZE as u32);
-            block.data[curr_size as usize..next_size as usize].fill(fill);
-            block.payload_size = next_size;
-            println!(
-                "Filled {} bytes with [{:#04X}]",
-                next_size - curr_size,
-                fill
-            );
+/// The length of `file` found by seeking to its end and back, or `None` if it cannot seek or
+/// claims to be empty
+fn seek_len(file: &mut File) -> std::io::Result<Option<u64>> {
+    match file.seek(SeekFrom::End(0)) {
+        Ok(len) if len > 0 => {
+            file.rewind()?;
+            Ok(Some(len))
         }
-
-        output
-            .write_all(&block.as_chunk())
-            .context("Failed to write UF2 block to output")?;
-        block.block_no += 1;
-        block.target_addr += block.payload_size;
+        // A file that reports a length of 0 may still have contents to read
+        _ => Ok(None),
     }
-    println!("{} written with {} block(s)", args.output, block.num_blocks);
-    Ok(())
 }
 
-fn read(args: ReadArgs) -> anyhow::Result<()> {
-    let mut input = BufReader::new(File::open(&args.input).context("Failed to open input file")?);
-    let len: usize = input
-        .get_ref()
-        .metadata()
-        .context("Failed to get metadata for input file")?
-        .len()
-        .try_into()?;
-    ensure!(
-        len.is_multiple_of(CHUNK_SIZE),
-        "Cannot read {} of len {len}. Must be a multiple of {CHUNK_SIZE}",
-        args.input
-    );
-    let mut buf = [0u8; CHUNK_SIZE];
-    while let Ok(()) = input.read_exact(&mut buf) {
-        let block = UF2Block::read(&buf).context("Failed to parse UF2 Block")?;
-        display_block(block, &mut std::io::stdout(), args.verbose)
-            .context("Failed to write to stdout")?;
-    }
-    Ok(())
+fn is_gz_path(path: &str) -> bool {
+    std::path::Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("gz"))
 }
 
-fn range_add(mut range: Range<u32>, offset: u32) -> Range<u32> {
-    range.start += offset;
-    range.end += offset;
-    range
+/// Decompresses `data` read from `path` if it is gzipped, detected by its magic number or a
+/// `.gz` extension, and otherwise returns it as is
+fn decompress_input(path: &str, data: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    if !is_gz_path(path) && !gzip::is_gzip(&data) {
+        return Ok(data);
+    }
+    let decompressed =
+        gzip::decompress(&data).with_context(|| format!("Failed to decompress {path}"))?;
+    info!(
+        "Decompressed {path} from {} to {} bytes",
+        data.len(),
+        decompressed.len()
+    );
+    Ok(decompressed)
 }
 
-fn range_sub(mut range: Range<u32>, offset: u32) -> Range<u32> {
-    range.start -= offset;
-    range.end -= offset;
-    range
+/// Opens the output like [`create_output_buffered`], or a sink that discards everything on a dry
+/// run
+fn open_output(
+    path: &str,
+    force: bool,
+    dry_run: bool,
+    buffer_size: usize,
+) -> anyhow::Result<Box<dyn Write>> {
+    if dry_run {
+        return Ok(Box::new(std::io::sink()));
+    }
+    create_output_buffered(path, force, buffer_size)
 }
 
-fn range_intersect(a: Range<u32>, b: Range<u32>) -> Range<u32> {
-    u32::max(a.start, b.start)..u32::min(a.end, b.end)
+/// Prints the blocks that would be written to `path`, their size and the memory they cover
+fn print_plan(path: &str, blocks: &[UF2Block]) -> anyhow::Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    let size = (blocks.len() * CHUNK_SIZE) as u64;
+    writeln!(stdout, "dry run: would write {path}")
+        .and_then(|()| writeln!(stdout, "blocks: {}", blocks.len()))
+        .and_then(|()| writeln!(stdout, "size: {size} bytes ({})", format_size(size)))
+        .and_then(|()| write_family_counts(blocks, &mut stdout))
+        .and_then(|()| write_memory_map(blocks, &mut stdout))
+        .context("Failed to write to stdout")
 }
 
-fn range_index(r: Range<u32>) -> Range<usize> {
-    r.start as usize..r.end as usize
+/// Opens `path` for writing, refusing to replace an existing file unless `force` is set
+fn create_output(path: &str, force: bool) -> anyhow::Result<Box<dyn Write>> {
+    create_output_buffered(path, force, STD_BUFFER_SIZE)
 }
 
-fn extract(args: ExtractArgs) -> anyhow::Result<()> {
-    let mut input = BufReader::new(File::open(&args.input).context("Failed to open input file")?);
-    let len: usize = input
-        .get_ref()
-        .metadata()
-        .context("Failed to get metadata for input file")?
-        .len()
-        .try_into()?;
-    ensure!(
-        len.is_multiple_of(CHUNK_SIZE),
-        "Cannot read {} of len {len}. Must be a multiple of {CHUNK_SIZE}",
-        args.input
-    );
-    let output_range = args.start_addr..args.end_addr;
-    let mut input_buf = [0u8; CHUNK_SIZE];
-    let mut output_buf = vec![args.fill_value; output_range.len()];
-    while let Ok(()) = input.read_exact(&mut input_buf) {
-        let block = UF2Block::read(&input_buf).context("Failed to parse UF2 block")?;
-        let block_range = range_add(0..block.payload_size, block.target_addr);
-        let intersect_range = range_intersect(output_range.clone(), block_range);
-        if intersect_range.is_empty() {
-            continue;
-        }
-        let block_rel_intersection = range_sub(intersect_range.clone(), block.target_addr);
-        let output_rel_intersection = range_sub(intersect_range.clone(), args.start_addr);
-        output_buf[range_index(output_rel_intersection)]
-            .copy_from_slice(&block.payload()[range_index(block_rel_intersection)]);
+/// Like [`create_output`], writing through a buffer of `capacity` bytes
+fn create_output_buffered(
+    path: &str,
+    force: bool,
+    capacity: usize,
+) -> anyhow::Result<Box<dyn Write>> {
+    if path == STDIO_PATH {
+        return Ok(Box::new(BufWriter::with_capacity(
+            capacity,
+            std::io::stdout().lock(),
+        )));
     }
-    let mut output = File::create(&args.output).context("Failed to create output file")?;
-    output
-        .write_all(&output_buf)
-        .context("Failed to write to output file")?;
-    Ok(())
+    Ok(Box::new(BufWriter::with_capacity(
+        capacity,
+        create_output_file(path, force)?,
+    )))
+}
+
+/// Like [`create_output`], for output that must be a file
+fn create_output_file(path: &str, force: bool) -> anyhow::Result<File> {
+    ensure!(
+        force || !std::path::Path::new(path).exists(),
+        "Output {path} exists; pass --force to overwrite"
+    );
+    File::create(path).context("Failed to create output file")
+}
+
+/// Passes writes through to `inner` while computing the SHA-256 of everything written
+struct Sha256Writer<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Sha256Writer<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Flushes the output and returns its digest, first writing it to `<path>.sha256` in the
+    /// format of `sha256sum` if `sidecar` is set
+    fn finish(mut self, path: &str, sidecar: bool, force: bool) -> anyhow::Result<[u8; 32]> {
+        self.inner
+            .flush()
+            .context("Failed to write to output file")?;
+        let digest = self.hasher.finalize();
+        if !sidecar {
+            return Ok(digest);
+        }
+        if path == STDIO_PATH {
+            info!("Not writing a sha256 file for output to stdout");
+            return Ok(digest);
+        }
+        let sidecar_path = format!("{path}.sha256");
+        let name = std::path::Path::new(path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(path);
+        let mut sidecar = create_output(&sidecar_path, force)?;
+        writeln!(sidecar, "{}  {name}", hex_digest(&digest))
+            .and_then(|()| sidecar.flush())
+            .with_context(|| format!("Failed to write to {sidecar_path}"))?;
+        info!("Wrote sha256 of {path} to {sidecar_path}");
+        Ok(digest)
+    }
+}
+
+impl<W: Write> Write for Sha256Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Fails unless `len` is a whole number of blocks
+fn check_block_aligned(path: &str, len: u64) -> anyhow::Result<()> {
+    if !len.is_multiple_of(CHUNK_SIZE as u64) {
+        return Err(Uf2Error::NotBlockAligned { len })
+            .with_context(|| format!("Cannot read {path}"));
+    }
+    Ok(())
+}
+
+/// Where a block was read from
+struct BlockSource<'a> {
+    file: &'a str,
+    offset: usize,
+}
+
+impl std::fmt::Display for BlockSource<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} offset {:#X}", self.file, self.offset)
+    }
+}
+
+/// Splits an input of the form `PATH@ADDRESS` into its path and address
+fn split_input_addr(input: &str) -> anyhow::Result<(&str, Option<u32>)> {
+    match input.rsplit_once('@') {
+        Some((path, addr)) => {
+            let addr = parse_multibase_u32(addr)
+                .with_context(|| format!("Invalid address {addr} for input {path}"))?;
+            Ok((path, Some(addr)))
+        }
+        None => Ok((input, None)),
+    }
+}
+
+/// The number of threads to use for `--jobs`, where 0 means one per CPU
+fn thread_count(jobs: usize) -> anyhow::Result<NonZeroUsize> {
+    match NonZeroUsize::new(jobs) {
+        Some(jobs) => Ok(jobs),
+        None => std::thread::available_parallelism().context("Failed to count CPUs"),
+    }
+}
+
+/// Input files that together hold less than this are read on one thread, as starting threads
+/// would take longer than reading them
+const PARALLEL_READ_MIN: u64 = 1 << 20;
+
+/// Reads the blocks of every input file, along with where each block came from
+///
+/// The files are read on up to `jobs` threads when they are large enough to be worth it; the
+/// blocks are in input order either way.
+fn read_inputs(
+    inputs: &[String],
+    buffer_size: usize,
+    jobs: NonZeroUsize,
+    on_error: OnError,
+) -> anyhow::Result<ReadInputs<'_>> {
+    let total_len: u64 = inputs
+        .iter()
+        .filter_map(|input| {
+            let (spec, _) = split_region_family(input).ok()?;
+            std::fs::metadata(split_input_addr(spec).ok()?.0).ok()
+        })
+        .map(|metadata| metadata.len())
+        .sum();
+    let per_input = if jobs.get() > 1 && inputs.len() > 1 && total_len >= PARALLEL_READ_MIN {
+        let per_thread = inputs.len().div_ceil(jobs.get());
+        debug!(
+            "Reading {} inputs on {} threads",
+            inputs.len(),
+            inputs.len().div_ceil(per_thread)
+        );
+        std::thread::scope(|scope| {
+            let threads = inputs
+                .chunks(per_thread)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|input| read_input_blocks(input, buffer_size))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            threads
+                .into_iter()
+                .flat_map(|thread| thread.join().unwrap())
+                .collect::<Vec<_>>()
+        })
+    } else {
+        inputs
+            .iter()
+            .map(|input| read_input_blocks(input, buffer_size))
+            .collect()
+    };
+    let mut read = ReadInputs {
+        blocks: Vec::new(),
+        sources: Vec::new(),
+        skipped: 0,
+    };
+    for (input, result) in inputs.iter().zip(per_input) {
+        let (file, file_blocks) = match result {
+            Ok(read) => read,
+            Err(err) if on_error == OnError::Skip && ExitCode::of(&err) != ExitCode::Io => {
+                warn!("Skipping {input}: {err:#}");
+                read.skipped += 1;
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        read.sources
+            .extend((0..file_blocks.len()).map(|i| BlockSource {
+                file,
+                offset: i * CHUNK_SIZE,
+            }));
+        read.blocks.extend(file_blocks);
+    }
+    ensure!(
+        inputs.is_empty() || read.skipped < inputs.len(),
+        InvalidError(format!("All {} inputs were skipped", inputs.len()))
+    );
+    Ok(read)
+}
+
+/// The blocks of the inputs to combine or merge, and where each came from
+struct ReadInputs<'a> {
+    blocks: Vec<UF2Block>,
+    sources: Vec<BlockSource<'a>>,
+    /// Inputs left out under `--on-error skip`
+    skipped: usize,
+}
+
+/// Fails once the output is written if `--on-error skip` left out any of `inputs`
+fn check_skipped(skipped: usize, inputs: &[String]) -> anyhow::Result<()> {
+    ensure!(
+        skipped == 0,
+        InvalidError(format!(
+            "Skipped {skipped} of {} inputs that could not be read as uf2 or placed",
+            inputs.len()
+        ))
+    );
+    Ok(())
+}
+
+/// Reads one input to combine or merge: a uf2, moved if it is given as `PATH@ADDRESS`, or a binary
+/// given as `PATH@ADDRESS[:FAMILY]`
+fn read_input_blocks(input: &str, buffer_size: usize) -> anyhow::Result<(&str, Vec<UF2Block>)> {
+    let (spec, family) = split_region_family(input)?;
+    let (file, addr) = split_input_addr(spec)?;
+    let mut input = open_input_buffered(file, buffer_size)
+        .with_context(|| format!("Failed to open input file {file}"))?;
+    let mut magic = Vec::with_capacity(4);
+    input
+        .by_ref()
+        .take(4)
+        .read_to_end(&mut magic)
+        .with_context(|| format!("Failed to read input file {file}"))?;
+    let input = magic.as_slice().chain(input);
+    let blocks = if magic == UF2Block::MAGIC_START_0.to_le_bytes() {
+        ensure!(
+            family.is_none(),
+            UsageError(format!(
+                "{file} is a uf2, whose blocks keep their own family; :FAMILY is only for \
+                binary inputs"
+            ))
+        );
+        let mut file_blocks =
+            read_blocks(input).with_context(|| format!("Failed to read input file {file}"))?;
+        if let Some(addr) = addr {
+            rebase(&mut file_blocks, addr).with_context(|| format!("Failed to move {file}"))?;
+        }
+        file_blocks
+    } else {
+        let addr = addr.ok_or_else(|| {
+            UsageError(format!(
+                "{file} is not a uf2, so it needs an address to be placed at \
+                (PATH@ADDRESS[:FAMILY])"
+            ))
+        })?;
+        let mut data = Vec::new();
+        { input }
+            .read_to_end(&mut data)
+            .with_context(|| format!("Failed to read input file {file}"))?;
+        debug!("{file}: binary of {} bytes at 0x{addr:08X}", data.len());
+        if family.is_none() {
+            warn!("{file} is given no family (PATH@ADDRESS:FAMILY), so its blocks carry none");
+        }
+        bin_blocks(&data, addr, family).with_context(|| format!("Failed to place {file}"))?
+    };
+    Ok((file, blocks))
+}
+
+/// Payload size of the blocks binary inputs to combine and merge are split into, which is what most
+/// bootloaders expect
+const BIN_INPUT_PAYLOAD_SIZE: usize = 256;
+
+/// Splits binary input into blocks placed from `addr`, the last of them holding whatever is left
+fn bin_blocks(data: &[u8], addr: u32, family: Option<u32>) -> anyhow::Result<Vec<UF2Block>> {
+    let len = u32::try_from(data.len())
+        .ok()
+        .filter(|len| addr.checked_add(*len).is_some())
+        .with_context(|| {
+            format!(
+                "{} bytes at 0x{addr:08X} do not fit in the 32-bit address space",
+                data.len()
+            )
+        })?;
+    let mut blocks = Vec::new();
+    for (i, payload) in data.chunks(BIN_INPUT_PAYLOAD_SIZE).enumerate() {
+        let mut block = UF2Block::new(payload.len() as u32, len);
+        block.block_no = i as u32;
+        block.num_blocks = num_blocks(len, BIN_INPUT_PAYLOAD_SIZE as u32);
+        block.target_addr = addr + (i * BIN_INPUT_PAYLOAD_SIZE) as u32;
+        block.data[..payload.len()].copy_from_slice(payload);
+        if let Some(family) = family {
+            block.set_family(family);
+        }
+        blocks.push(block);
+    }
+    Ok(blocks)
+}
+
+/// Warns if the inputs declare more than one family, or fails if `strict` is set; returns whether
+/// they do. Each block keeps its own family either way; blocks with no family do not conflict with
+/// any.
+fn check_family_mix(
+    blocks: &[UF2Block],
+    sources: &[BlockSource],
+    strict: bool,
+) -> anyhow::Result<bool> {
+    let mut first_seen: Vec<(u32, &str)> = Vec::new();
+    for (block, source) in blocks.iter().zip(sources) {
+        if let Some(family) = block.family()
+            && !first_seen.iter().any(|&(seen, _)| seen == family)
+        {
+            first_seen.push((family, source.file));
+        }
+    }
+    if first_seen.len() < 2 {
+        return Ok(false);
+    }
+    let message = format!(
+        "Inputs are for different families: {}",
+        first_seen
+            .iter()
+            .map(|&(family, file)| format!("{} in {file}", format_family(Some(family))))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    ensure!(!strict, "{message}");
+    warn!("{message}; each block keeps its family (pass --strict-family to make this an error)");
+    Ok(true)
+}
+
+/// Warns when the inputs carry different payload sizes, or fails if `strict` is set; returns
+/// whether they do
+///
+/// The last block of an input is often shorter than the rest, so each input is described by its
+/// largest payload.
+fn check_payload_sizes(
+    blocks: &[UF2Block],
+    sources: &[BlockSource],
+    strict: bool,
+) -> anyhow::Result<bool> {
+    let mut sizes: Vec<(&str, u32)> = Vec::new();
+    for (block, source) in blocks.iter().zip(sources) {
+        match sizes.iter_mut().find(|(file, _)| *file == source.file) {
+            Some((_, size)) => *size = (*size).max(block.payload_size),
+            None => sizes.push((source.file, block.payload_size)),
+        }
+    }
+    if sizes.iter().all(|&(_, size)| size == sizes[0].1) {
+        return Ok(false);
+    }
+    let message = format!(
+        "Inputs carry different payload sizes: {}",
+        sizes
+            .iter()
+            .map(|&(file, size)| format!("{size} bytes per block in {file}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    ensure!(!strict, "{message}");
+    warn!("{message} (pass --strict to make this an error)");
+    Ok(true)
+}
+
+/// Fails if any two blocks of the same family overlap, or only warns if `allow_overlap` is set
+fn check_overlaps(
+    blocks: &[UF2Block],
+    sources: &[BlockSource],
+    allow_overlap: bool,
+) -> anyhow::Result<()> {
+    for overlap in find_overlaps(blocks) {
+        let message = format!(
+            "Block at {} and block at {} ({}) both write to 0x{:08X}..0x{:08X}",
+            sources[overlap.first],
+            sources[overlap.second],
+            format_family(blocks[overlap.first].family()),
+            overlap.range.start,
+            overlap.range.end
+        );
+        ensure!(
+            allow_overlap,
+            "{message} (use --allow-overlap to allow this)"
+        );
+        warn!("{message}");
+    }
+    Ok(())
+}
+
+fn combine(args: CombineArgs, dry_run: bool) -> anyhow::Result<()> {
+    let mut blocks = Vec::new();
+    let mut sources = Vec::new();
+    if args.append {
+        ensure!(
+            args.output != STDIO_PATH,
+            UsageError("Cannot append to stdout; --append needs an output file".into())
+        );
+        if let Some(existing) = read_existing_output(&args.output)? {
+            sources.extend((0..existing.len()).map(|i| BlockSource {
+                file: &args.output,
+                offset: i * CHUNK_SIZE,
+            }));
+            blocks.extend(existing);
+        }
+    }
+    let existing = blocks.len();
+    let read = read_inputs(
+        &args.inputs,
+        args.buffer_size,
+        thread_count(args.jobs)?,
+        args.on_error,
+    )?;
+    blocks.extend(read.blocks);
+    sources.extend(read.sources);
+    let hint = "combine only concatenates its inputs; use merge for inputs that differ";
+    let mixed_families =
+        check_family_mix(&blocks, &sources, args.strict_family || args.strict).context(hint)?;
+    let mixed_payloads = check_payload_sizes(&blocks, &sources, args.strict).context(hint)?;
+    if mixed_families || mixed_payloads {
+        warn!(
+            "combine only concatenates its inputs; merge groups blocks by family and address into \
+            a well-formed uf2"
+        );
+    }
+    check_overlaps(&blocks, &sources, args.allow_overlap)?;
+    renumber(&mut blocks);
+    if dry_run {
+        print_plan(&args.output, &blocks)?;
+        return check_skipped(read.skipped, &args.inputs);
+    }
+    let mut output = Sha256Writer::new(create_output_buffered(
+        &args.output,
+        args.force || args.append,
+        args.buffer_size,
+    )?);
+    let mut progress = Progress::new();
+    write_blocks(
+        blocks.iter().inspect(|block| progress.block_written(block)),
+        &mut output,
+    )
+    .context("Failed to write to output file")?;
+    drop(progress);
+    output.finish(&args.output, args.sha256, args.force || args.append)?;
+    blocks.iter().for_each(log_block);
+    if existing > 0 {
+        info!(
+            "Appended {} blocks from {} input files to the {existing} blocks in {}",
+            blocks.len() - existing,
+            args.inputs.len() - read.skipped,
+            args.output
+        );
+    } else {
+        info!(
+            "Wrote {} by combining {} input files ({} blocks)",
+            args.output,
+            args.inputs.len() - read.skipped,
+            blocks.len()
+        );
+    }
+    log_family_counts(&blocks);
+    check_skipped(read.skipped, &args.inputs)
+}
+
+/// Reads the blocks of an output file being appended to, or `None` if it does not exist yet
+fn read_existing_output(path: &str) -> anyhow::Result<Option<Vec<UF2Block>>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("Failed to open {path} to append")),
+    };
+    let len = file
+        .metadata()
+        .with_context(|| format!("Failed to get metadata for {path}"))?
+        .len();
+    check_block_aligned(path, len)?;
+    let blocks = read_blocks(BufReader::new(file))
+        .with_context(|| format!("Failed to read {path} to append"))?;
+    Ok(Some(blocks))
+}
+
+fn log_block(block: &UF2Block) {
+    debug!("{block}");
+}
+
+fn format_family(family: Option<u32>) -> String {
+    match family {
+        Some(id) => match families::find_by_id(id) {
+            Some(family) => format!("{} (0x{id:08X})", family.short_name),
+            None => format!("unknown (0x{id:08X})"),
+        },
+        None => "no family".to_string(),
+    }
+}
+
+fn merge(args: MergeArgs, dry_run: bool) -> anyhow::Result<()> {
+    let ReadInputs {
+        mut blocks,
+        sources,
+        skipped,
+    } = read_inputs(
+        &args.inputs,
+        STD_BUFFER_SIZE,
+        thread_count(args.jobs)?,
+        args.on_error,
+    )?;
+    check_family_mix(&blocks, &sources, args.strict_family)?;
+    let policy = if args.allow_overlap {
+        OverlapPolicy::Warn
+    } else {
+        args.on_overlap
+    };
+    if policy == OverlapPolicy::Replace {
+        for overlap in find_overlaps(&blocks) {
+            info!(
+                "Block at {} replaces 0x{:08X}..0x{:08X} of block at {}",
+                sources[overlap.second],
+                overlap.range.start,
+                overlap.range.end,
+                sources[overlap.first]
+            );
+        }
+        let count = blocks.len();
+        blocks = overlay(&blocks);
+        debug!("Replacing overlaps took {count} blocks to {}", blocks.len());
+    } else {
+        check_overlaps(&blocks, &sources, policy == OverlapPolicy::Warn)?;
+    }
+    if let Some(align) = args.align {
+        let padding = align_blocks(&blocks, align, args.fill)?;
+        if !padding.is_empty() {
+            info!(
+                "Added {} blocks to align regions to {align} bytes",
+                padding.len()
+            );
+        }
+        blocks.extend(padding);
+    }
+    if let Some(max_address) = args.max_address {
+        check_max_address(blocks_end(&blocks), max_address)?;
+    }
+
+    sort_by_family(&mut blocks);
+    renumber(&mut blocks);
+    if dry_run {
+        print_plan(&args.output, &blocks)?;
+        return check_skipped(skipped, &args.inputs);
+    }
+
+    let mut output = create_output(&args.output, args.force)?;
+    let mut progress = Progress::new();
+    write_blocks(
+        blocks.iter().inspect(|block| progress.block_written(block)),
+        &mut output,
+    )
+    .and_then(|()| output.flush())
+    .context("Failed to write to output file")?;
+    drop(progress);
+    blocks.iter().for_each(log_block);
+    info!(
+        "Wrote {} by merging {} input files ({} blocks)",
+        args.output,
+        args.inputs.len() - skipped,
+        blocks.len()
+    );
+    log_family_counts(&blocks);
+    check_skipped(skipped, &args.inputs)
+}
+
+/// Sorts `blocks` into one run per family, in order of each family's first appearance, with each
+/// run in address order
+fn sort_by_family(blocks: &mut [UF2Block]) {
+    let mut families = Vec::new();
+    for block in &*blocks {
+        if !families.contains(&block.family()) {
+            families.push(block.family());
+        }
+    }
+    blocks.sort_by_key(|block| {
+        let group = families.iter().position(|&f| f == block.family());
+        (group, block.target_addr)
+    });
+}
+
+/// Logs how many blocks of each family were written
+fn log_family_counts(blocks: &[UF2Block]) {
+    for (family, group) in group_by_family(blocks) {
+        info!("    {}: {} blocks", format_family(family), group.len());
+    }
+}
+
+/// Checks that each contiguous run of blocks of each family starts on an `align` boundary, and
+/// returns the blocks of `fill` needed to extend each run to the next one
+fn align_blocks(blocks: &[UF2Block], align: u32, fill: u8) -> anyhow::Result<Vec<UF2Block>> {
+    let mut padding = Vec::new();
+    for (family, group) in group_by_family(blocks) {
+        let regions = coalesce(&group);
+        if let Some(region) = regions
+            .iter()
+            .find(|region| !region.addr.is_multiple_of(align))
+        {
+            bail!(
+                "Region at 0x{:08X} ({}) is not aligned to {align} bytes",
+                region.addr,
+                format_family(family)
+            );
+        }
+        for (i, region) in regions.iter().enumerate() {
+            let range = region.address_range();
+            let padded_end = range
+                .end
+                .checked_next_multiple_of(align)
+                .with_context(|| format!("Cannot align the region at 0x{:08X}", range.start))?;
+            if let Some(next) = regions.get(i + 1) {
+                ensure!(
+                    padded_end <= next.addr,
+                    "Aligning the region at 0x{:08X} to {align} bytes would overlap the region at \
+                    0x{:08X} ({})",
+                    range.start,
+                    next.addr,
+                    format_family(family)
+                );
+            }
+            // Padding blocks carry the same flags and payload size as the region's last block
+            let last = group
+                .iter()
+                .find(|block| block.address_range().end == range.end)
+                .expect("every region ends with one of its blocks");
+            let mut addr = range.end;
+            while addr < padded_end {
+                let mut block = last.clone();
+                block.target_addr = addr;
+                block.payload_size = last.payload_size.min(padded_end - addr);
+                block.data = [0; MAX_PAYLOAD_SIZE];
+                block.data[..block.payload_size as usize].fill(fill);
+                if block.md5_checksum().is_some() {
+                    block.set_md5();
+                }
+                addr += block.payload_size;
+                padding.push(block);
+            }
+        }
+    }
+    Ok(padding)
+}
+
+/// Fails if `addr` is not a multiple of the `alignment` given by `--require-alignment`
+fn check_alignment(what: &str, addr: u32, alignment: u32) -> anyhow::Result<()> {
+    ensure!(
+        addr.is_multiple_of(alignment),
+        "{what} 0x{addr:08X} is not aligned to {alignment:#X} bytes, as --require-alignment \
+        requires; the nearest aligned address below it is 0x{:08X}",
+        addr - addr % alignment
+    );
+    Ok(())
+}
+
+/// Fails if an image whose last byte is just before `end` runs past `max_address`
+fn check_max_address(end: u64, max_address: u32) -> anyhow::Result<()> {
+    let max = u64::from(max_address);
+    ensure!(
+        end <= max,
+        "Image ends at 0x{end:08X}, {} bytes past --max-address 0x{max_address:08X}",
+        end - max
+    );
+    Ok(())
+}
+
+/// The address just past the highest byte any of `blocks` writes
+fn blocks_end(blocks: &[UF2Block]) -> u64 {
+    blocks
+        .iter()
+        .map(|block| u64::from(block.target_addr) + u64::from(block.payload_size))
+        .max()
+        .unwrap_or_default()
+}
+
+/// Checks that every region starts on an `align` boundary, and pads its data with `fill` up to
+/// the next one; returns the number of bytes added
+fn align_regions<'a>(
+    regions: impl IntoIterator<Item = &'a mut Region>,
+    align: u32,
+    fill: u8,
+) -> anyhow::Result<u64> {
+    let mut added = 0;
+    for region in regions {
+        ensure!(
+            region.addr.is_multiple_of(align),
+            "Region at 0x{:08X} is not aligned to {align} bytes",
+            region.addr
+        );
+        let len = region.data.len() as u64;
+        let padded_len = len.next_multiple_of(u64::from(align));
+        ensure!(
+            u64::from(region.addr) + padded_len <= 1 << 32,
+            "Cannot align the region at 0x{:08X}",
+            region.addr
+        );
+        region.data.resize(padded_len as usize, fill);
+        added += padded_len - len;
+    }
+    Ok(added)
+}
+
+fn generate(mut args: GenerateArgs, dry_run: bool) -> anyhow::Result<()> {
+    if args.watch {
+        return watch(args, dry_run);
+    }
+    if let Some(family) = args.family {
+        check_known_family(family, args.strict_family)?;
+    }
+    ensure!(
+        args.align_output != Some(0),
+        UsageError("--align-output must be at least 1".into())
+    );
+    if let (Some(start), Some(total)) = (args.start_block, args.total_blocks) {
+        ensure!(
+            start < total,
+            UsageError(format!(
+                "--start-block {start} is past the end of an image of {total} blocks"
+            ))
+        );
+    }
+    let format = args.input.as_deref().map(|input| {
+        args.input_format
+            .unwrap_or_else(|| InputFormat::detect(input))
+    });
+    ensure!(
+        format != Some(InputFormat::Hex) || args.base_address.is_none(),
+        UsageError(
+            "HEX input carries its own addresses, so --base-address cannot be used with it".into()
+        )
+    );
+    ensure!(
+        format == Some(InputFormat::Elf) || !args.entry_base,
+        UsageError("--entry-base can only be used with ELF input".into())
+    );
+    // HEX and ELF input carry their own addresses
+    if format == Some(InputFormat::Bin)
+        && args.base_address.is_none()
+        && let Some(family) = args.family.and_then(families::find_by_id)
+        && let Some(base) = family.flash_base
+    {
+        info!(
+            "Placing the input at 0x{base:08X}, where {} flash starts; pass --base-address to \
+            place it elsewhere",
+            family.short_name
+        );
+        args.base_address = Some(base);
+    }
+    if let Some(checksum) = args.image_checksum.map(ChecksumAlgo::image_checksum) {
+        ensure!(
+            !args.tags.iter().any(|tag| tag.kind == checksum.kind()),
+            UsageError(format!(
+                "--image-checksum stores its checksum in a tag of type {:#08X}, so --tag cannot \
+                also give one",
+                checksum.kind()
+            ))
+        );
+    }
+    let mut options = args.options();
+    let fill = options.fill;
+    if args.optimize_page_size {
+        let page_size = best_page_size(&options)?;
+        if page_size != args.page_size {
+            info!(
+                "Using page size {page_size}, which divides {}, to fill more of each block",
+                args.page_size
+            );
+        }
+        args.page_size = page_size;
+        options.page_size = page_size;
+    }
+    check_page_layout(&options, args.optimize_page_size)?;
+    // A dry run encodes to a sink, so that the plan comes from the same code as a real run
+    let mut planned = Vec::new();
+    let mut progress = Progress::new();
+    let mut image_end = 0;
+    let mut written = WrittenImage::default();
+    let on_block = |block: &UF2Block| {
+        log_block(block);
+        progress.block_written(block);
+        image_end = image_end.max(u64::from(block.target_addr) + u64::from(block.payload_size));
+        written.add(block);
+        if dry_run {
+            planned.push(block.clone());
+        }
+    };
+    let sha256 = args.sha256 && !dry_run;
+    ensure!(
+        args.report.as_deref() != Some(STDIO_PATH) || args.output != STDIO_PATH,
+        UsageError("--report cannot be written to stdout along with the output".into())
+    );
+    let jobs = thread_count(args.jobs)?;
+    ensure!(
+        !args.verify_after || args.output != STDIO_PATH,
+        UsageError("--verify-after needs an output file to read back".into())
+    );
+    let input_path = args.input.as_deref().unwrap_or(STDIO_PATH);
+    // Aligning pads the data and verifying compares against it, so for either the input is read in
+    // full rather than streamed
+    let streamable = args.region.is_empty()
+        && args.trailer.is_empty()
+        && format == Some(InputFormat::Bin)
+        && args.align.is_none()
+        && !args.verify_after;
+    ensure!(
+        streamable || (args.file_size.is_none() && args.num_blocks.is_none()),
+        UsageError(
+            "--file-size and --num-blocks can only be used with a single binary input, without \
+            --region, --trailer, --align or --verify-after"
+                .into()
+        )
+    );
+    if streamable && let Some(alignment) = args.require_alignment {
+        check_alignment("Base address", options.base_address, alignment)?;
+    }
+    // Where `len` bytes at `addr` end once padded, for checking --max-address before writing
+    let padded_end = |addr: u32, len: usize| {
+        let len = len as u64;
+        let len = match fill {
+            Some(_) => len.next_multiple_of(u64::from(args.page_size.max(1))),
+            None => len,
+        };
+        u64::from(addr) + len
+    };
+    let (summary, digest) = if streamable && jobs.get() > 1 {
+        let data = read_input(input_path)?;
+        check_file_size(data.len() as u64, args.file_size)?;
+        if let Some(max_address) = args.max_address {
+            check_max_address(padded_end(options.base_address, data.len()), max_address)?;
+        }
+        // Checked before creating the output, so that no empty output file is left behind
+        if data.is_empty() {
+            return Err(Uf2Error::EmptyInput.into());
+        }
+        let mut output = Sha256Writer::new(open_output(
+            &args.output,
+            args.force,
+            dry_run,
+            args.buffer_size,
+        )?);
+        let summary = encode_bin_to_uf2_parallel(&data, &mut output, &options, jobs, on_block)
+            .map_err(suggest_pad)?;
+        let digest = output.finish(&args.output, sha256, args.force)?;
+        check_num_blocks(&summary, args.num_blocks)?;
+        (summary, Some(digest))
+    } else if streamable {
+        // A dry run measures stdin by reading it into memory, where a real run streams it
+        let stream_stdin = !dry_run;
+        let (mut input, len) =
+            open_sized_input(input_path, stream_stdin, !args.watched, args.buffer_size)?;
+        let len = match (len, args.file_size) {
+            (None, None) => None,
+            (None, Some(file_size)) => Some(file_size.into()),
+            (Some(len), file_size) => {
+                check_file_size(len, file_size)?;
+                Some(len)
+            }
+        };
+        let (summary, digest) = if let Some(len) = len {
+            let len: u32 = len
+                .try_into()
+                .context("Input file is too large for a uf2")?;
+            // Checked before creating the output, so that no empty output file is left behind
+            if len == 0 {
+                return Err(Uf2Error::EmptyInput.into());
+            }
+            if let Some(max_address) = args.max_address {
+                check_max_address(padded_end(options.base_address, len as usize), max_address)?;
+            }
+            let mut output = Sha256Writer::new(open_output(
+                &args.output,
+                args.force,
+                dry_run,
+                args.buffer_size,
+            )?);
+            let summary = encode_bin_to_uf2_with(&mut input, &mut output, len, &options, on_block)
+                .map_err(|err| match err {
+                    Uf2Error::Io(err)
+                        if args.file_size.is_some()
+                            && err.kind() == std::io::ErrorKind::UnexpectedEof =>
+                    {
+                        anyhow!("Input ended before the {len} bytes given by --file-size")
+                    }
+                    err => suggest_pad(err),
+                })?;
+            ensure!(
+                input.read(&mut [0]).context("Failed to read input")? == 0,
+                "Input holds more than the {len} bytes given by --file-size"
+            );
+            let digest = output.finish(&args.output, sha256, args.force)?;
+            (summary, Some(digest))
+        } else {
+            encode_unsized(&args, input, &options, sha256, on_block)?
+        };
+        check_num_blocks(&summary, args.num_blocks)?;
+        (summary, digest)
+    } else {
+        let mut regions = read_regions(&args, format)?;
+        if let Some(alignment) = args.require_alignment {
+            for (_, region) in &regions {
+                check_alignment("Region start", region.addr, alignment)?;
+            }
+        }
+        if let Some(align) = args.align {
+            let added = align_regions(
+                regions.iter_mut().map(|(_, region)| region),
+                align,
+                args.fill.unwrap_or(0xFF),
+            )?;
+            info!("Padded regions with {added} bytes to align them to {align} bytes");
+        }
+        let groups = group_regions_by_family(&regions);
+        for (family, group) in &groups {
+            let checked = check_region_overlaps(group, args.allow_overlap);
+            if groups.len() > 1 {
+                checked
+                    .with_context(|| format!("Regions of {} overlap", format_family(*family)))?;
+            } else {
+                checked?;
+            }
+        }
+        if regions.iter().all(|(_, region)| region.data.is_empty()) {
+            return Err(Uf2Error::EmptyInput.into());
+        }
+        if let Some(max_address) = args.max_address {
+            let end = regions
+                .iter()
+                .map(|(_, region)| padded_end(region.addr, region.data.len()))
+                .max()
+                .unwrap_or_default();
+            check_max_address(end, max_address)?;
+        }
+        // Trailers come last in `regions`, and stay last when the rest are put in address order
+        let mut to_encode = regions
+            .iter()
+            .map(|(family, region)| (*family, region))
+            .collect::<Vec<_>>();
+        let image_len = to_encode.len() - args.trailer.len();
+        to_encode[..image_len].sort_by_key(|(_, region)| region.addr);
+        let mut output = Sha256Writer::new(open_output(
+            &args.output,
+            args.force,
+            dry_run,
+            args.buffer_size,
+        )?);
+        let summary = encode_family_regions_in_order(&to_encode, &mut output, &options, on_block)
+            .map_err(suggest_pad)?;
+        let digest = output.finish(&args.output, sha256, args.force)?;
+        if args.verify_after && !dry_run {
+            verify_output(
+                &args.output,
+                &regions,
+                args.page_size,
+                fill,
+                options.first_block_no,
+                options.num_blocks_override,
+            )
+            .with_context(|| format!("Verifying {} failed", args.output))?;
+            info!("Verified that {} holds exactly the input", args.output);
+        }
+        (summary, Some(digest))
+    };
+    drop(progress);
+    if summary.padding > 0 {
+        info!(
+            "Added {} padding blocks to make {} blocks",
+            summary.padding, summary.num_blocks
+        );
+    }
+    if let Some(total) = args.total_blocks {
+        let end = u64::from(options.first_block_no) + u64::from(summary.num_blocks);
+        ensure!(
+            end <= u64::from(total),
+            "Wrote blocks {} to {}, which do not fit in the {total} blocks given by --total-blocks",
+            options.first_block_no,
+            end - 1
+        );
+    }
+    if let Some(num_blocks) = args.stamp_num_blocks
+        && num_blocks != summary.num_blocks
+    {
+        warn!(
+            "Every block claims to be one of {num_blocks} blocks, but {} were written",
+            summary.num_blocks
+        );
+    }
+    if let Some(file_size) = args.stamp_file_size
+        && u64::from(file_size) != written.payload_bytes
+    {
+        warn!(
+            "Every block claims a file size of {file_size} bytes, but the blocks hold {}",
+            written.payload_bytes
+        );
+    }
+    // Input of unknown size can only be checked once it has been written
+    if let Some(max_address) = args.max_address {
+        check_max_address(image_end, max_address)?;
+    }
+    if let Some(fill) = fill
+        && summary.filled > 0
+    {
+        info!("Filled {} bytes with [{fill:#04X}]", summary.filled);
+    }
+    if dry_run {
+        if let Some(report) = &args.report {
+            info!("Not writing {report} in a dry run");
+        }
+        return print_plan(&args.output, &planned);
+    }
+    info!(
+        "{} written with {} block(s)",
+        args.output, summary.num_blocks
+    );
+    if let Some(report) = &args.report {
+        let json = generate_report(&args, &summary, digest, &written);
+        let mut w = create_output(report, args.force)?;
+        writeln!(w, "{json:#}")
+            .and_then(|()| w.flush())
+            .with_context(|| format!("Failed to write to {report}"))?;
+        info!("Wrote report of {} to {report}", args.output);
+    }
+    Ok(())
+}
+
+/// What `generate` wrote, gathered block by block for `--report`
+#[derive(Default)]
+struct WrittenImage {
+    /// The lowest address written
+    start: Option<u32>,
+    /// The largest payload of any block
+    payload_size: u32,
+    payload_bytes: u64,
+    /// The families of the blocks, in order of first appearance
+    families: Vec<u32>,
+}
+
+impl WrittenImage {
+    fn add(&mut self, block: &UF2Block) {
+        self.start = Some(
+            self.start
+                .map_or(block.target_addr, |start| start.min(block.target_addr)),
+        );
+        self.payload_size = self.payload_size.max(block.payload_size);
+        self.payload_bytes += u64::from(block.payload_size);
+        if let Some(family) = block.family()
+            && !self.families.contains(&family)
+        {
+            self.families.push(family);
+        }
+    }
+}
+
+/// Reports how much of each block the page size of `options` wastes, and warns if blocks would end
+/// partway through a word
+fn check_page_layout(options: &Uf2Options, optimized: bool) -> anyhow::Result<()> {
+    let page_size = options.page_size;
+    let payload_size = options.payload_size()?;
+    let capacity = Uf2Options {
+        page_size: 1,
+        ..options.clone()
+    }
+    .payload_size()?;
+    // Without a page size there is nothing to report
+    if page_size > 1 {
+        info!(
+            "Page size {page_size}: each block carries {} page(s), {payload_size} bytes, leaving {} \
+            of its {capacity} bytes unused",
+            payload_size / page_size,
+            capacity - payload_size
+        );
+    }
+    if !payload_size.is_multiple_of(4) {
+        warn!(
+            "Blocks carry {payload_size} bytes, which is not a multiple of 4, so they end partway \
+            through a word; targets that program whole words may reject them"
+        );
+    }
+    let best = best_page_size(options)?;
+    if !optimized && best != page_size {
+        info!(
+            "Page size {best}, which divides {page_size}, would carry {} bytes per block; pass \
+            --optimize-page-size to use it",
+            Uf2Options {
+                page_size: best,
+                ..options.clone()
+            }
+            .payload_size()?
+        );
+    }
+    Ok(())
+}
+
+/// The divisor of the page size of `options` that gives the largest payloads, among those that are
+/// a multiple of 4 and the page size itself, preferring the largest on a tie
+fn best_page_size(options: &Uf2Options) -> anyhow::Result<u32> {
+    let page_size = options.page_size;
+    let mut best = (options.payload_size()?, page_size);
+    for divisor in (4..page_size).step_by(4).rev() {
+        if !page_size.is_multiple_of(divisor) {
+            continue;
+        }
+        let payload_size = Uf2Options {
+            page_size: divisor,
+            ..options.clone()
+        }
+        .payload_size()?;
+        if payload_size > best.0 {
+            best = (payload_size, divisor);
+        }
+    }
+    Ok(best.1)
+}
+
+/// The manifest `--report` writes, describing how `summary` was made from `args`
+fn generate_report(
+    args: &GenerateArgs,
+    summary: &EncodeSummary,
+    digest: Option<[u8; 32]>,
+    written: &WrittenImage,
+) -> Json {
+    Json::object([
+        ("output", args.output.as_str().into()),
+        ("input", args.input.as_deref().into()),
+        ("regions", args.region.iter().map(String::as_str).collect()),
+        (
+            "trailers",
+            args.trailer.iter().map(String::as_str).collect(),
+        ),
+        ("base_address", written.start.into()),
+        (
+            "families",
+            written
+                .families
+                .iter()
+                .map(|&id| {
+                    Json::object([
+                        ("id", id.into()),
+                        (
+                            "name",
+                            families::find_by_id(id)
+                                .map(|family| family.short_name)
+                                .into(),
+                        ),
+                    ])
+                })
+                .collect(),
+        ),
+        ("page_size", args.page_size.into()),
+        ("payload_size", written.payload_size.into()),
+        ("blocks", summary.num_blocks.into()),
+        ("payload_bytes", written.payload_bytes.into()),
+        (
+            "total_bytes",
+            (u64::from(summary.num_blocks) * CHUNK_SIZE as u64).into(),
+        ),
+        ("block_fill", u32::from(args.block_fill).into()),
+        ("md5", args.md5.into()),
+        ("block_crc", args.block_crc.into()),
+        ("not_main_flash", args.not_main_flash.into()),
+        ("stamp_num_blocks", args.stamp_num_blocks.into()),
+        ("stamp_file_size", args.stamp_file_size.into()),
+        ("start_block", args.start_block.into()),
+        ("total_blocks", args.total_blocks.into()),
+        ("align_output", args.align_output.into()),
+        ("padding_blocks", summary.padding.into()),
+        (
+            "image_checksum",
+            args.image_checksum
+                .map(|algo| algo.image_checksum().name())
+                .into(),
+        ),
+        (
+            "tags",
+            args.tags
+                .iter()
+                .map(|tag| {
+                    Json::object([
+                        ("type", tag.kind.into()),
+                        ("name", tag.name().into()),
+                        ("data", hex_digest(&tag.data).into()),
+                    ])
+                })
+                .collect(),
+        ),
+        (
+            "sha256",
+            digest.as_ref().map(|digest| hex_digest(digest)).into(),
+        ),
+    ])
+}
+
+/// How often `--watch` checks the inputs for changes
+const WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+/// How long the inputs must go unchanged before `--watch` regenerates, so that a build writing
+/// them in several steps leads to one run
+const WATCH_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Generates the output, then again each time the inputs change, until the process is stopped
+fn watch(mut args: GenerateArgs, dry_run: bool) -> anyhow::Result<()> {
+    let mut paths = args.input.iter().map(String::as_str).collect::<Vec<_>>();
+    for region in &args.region {
+        let (region, _) = split_region_family(region)?;
+        paths.push(split_input_addr(region)?.0);
+    }
+    for trailer in &args.trailer {
+        paths.push(split_input_addr(trailer)?.0);
+    }
+    ensure!(
+        !paths.contains(&STDIO_PATH) && args.output != STDIO_PATH,
+        UsageError("--watch needs input and output files rather than stdin and stdout".into())
+    );
+    // The size and modification time of each input, or None while it does not exist
+    let snapshot = || {
+        paths
+            .iter()
+            .map(|path| {
+                let metadata = std::fs::metadata(path).ok()?;
+                Some((metadata.len(), metadata.modified().ok()))
+            })
+            .collect::<Vec<_>>()
+    };
+    args.watch = false;
+    args.watched = true;
+    let mut seen = snapshot();
+    loop {
+        let result = generate(args.clone(), dry_run);
+        let time = utc_time(std::time::SystemTime::now());
+        match result {
+            Ok(()) => {
+                info!("[{time}] {}: ok", args.output);
+                // Later runs replace the output this one wrote, but not one that was already
+                // there without --force
+                args.force = true;
+            }
+            Err(err) => warn!("[{time}] {}: failed: {err:#}", args.output),
+        }
+        info!("Watching {} for changes", paths.join(", "));
+        // A build may delete an input before writing it again, so a missing input is waited for
+        // rather than reported
+        loop {
+            let mut changed_at = loop {
+                std::thread::sleep(WATCH_INTERVAL);
+                let now = snapshot();
+                if now != seen {
+                    seen = now;
+                    break std::time::Instant::now();
+                }
+            };
+            while changed_at.elapsed() < WATCH_DEBOUNCE {
+                std::thread::sleep(WATCH_INTERVAL);
+                let now = snapshot();
+                if now != seen {
+                    seen = now;
+                    changed_at = std::time::Instant::now();
+                }
+            }
+            if seen.iter().all(Option::is_some) {
+                break;
+            }
+            info!("Waiting for missing inputs to be written");
+        }
+    }
+}
+
+/// The time of day of `time` in UTC, as HH:MM:SS
+fn utc_time(time: std::time::SystemTime) -> String {
+    let secs = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let (hours, minutes, seconds) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    format!("{hours:02}:{minutes:02}:{seconds:02}Z")
+}
+
+fn check_file_size(len: u64, file_size: Option<u32>) -> anyhow::Result<()> {
+    if let Some(file_size) = file_size {
+        ensure!(
+            len == u64::from(file_size),
+            "Input holds {len} bytes, not the {file_size} given by --file-size"
+        );
+    }
+    Ok(())
+}
+
+fn check_num_blocks(summary: &EncodeSummary, num_blocks: Option<u32>) -> anyhow::Result<()> {
+    if let Some(num_blocks) = num_blocks {
+        let data_blocks = summary.num_blocks - summary.padding;
+        ensure!(
+            data_blocks == num_blocks,
+            "Input made {data_blocks} blocks, not the {num_blocks} given by --num-blocks"
+        );
+    }
+    Ok(())
+}
+
+/// Encodes input whose length is not known, such as stdin or a named pipe.
+///
+/// Written to a file, the blocks are numbered with a placeholder count that is patched in once the
+/// input ends. A pipe cannot be patched afterwards, so there the count must be given up front.
+fn encode_unsized(
+    args: &GenerateArgs,
+    input: impl Read,
+    options: &Uf2Options,
+    sha256: bool,
+    on_block: impl FnMut(&UF2Block),
+) -> anyhow::Result<(EncodeSummary, Option<[u8; 32]>)> {
+    if args.output == STDIO_PATH {
+        let num_blocks = args.num_blocks.ok_or_else(|| {
+            UsageError(
+                "The size of the input is not known, and the block count cannot be patched into \
+                stdout afterwards; pass --file-size or --num-blocks"
+                    .into(),
+            )
+        })?;
+        let mut output = Sha256Writer::new(create_output_buffered(
+            &args.output,
+            args.force,
+            args.buffer_size,
+        )?);
+        let summary = encode_bin_to_uf2_unsized(input, &mut output, num_blocks, options, on_block)
+            .map_err(suggest_pad)?;
+        let digest = output.finish(&args.output, sha256, args.force)?;
+        return Ok((summary, Some(digest)));
+    }
+    let file = create_output_file(&args.output, args.force)?;
+    let mut writer = Uf2Writer::new(
+        BufWriter::with_capacity(args.buffer_size, file),
+        options.clone(),
+    )?;
+    writer
+        .write_stream_with(options.base_address, input, on_block)
+        .map_err(suggest_pad)?;
+    let (_, summary) = writer.finish()?;
+    if !sha256 && args.report.is_none() {
+        return Ok((summary, None));
+    }
+    // Patching the count rewrote the blocks, so the digest is taken from the finished file
+    let mut file = File::open(&args.output).context("Failed to reopen output file")?;
+    let mut hasher = Sha256Writer::new(std::io::sink());
+    std::io::copy(&mut file, &mut hasher).context("Failed to read back output file")?;
+    let digest = hasher.finish(&args.output, sha256, args.force)?;
+    Ok((summary, Some(digest)))
+}
+
+/// Points out `--pad` when `err` is from an input that is not a whole number of pages
+fn suggest_pad(err: Uf2Error) -> anyhow::Error {
+    match err {
+        Uf2Error::NotPageAligned { .. } => anyhow::Error::from(err).context(
+            "Input is not a whole number of pages; pass --pad to pad the final page with the fill \
+            byte",
+        ),
+        err => err.into(),
+    }
+}
+
+/// Reads back the uf2 written to `path` and checks that it holds exactly `regions`, each padded to
+/// a whole number of pages with `fill` as the encoder does
+///
+/// Every block must claim to be one of `num_blocks`, or of the blocks read back if it is not given.
+fn verify_output(
+    path: &str,
+    regions: &[(Option<u32>, Region)],
+    page_size: u32,
+    fill: Option<u8>,
+    first_block_no: u32,
+    num_blocks: Option<u32>,
+) -> anyhow::Result<()> {
+    let file = File::open(path).with_context(|| format!("Failed to reopen {path}"))?;
+    let blocks = Uf2Reader::new(BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to read back {path}"))?;
+    let num_blocks = num_blocks.map_or(blocks.len() as u64, u64::from);
+    for (i, block) in blocks.iter().enumerate() {
+        let block_no = u64::from(first_block_no) + i as u64;
+        ensure!(
+            u64::from(block.block_no) == block_no && u64::from(block.num_blocks) == num_blocks,
+            "Block {i} is numbered {}/{}, expected {block_no}/{num_blocks}",
+            block.block_no,
+            block.num_blocks,
+        );
+    }
+    if let Some(mismatch) = check_md5(&blocks).first() {
+        bail!(
+            "Block {}: {}",
+            mismatch.block,
+            format_md5_mismatch(mismatch)
+        );
+    }
+
+    let expected_groups = group_regions_by_family(regions);
+    let actual_groups = group_by_family(&blocks);
+    for (family, _) in &actual_groups {
+        ensure!(
+            expected_groups.iter().any(|(other, _)| other == family),
+            "{path} has blocks for {}, which the input does not",
+            format_family(*family)
+        );
+    }
+    for (family, group) in expected_groups {
+        // Laid out as the encoder does: in address order, with later regions winning overlaps
+        let mut expected: Vec<Region> = Vec::new();
+        let mut group = group;
+        group.sort_by_key(|region| region.addr);
+        for mut region in group {
+            if let Some(fill) = fill {
+                let padded = region.data.len().next_multiple_of(page_size as usize);
+                region.data.resize(padded, fill);
+            }
+            match expected.last_mut() {
+                Some(last) if region.addr <= last.address_range().end => {
+                    let start = (region.addr - last.addr) as usize;
+                    let end = start + region.data.len();
+                    if end > last.data.len() {
+                        last.data.resize(end, 0);
+                    }
+                    last.data[start..end].copy_from_slice(&region.data);
+                }
+                _ => expected.push(region),
+            }
+        }
+        expected.retain(|region| !region.data.is_empty());
+        let actual = actual_groups
+            .iter()
+            .find(|(other, _)| *other == family)
+            .map(|(_, blocks)| coalesce(blocks))
+            .unwrap_or_default();
+        let describe = |regions: &[Region]| {
+            regions
+                .iter()
+                .map(|region| {
+                    let range = region.address_range();
+                    format!("0x{:08X}..0x{:08X}", range.start, range.end)
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        ensure!(
+            expected.len() == actual.len()
+                && expected
+                    .iter()
+                    .zip(&actual)
+                    .all(|(e, a)| e.address_range() == a.address_range()),
+            "{path} covers {} for {}, but the input covers {}",
+            describe(&actual),
+            format_family(family),
+            describe(&expected)
+        );
+        for (e, a) in expected.iter().zip(&actual) {
+            if let Some(i) = e.data.iter().zip(&a.data).position(|(e, a)| e != a) {
+                bail!(
+                    "{path} differs from the input at 0x{:08X} ({})",
+                    e.addr as usize + i,
+                    format_family(family)
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads an input to be encoded, decompressing it if it is gzipped
+fn read_input(path: &str) -> anyhow::Result<Vec<u8>> {
+    decompress_input(path, read_file(path)?)
+}
+
+fn read_file(path: &str) -> anyhow::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    open_input(path)
+        .with_context(|| format!("Failed to open input file {path}"))?
+        .read_to_end(&mut data)
+        .with_context(|| format!("Failed to read input file {path}"))?;
+    Ok(data)
+}
+
+/// Reads the main input (placed at the base address unless it is HEX or ELF), every `--region`
+/// input and then every `--trailer`, each with the family its blocks are tagged with
+fn read_regions(
+    args: &GenerateArgs,
+    format: Option<InputFormat>,
+) -> anyhow::Result<Vec<(Option<u32>, Region)>> {
+    let mut regions = Vec::new();
+    // An ELF's own family stands in for --family when that is not given
+    let mut family = args.family;
+    if let Some(input) = &args.input {
+        match format {
+            Some(InputFormat::Hex) => {
+                let data = read_input(input)?;
+                regions.extend(
+                    ihex::parse_hex(data.as_slice())
+                        .with_context(|| format!("Failed to parse HEX file {input}"))?
+                        .into_iter()
+                        .map(|region| (args.family, region)),
+                );
+            }
+            Some(InputFormat::Elf) => {
+                let data = read_input(input)?;
+                if family.is_none() {
+                    family = elf_family(&data, input)?;
+                }
+                regions.extend(
+                    elf_regions(&data, args)
+                        .with_context(|| format!("Failed to parse ELF file {input}"))?
+                        .into_iter()
+                        .map(|region| (family, region)),
+                );
+            }
+            _ => regions.push((
+                args.family,
+                Region {
+                    addr: args.base_address.unwrap_or(0),
+                    data: read_input(input)?,
+                },
+            )),
+        }
+    }
+    for region in &args.region {
+        let (region, region_family) = split_region_family(region)?;
+        let (path, addr) = split_input_addr(region)?;
+        let addr =
+            addr.with_context(|| format!("Region {path} needs an address (PATH@ADDRESS)"))?;
+        if let Some(family) = region_family {
+            check_known_family(family, args.strict_family)?;
+        }
+        regions.push((
+            region_family.or(family),
+            Region {
+                addr,
+                data: read_input(path)?,
+            },
+        ));
+    }
+    for trailer in &args.trailer {
+        let (path, addr) = split_input_addr(trailer)?;
+        let addr =
+            addr.with_context(|| format!("Trailer {path} needs an address (PATH@ADDRESS)"))?;
+        regions.push((
+            family,
+            Region {
+                addr,
+                data: read_input(path)?,
+            },
+        ));
+    }
+    Ok(regions)
+}
+
+/// Splits a region of the form `PATH@ADDRESS:FAMILY` into `PATH@ADDRESS` and its family
+fn split_region_family(region: &str) -> anyhow::Result<(&str, Option<u32>)> {
+    let Some(at) = region.rfind('@') else {
+        return Ok((region, None));
+    };
+    match region[at..].split_once(':') {
+        Some((addr, family)) => {
+            let family = parse_family(family)
+                .with_context(|| format!("Invalid family for region {region}"))?;
+            Ok((&region[..at + addr.len()], Some(family)))
+        }
+        None => Ok((region, None)),
+    }
+}
+
+/// Warns that `family` is not a known family id, or fails if `strict` is set
+fn check_known_family(family: u32, strict: bool) -> anyhow::Result<()> {
+    if families::find_by_id(family).is_none() {
+        let message = format!("Family id 0x{family:08X} is not a known family");
+        ensure!(!strict, "{message}");
+        warn!("{message}; check it for typos, or pass --strict-family to make this an error");
+    }
+    Ok(())
+}
+
+/// The family named by a note or symbol of the ELF `data`, if it names one
+fn elf_family(data: &[u8], input: &str) -> anyhow::Result<Option<u32>> {
+    let found = elf::find_family(data)
+        .with_context(|| format!("Failed to read the family of ELF file {input}"))?;
+    let Some((family, source)) = found else {
+        info!(
+            "{input} does not name a family in a {} note or a {} symbol; pass --family to set one",
+            elf::FAMILY_NOTE,
+            elf::FAMILY_SYMBOLS.join(" or ")
+        );
+        return Ok(None);
+    };
+    info!(
+        "Using family {} from {source} in {input}",
+        format_family(Some(family))
+    );
+    check_known_family(family, false)?;
+    Ok(Some(family))
+}
+
+/// Converts the loadable segments of an ELF file into regions at their physical addresses,
+/// moved as a whole if `--base-address` or `--entry-base` is given
+fn elf_regions(data: &[u8], args: &GenerateArgs) -> anyhow::Result<Vec<Region>> {
+    let elf = elf::parse(data)?;
+    let mut regions = Vec::new();
+    let mut unwritten_bss = 0;
+    for segment in elf.segments {
+        let mut data = segment.data.to_vec();
+        let bss = segment.memsz.saturating_sub(data.len() as u64);
+        if args.zero_bss && bss > 0 {
+            let memsz = usize::try_from(segment.memsz).context("Segment is too large")?;
+            debug!(
+                "Zero-filling {bss} bytes after the {} bytes of the segment at {:#010X}",
+                data.len(),
+                segment.paddr
+            );
+            data.resize(memsz, 0);
+        } else {
+            unwritten_bss += bss;
+        }
+        if data.is_empty() {
+            continue;
+        }
+        let addr = u32::try_from(segment.paddr).with_context(|| {
+            format!(
+                "Segment address {:#X} does not fit in 32 bits",
+                segment.paddr
+            )
+        })?;
+        ensure!(
+            u32::try_from(data.len() - 1).is_ok_and(|last| addr.checked_add(last).is_some()),
+            "Segment at {addr:#010X} extends past the 32-bit address space"
+        );
+        debug!("ELF segment at {addr:#010X} ({} bytes)", data.len());
+        regions.push(Region { addr, data });
+    }
+    if unwritten_bss > 0 {
+        info!(
+            "Not writing {unwritten_bss} bytes of zero-initialized data (pass --zero-bss to write it as zeros)"
+        );
+    }
+    let base = if args.entry_base {
+        let entry = u32::try_from(elf.entry)
+            .with_context(|| format!("Entry point {:#X} does not fit in 32 bits", elf.entry))?;
+        Some(entry)
+    } else {
+        args.base_address
+    };
+    if let Some(base) = base
+        && let Some(lowest) = regions.iter().map(|region| region.addr).min()
+    {
+        for region in &mut regions {
+            region.addr = (region.addr - lowest)
+                .checked_add(base)
+                .filter(|addr| addr.checked_add(region.data.len() as u32 - 1).is_some())
+                .with_context(|| {
+                    format!(
+                        "Moving segment at {:#010X} to base {base:#010X} overflows the address space",
+                        region.addr
+                    )
+                })?;
+        }
+        if lowest != base {
+            info!("Moved ELF segments from {lowest:#010X} to {base:#010X}");
+        }
+    }
+    Ok(regions)
+}
+
+/// Fails if any two regions overlap, or only warns if `allow_overlap` is set
+fn check_region_overlaps(regions: &[Region], allow_overlap: bool) -> anyhow::Result<()> {
+    let mut ranges = regions
+        .iter()
+        .map(|region| region.addr as u64..region.addr as u64 + region.data.len() as u64)
+        .collect::<Vec<_>>();
+    ranges.sort_by_key(|range| range.start);
+    for pair in ranges.windows(2) {
+        if pair[1].start < pair[0].end {
+            let message = format!(
+                "Region at 0x{:08X} overlaps region at 0x{:08X}..0x{:08X}",
+                pair[1].start, pair[0].start, pair[0].end
+            );
+            ensure!(
+                allow_overlap,
+                "{message} (use --allow-overlap to allow this)"
+            );
+            warn!("{message}");
+        }
+    }
+    Ok(())
+}
+
+/// Reads every block of the uf2 file at `path`, or of stdin for `-`
+///
+/// A file's length is checked first, so that a partial final block is reported before any block is
+/// read; stdin can only be found to end partway through a block when it does.
+fn read_uf2(path: &str) -> anyhow::Result<Vec<UF2Block>> {
+    if path == STDIO_PATH {
+        return read_blocks(std::io::stdin().lock()).context("Failed to read stdin");
+    }
+    let input = BufReader::new(
+        File::open(path).with_context(|| format!("Failed to open input file {path}"))?,
+    );
+    let len = input
+        .get_ref()
+        .metadata()
+        .with_context(|| format!("Failed to get metadata for input file {path}"))?
+        .len();
+    check_block_aligned(path, len)?;
+    read_blocks(input).with_context(|| format!("Failed to read input file {path}"))
+}
+
+fn info(args: InfoArgs) -> anyhow::Result<()> {
+    if let [input] = args.inputs.as_slice() {
+        return info_file(&args, input);
+    }
+    ensure!(
+        !args.tags,
+        UsageError("--tags describes a single file".into())
+    );
+    ensure!(
+        !args.families_only,
+        UsageError("--families-only describes a single file".into())
+    );
+    ensure!(
+        args.inputs
+            .iter()
+            .filter(|input| *input == STDIO_PATH)
+            .count()
+            <= 1,
+        UsageError("stdin can only be read once".into())
+    );
+    let mut inputs = args.inputs.clone();
+    inputs.sort();
+    let files = inputs
+        .into_iter()
+        .map(|input| read_uf2(&input).map(|blocks| (input, blocks)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if args.json {
+        let files = files
+            .iter()
+            .map(|(input, blocks)| {
+                let mut json = info_json(blocks);
+                if let Json::Object(fields) = &mut json {
+                    fields.insert(0, ("file", input.as_str().into()));
+                }
+                json
+            })
+            .collect();
+        println!("{:#}", Json::Array(files));
+        return Ok(());
+    }
+    write_info_table(&files, &mut std::io::stdout().lock()).context("Failed to write to stdout")
+}
+
+/// Prints a row for each file: its name, block count, payload bytes, families and flags
+fn write_info_table(
+    files: &[(String, Vec<UF2Block>)],
+    w: &mut impl std::io::Write,
+) -> std::io::Result<()> {
+    let header = ["file", "blocks", "bytes", "family", "flags"].map(str::to_string);
+    let rows = files
+        .iter()
+        .map(|(input, blocks)| {
+            let bytes: u64 = blocks
+                .iter()
+                .map(|block| u64::from(block.payload_size))
+                .sum();
+            let families = group_by_family(blocks)
+                .into_iter()
+                .map(|(family, _)| format_family(family))
+                .collect::<Vec<_>>();
+            let all_flags = blocks.iter().fold(0, |flags, block| flags | block.flags);
+            let known = FLAG_NAMES.iter().fold(0, |known, (bit, ..)| known | bit);
+            let mut flags = FLAG_NAMES
+                .iter()
+                .filter(|(bit, ..)| all_flags & bit != 0)
+                .map(|(_, name, _)| name.to_string())
+                .collect::<Vec<_>>();
+            if all_flags & !known != 0 {
+                flags.push(format!("unknown({:#X})", all_flags & !known));
+            }
+            [
+                input.clone(),
+                blocks.len().to_string(),
+                bytes.to_string(),
+                families.join(", "),
+                if flags.is_empty() {
+                    "none".to_string()
+                } else {
+                    flags.join(", ")
+                },
+            ]
+        })
+        .collect::<Vec<_>>();
+    let mut widths = header.clone().map(|column| column.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        let [file, blocks, bytes, family, flags] = row;
+        writeln!(
+            w,
+            "{file:<0$}  {blocks:>1$}  {bytes:>2$}  {family:<3$}  {flags}",
+            widths[0], widths[1], widths[2], widths[3]
+        )?;
+    }
+    Ok(())
+}
+
+fn info_file(args: &InfoArgs, input: &str) -> anyhow::Result<()> {
+    let mut blocks = read_uf2(input)?;
+    warn_if_unordered(input, &blocks);
+    if args.sort {
+        sort_by_family(&mut blocks);
+    }
+    if args.json {
+        println!("{:#}", info_json(&blocks));
+        return Ok(());
+    }
+    let mut stdout = std::io::stdout().lock();
+    if args.tags {
+        let mut seen = Vec::new();
+        for block in &blocks {
+            match tags::parse(block) {
+                Ok(tags) => {
+                    for tag in tags {
+                        if !seen.contains(&tag) {
+                            seen.push(tag);
+                        }
+                    }
+                }
+                Err(err) => warn!("{err}"),
+            }
+        }
+        if seen.is_empty() {
+            info!("{input} has no extension tags");
+        }
+        for tag in &seen {
+            write_extension(&mut stdout, tag).context("Failed to write to stdout")?;
+        }
+        return Ok(());
+    }
+    if args.families_only {
+        let mut families = Vec::new();
+        for family in blocks.iter().filter_map(UF2Block::family) {
+            if !families.contains(&family) {
+                families.push(family);
+            }
+        }
+        ensure!(
+            !families.is_empty(),
+            InvalidError(format!("{input} has no blocks with a family id"))
+        );
+        let untagged = blocks
+            .iter()
+            .filter(|block| block.family().is_none())
+            .count();
+        if untagged > 0 {
+            info!("{untagged} blocks of {input} have no family id");
+        }
+        for family in families {
+            writeln!(stdout, "{}", format_family(Some(family)))
+                .context("Failed to write to stdout")?;
+        }
+        return Ok(());
+    }
+    if !args.summary {
+        let limit = args.limit.unwrap_or(usize::MAX);
+        let omitted = blocks.len().saturating_sub(limit.saturating_mul(2));
+        for (i, block) in blocks.iter().enumerate() {
+            if omitted > 0 && i >= limit && i < limit + omitted {
+                if i == limit {
+                    let plural = if omitted == 1 { "" } else { "s" };
+                    writeln!(stdout, "… {omitted} block{plural} omitted …\n")
+                        .context("Failed to write to stdout")?;
+                }
+                continue;
+            }
+            display_block(block.clone(), &mut stdout, log::enabled(Level::Info))
+                .context("Failed to write to stdout")?;
+        }
+    }
+    write_flag_counts(&blocks, &mut stdout)
+        .and_then(|()| write_family_counts(&blocks, &mut stdout))
+        .and_then(|()| write_memory_map(&blocks, &mut stdout))
+        .context("Failed to write to stdout")?;
+    Ok(())
+}
+
+/// Streams the input, so that only the blocks that might be dumped are parsed; in particular,
+/// blocks before position N need not be valid
+fn dump_block(args: DumpBlockArgs) -> anyhow::Result<()> {
+    let mut input = open_input(&args.input)
+        .with_context(|| format!("Failed to open input file {}", args.input))?;
+    let mut stdout = std::io::stdout().lock();
+    let mut chunk = [0; CHUNK_SIZE];
+    let mut position = 0;
+    let mut dumped = 0;
+    while read_chunk(&mut input, &mut chunk)
+        .with_context(|| format!("Failed to read input file {}", args.input))?
+    {
+        let offset = u64::from(position) * CHUNK_SIZE as u64;
+        let matches = !args.by_block_no && position == args.block;
+        if matches || args.by_block_no {
+            let block = UF2Block::from_chunk(&chunk)
+                .map_err(|err| err.at_offset(offset))
+                .with_context(|| format!("Failed to read input file {}", args.input))?;
+            if matches || block.block_no == args.block {
+                if dumped > 0 {
+                    writeln!(stdout).context("Failed to write to stdout")?;
+                }
+                write_block_dump(&block, position, &mut stdout)
+                    .context("Failed to write to stdout")?;
+                dumped += 1;
+            }
+        }
+        if matches {
+            return Ok(());
+        }
+        position += 1;
+    }
+    ensure!(
+        dumped > 0,
+        InvalidError(if args.by_block_no {
+            format!("{} has no block numbered {}", args.input, args.block)
+        } else {
+            format!(
+                "{} has {position} blocks, so there is no block at position {}",
+                args.input, args.block
+            )
+        })
+    );
+    Ok(())
+}
+
+/// Prints where `block` is in its file, its header and its payload as a hex dump, 16 bytes a line
+/// with the address of the first and the bytes as ASCII
+fn write_block_dump(
+    block: &UF2Block,
+    position: u32,
+    w: &mut impl std::io::Write,
+) -> std::io::Result<()> {
+    writeln!(
+        w,
+        "position {position}, at offset {:#X}",
+        u64::from(position) * CHUNK_SIZE as u64
+    )?;
+    display_header(block, w, true)?;
+    writeln!(w, "payload:")?;
+    for (i, line) in block.payload().chunks(16).enumerate() {
+        let addr = u64::from(block.target_addr) + 16 * i as u64;
+        let hex = line
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let ascii = line
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect::<String>();
+        writeln!(w, "    0x{addr:08X}  {hex:<47}  |{ascii}|")?;
+    }
+    Ok(())
+}
+
+fn count(args: CountArgs) -> anyhow::Result<()> {
+    let data = read_file(&args.input)?;
+    check_block_aligned(&args.input, data.len() as u64)?;
+    let blocks = read_blocks(data.as_slice()).context("Failed to read input file")?;
+    if args.bytes {
+        let bytes: u64 = blocks
+            .iter()
+            .map(|block| u64::from(block.payload_size))
+            .sum();
+        println!("{bytes}");
+    } else {
+        println!("{}", blocks.len());
+    }
+    Ok(())
+}
+
+fn is_uf2(args: IsUf2Args) -> anyhow::Result<()> {
+    let input = open_input(&args.input)
+        .with_context(|| format!("Failed to open input file {}", args.input))?;
+    // Only the start magic numbers are read, so stdin is left unread past them
+    let mut magic = Vec::with_capacity(8);
+    input
+        .take(8)
+        .read_to_end(&mut magic)
+        .with_context(|| format!("Failed to read input file {}", args.input))?;
+    let is_uf2 = magic.len() == 8
+        && magic[..4] == UF2Block::MAGIC_START_0.to_le_bytes()
+        && magic[4..] == UF2Block::MAGIC_START_1.to_le_bytes();
+    if args.print {
+        println!("{}", if is_uf2 { "yes" } else { "no" });
+    }
+    ensure!(is_uf2, InvalidError(format!("{} is not a uf2", args.input)));
+    Ok(())
+}
+
+fn checksum(args: ChecksumArgs) -> anyhow::Result<()> {
+    let data = read_file(&args.input)?;
+    check_block_aligned(&args.input, data.len() as u64)?;
+    let blocks = read_blocks(data.as_slice()).context("Failed to read input file")?;
+    let groups = family_regions(&blocks);
+    let mut stdout = std::io::stdout().lock();
+    for (family, regions) in &groups {
+        let indent = if groups.len() > 1 {
+            writeln!(stdout, "{}:", format_family(*family))?;
+            "    "
+        } else {
+            ""
+        };
+        for region in regions {
+            writeln!(
+                stdout,
+                "{indent}0x{:08X} {:>10} bytes  {}",
+                region.addr,
+                region.data.len(),
+                args.algo.digest(&region.data)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Warns if a block is stored after a block of the same family at a higher address, a sign that
+/// whatever wrote the file did not write it in sequence
+fn warn_if_unordered(input: &str, blocks: &[UF2Block]) {
+    let mut last_addr: Vec<(Option<u32>, u32)> = Vec::new();
+    for block in blocks {
+        match last_addr
+            .iter_mut()
+            .find(|(family, _)| *family == block.family())
+        {
+            Some((_, addr)) if block.target_addr < *addr => {
+                warn!(
+                    "{input} is not in address order: block {} at 0x{:08X} is stored after a \
+                    block at 0x{addr:08X}",
+                    block.block_no, block.target_addr
+                );
+                return;
+            }
+            Some((_, addr)) => *addr = block.target_addr,
+            None => last_addr.push((block.family(), block.target_addr)),
+        }
+    }
+}
+
+/// Splits `blocks` by family, in order of each family's first appearance
+fn group_by_family(blocks: &[UF2Block]) -> Vec<(Option<u32>, Vec<UF2Block>)> {
+    let mut groups: Vec<(Option<u32>, Vec<UF2Block>)> = Vec::new();
+    for block in blocks {
+        match groups
+            .iter_mut()
+            .find(|(family, _)| *family == block.family())
+        {
+            Some((_, group)) => group.push(block.clone()),
+            None => groups.push((block.family(), vec![block.clone()])),
+        }
+    }
+    groups
+}
+
+fn format_size(bytes: u64) -> String {
+    const KIB: u64 = 1024;
+    const MIB: u64 = 1024 * KIB;
+    let (unit, name) = if bytes < KIB {
+        return format!("{bytes} bytes");
+    } else if bytes < MIB {
+        (KIB, "KiB")
+    } else {
+        (MIB, "MiB")
+    };
+    if bytes.is_multiple_of(unit) {
+        format!("{} {name}", bytes / unit)
+    } else {
+        format!("{:.1} {name}", bytes as f64 / unit as f64)
+    }
+}
+
+/// Lists each family with its block count, if the blocks belong to more than one family
+fn write_family_counts(blocks: &[UF2Block], w: &mut impl std::io::Write) -> std::io::Result<()> {
+    let groups = group_by_family(blocks);
+    if groups.len() < 2 {
+        return Ok(());
+    }
+    writeln!(w, "families:")?;
+    for (family, group) in groups {
+        writeln!(w, "    {}: {} blocks", format_family(family), group.len())?;
+    }
+    Ok(())
+}
+
+/// Prints how many contiguous regions the blocks of each family form, with the smallest region
+/// and the largest gap between two, as a measure of how fragmented the image is
+fn write_region_stats(blocks: &[UF2Block], w: &mut impl std::io::Write) -> std::io::Result<()> {
+    for (family, regions) in family_regions(blocks) {
+        write!(w, "regions ({}): {}", format_family(family), regions.len())?;
+        if let Some(smallest) = regions
+            .iter()
+            .min_by_key(|region| region.data.len())
+            .filter(|_| regions.len() > 1)
+        {
+            write!(
+                w,
+                ", the smallest {} at 0x{:08X}",
+                format_size(smallest.data.len() as u64),
+                smallest.addr
+            )?;
+        }
+        writeln!(w)?;
+        let gap = regions
+            .windows(2)
+            .map(|pair| pair[0].address_range().end..pair[1].addr)
+            .max_by_key(|gap| gap.len());
+        match gap {
+            Some(gap) => writeln!(
+                w,
+                "    largest gap: {} at 0x{:08X} - 0x{:08X}",
+                format_size(gap.len() as u64),
+                gap.start,
+                gap.end - 1
+            )?,
+            None => writeln!(w, "    largest gap: none")?,
+        }
+    }
+    Ok(())
+}
+
+/// Prints how many of `blocks` set each flag
+fn write_flag_counts(blocks: &[UF2Block], w: &mut impl std::io::Write) -> std::io::Result<()> {
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    for name in blocks.iter().flat_map(|block| flag_names(block.flags)) {
+        match counts.iter_mut().find(|(seen, _)| *seen == name) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((name, 1)),
+        }
+    }
+    if counts.is_empty() {
+        return writeln!(w, "flags: none");
+    }
+    writeln!(w, "flags:")?;
+    for (name, count) in counts {
+        writeln!(w, "    {name}: {count} of {} blocks", blocks.len())?;
+    }
+    Ok(())
+}
+
+/// Prints the contiguous regions covered by `blocks` and the gaps between them, per family
+fn write_memory_map(blocks: &[UF2Block], w: &mut impl std::io::Write) -> std::io::Result<()> {
+    for (family, regions) in family_regions(blocks) {
+        writeln!(w, "memory map ({}):", format_family(family))?;
+        let mut prev_end: Option<u32> = None;
+        for region in regions {
+            let range = region.address_range();
+            if let Some(prev_end) = prev_end {
+                writeln!(
+                    w,
+                    "    gap        0x{prev_end:08X} - 0x{:08X} ({})",
+                    range.start - 1,
+                    format_size(u64::from(range.start - prev_end))
+                )?;
+            }
+            writeln!(
+                w,
+                "    0x{:08X} - 0x{:08X} ({})",
+                range.start,
+                (region.end() - 1) as u32,
+                format_size(region.data.len() as u64)
+            )?;
+            prev_end = Some(range.end);
+        }
+    }
+    Ok(())
+}
+
+/// The flag bits defined by the UF2 spec, with their symbolic names and the names `info --json`
+/// gives them
+const FLAG_NAMES: [(u32, &str, &str); 5] = [
+    (
+        UF2Block::NOT_MAIN_FLASH_FLAG,
+        "NOT_MAIN_FLASH",
+        "not main flash",
+    ),
+    (
+        UF2Block::FILE_CONTAINER_FLAG,
+        "FILE_CONTAINER",
+        "file container",
+    ),
+    (UF2Block::FAMILY_FLAG, "FAMILY_ID_PRESENT", "family id"),
+    (UF2Block::MD5_FLAG, "MD5_PRESENT", "md5 checksum"),
+    (
+        UF2Block::EXTENSION_TAGS_FLAG,
+        "EXTENSION_TAGS",
+        "extension tags",
+    ),
+];
+
+/// Splits `flags` into the names of the bits it sets, e.g. `FAMILY_ID_PRESENT (0x2000)`, with any
+/// bits the spec does not define shown together as `unknown(0x...)`
+fn flag_names(flags: u32) -> Vec<String> {
+    let known = FLAG_NAMES.iter().fold(0, |known, (bit, ..)| known | bit);
+    let mut names = FLAG_NAMES
+        .iter()
+        .filter(|(bit, ..)| flags & bit != 0)
+        .map(|(bit, name, _)| format!("{name} ({bit:#X})"))
+        .collect::<Vec<_>>();
+    if flags & !known != 0 {
+        names.push(format!("unknown({:#X})", flags & !known));
+    }
+    names
+}
+
+fn format_flags(flags: u32) -> String {
+    if flags == 0 {
+        return "none".to_string();
+    }
+    flag_names(flags).join(", ")
+}
+
+fn info_json(blocks: &[UF2Block]) -> Json {
+    let mut families = blocks
+        .iter()
+        .filter_map(UF2Block::family)
+        .collect::<Vec<_>>();
+    families.sort();
+    families.dedup();
+    let families = families
+        .into_iter()
+        .map(|id| {
+            Json::object([
+                ("id", id.into()),
+                (
+                    "name",
+                    families::find_by_id(id)
+                        .map(|family| family.short_name)
+                        .into(),
+                ),
+                (
+                    "blocks",
+                    blocks
+                        .iter()
+                        .filter(|b| b.family() == Some(id))
+                        .count()
+                        .into(),
+                ),
+            ])
+        })
+        .collect();
+    let all_flags = blocks.iter().fold(0, |flags, block| flags | block.flags);
+    let flags = FLAG_NAMES
+        .iter()
+        .filter(|(bit, ..)| all_flags & bit != 0)
+        .map(|(.., name)| *name)
+        .collect();
+    let regions = coalesce(blocks)
+        .iter()
+        .map(|region| {
+            Json::object([
+                ("start", region.addr.into()),
+                ("end", region.end().into()),
+                ("bytes", region.data.len().into()),
+            ])
+        })
+        .collect();
+    let payload_bytes: u64 = blocks
+        .iter()
+        .map(|block| u64::from(block.payload_size))
+        .sum();
+    Json::object([
+        ("blocks", blocks.len().into()),
+        ("payload_bytes", payload_bytes.into()),
+        ("families", families),
+        ("flags", flags),
+        ("regions", regions),
+    ])
+}
+
+fn range_sub(mut range: Range<u32>, offset: u32) -> Range<u32> {
+    range.start -= offset;
+    range.end -= offset;
+    range
+}
+
+fn range_intersect(a: Range<u32>, b: Range<u32>) -> Range<u32> {
+    u32::max(a.start, b.start)..u32::min(a.end, b.end)
+}
+
+fn range_index(r: Range<u32>) -> Range<usize> {
+    r.start as usize..r.end as usize
+}
+
+fn extract(args: ExtractArgs) -> anyhow::Result<()> {
+    let mut blocks = read_uf2(&args.input)?;
+    warn_if_unordered(&args.input, &blocks);
+    if !args.ranges.is_empty() {
+        let count = blocks.len();
+        blocks = clip(&blocks, &args.ranges);
+        ensure!(
+            !blocks.is_empty(),
+            "None of the {count} blocks of {} hold data in the given --range",
+            args.input
+        );
+        debug!("Kept {} of {count} blocks in range", blocks.len());
+    }
+    blocks.sort_by_key(|block| block.block_no);
+    if args.sort {
+        blocks.sort_by_key(|block| block.target_addr);
+    }
+    if let Some(base) = args.image_base
+        && let Some(block) = blocks.iter().find(|block| block.target_addr < base)
+    {
+        bail!(
+            "Block {} at 0x{:08X} is below --image-base 0x{base:08X}",
+            block.block_no,
+            block.target_addr
+        );
+    }
+    let start_addr = args
+        .start_addr
+        .or(args.image_base)
+        .or_else(|| blocks.iter().map(|block| block.target_addr).min())
+        .unwrap_or(0);
+    let end_addr = args
+        .end_addr
+        .or_else(|| blocks.iter().map(|block| block.address_range().end).max())
+        .unwrap_or(start_addr);
+    ensure!(
+        start_addr <= end_addr,
+        "Start address 0x{start_addr:08X} is past end address 0x{end_addr:08X}"
+    );
+    let output_range = start_addr..end_addr;
+    ensure!(
+        args.output_format == OutputFormat::Bin || args.trim.is_none(),
+        UsageError("--trim can only be used with --output-format bin".into())
+    );
+    match args.output_format {
+        OutputFormat::Bin => {}
+        OutputFormat::Hex => {
+            let regions = clip_regions(coalesce(&blocks), output_range);
+            let mut output = create_output(&args.output, args.force)?;
+            let records = ihex::write_hex(&regions, 16, &mut output)
+                .and_then(|records| output.flush().map(|()| records))
+                .context("Failed to write to output file")?;
+            info!("{} written with {records} data record(s)", args.output);
+            return Ok(());
+        }
+        OutputFormat::Sparse => {
+            let regions = clip_regions(coalesce(&blocks), output_range);
+            let mut output = create_output(&args.output, args.force)?;
+            write_sparse(&regions, &mut output)
+                .and_then(|()| output.flush())
+                .context("Failed to write to output file")?;
+            info!("{} written with {} region(s)", args.output, regions.len());
+            return Ok(());
+        }
+        OutputFormat::Uf2 => return reencode(&args, &blocks, output_range),
+    }
+
+    // Contiguous blocks join into one region, so only the gaps between regions need filling
+    let regions = clip_regions(coalesce(&blocks), output_range.clone());
+    for pair in regions.windows(2) {
+        let gap = pair[0].address_range().end..pair[1].addr;
+        warn!(
+            "Filled a gap of {} bytes at 0x{:08X}..0x{:08X} with [{:#04X}]",
+            gap.len(),
+            gap.start,
+            gap.end,
+            args.fill
+        );
+    }
+    let mut pieces = Vec::new();
+    let mut cursor = start_addr;
+    for region in &regions {
+        pieces.push(Piece::Fill(region.addr - cursor));
+        pieces.push(Piece::Data(&region.data));
+        cursor = region.address_range().end;
+    }
+    pieces.push(Piece::Fill(end_addr - cursor));
+    if let Some(trim) = args.trim {
+        let trimmed = trim_pieces(&mut pieces, args.fill, trim);
+        info!("Trimmed {trimmed} trailing [{trim:#04X}] bytes");
+    }
+
+    let mut output = create_output(&args.output, args.force)?;
+    pieces
+        .iter()
+        .try_for_each(|piece| match *piece {
+            Piece::Data(data) => output.write_all(data),
+            Piece::Fill(len) => std::io::copy(
+                &mut std::io::repeat(args.fill).take(len.into()),
+                &mut output,
+            )
+            .map(|_| ()),
+        })
+        .and_then(|()| output.flush())
+        .context("Failed to write to output file")?;
+    Ok(())
+}
+
+/// Writes each of `regions` as a record of its address, its length and its data
+fn write_sparse(regions: &[Region], w: &mut impl Write) -> std::io::Result<()> {
+    for region in regions {
+        w.write_all(&region.addr.to_le_bytes())?;
+        w.write_all(&(region.data.len() as u32).to_le_bytes())?;
+        w.write_all(&region.data)?;
+    }
+    Ok(())
+}
+
+/// A run of extracted output: data from the uf2, or `len` fill bytes where it has none
+enum Piece<'a> {
+    Data(&'a [u8]),
+    Fill(u32),
+}
+
+/// Strips trailing `trim` bytes from the end of `pieces`, returning how many were removed
+fn trim_pieces(pieces: &mut Vec<Piece>, fill: u8, trim: u8) -> u64 {
+    let mut trimmed = 0;
+    while let Some(piece) = pieces.last_mut() {
+        match piece {
+            Piece::Fill(len) if fill == trim || *len == 0 => trimmed += u64::from(*len),
+            Piece::Fill(_) => break,
+            Piece::Data(data) => {
+                let kept = data
+                    .iter()
+                    .rposition(|&byte| byte != trim)
+                    .map_or(0, |last| last + 1);
+                trimmed += (data.len() - kept) as u64;
+                if kept > 0 {
+                    *data = &data[..kept];
+                    break;
+                }
+            }
+        }
+        pieces.pop();
+    }
+    trimmed
+}
+
+/// The parts of `regions` that lie within `range`
+fn clip_regions(regions: Vec<Region>, range: Range<u32>) -> Vec<Region> {
+    regions
+        .into_iter()
+        .filter_map(|region| {
+            let kept = range_intersect(region.address_range(), range.clone());
+            if kept.is_empty() {
+                return None;
+            }
+            let data = region.data[range_index(range_sub(kept.clone(), region.addr))].to_vec();
+            Some(Region {
+                addr: kept.start,
+                data,
+            })
+        })
+        .collect()
+}
+
+/// Writes the data of `blocks` within `range` as a fresh uf2, numbered from zero and without
+/// overlapping or duplicate blocks.
+///
+/// Blocks keep their size where the data allows it: the page size is the largest that divides
+/// every input payload and every region, so nothing needs padding. Blocks are checksummed or
+/// marked not main flash if every input block was.
+fn reencode(args: &ExtractArgs, blocks: &[UF2Block], range: Range<u32>) -> anyhow::Result<()> {
+    let groups = family_regions(blocks)
+        .into_iter()
+        .map(|(family, regions)| (family, clip_regions(regions, range.clone())))
+        .collect::<Vec<_>>();
+    let regions = groups
+        .iter()
+        .flat_map(|(family, regions)| regions.iter().map(|region| (*family, region)))
+        .collect::<Vec<_>>();
+    let gcd = |mut a: u32, mut b: u32| {
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        a
+    };
+    let page_size = blocks
+        .iter()
+        .map(|block| block.payload_size)
+        .chain(regions.iter().map(|(_, region)| region.data.len() as u32))
+        .fold(0, gcd)
+        .max(1);
+    let all = |flag| blocks.iter().all(|block| block.flags & flag != 0);
+    let options = Uf2Options {
+        page_size,
+        md5: all(UF2Block::MD5_FLAG),
+        not_main_flash: all(UF2Block::NOT_MAIN_FLASH_FLAG),
+        ..Uf2Options::default()
+    };
+    let mut output = create_output(&args.output, args.force)?;
+    let summary = encode_family_regions(&regions, &mut output, &options, log_block)?;
+    output.flush().context("Failed to write to output file")?;
+    info!(
+        "{} written with {} block(s), re-encoded from {}",
+        args.output,
+        summary.num_blocks,
+        blocks.len()
+    );
+    Ok(())
+}
+
+fn to_hex(args: ToHexArgs) -> anyhow::Result<()> {
+    let input = open_input(&args.input).context("Failed to open input file")?;
+    let blocks = read_blocks(input).context("Failed to read input file")?;
+    let mut output = create_output(&args.output, args.force)?;
+    let records = ihex::write_hex(&coalesce(&blocks), args.record_size, &mut output)
+        .and_then(|records| output.flush().map(|()| records))
+        .context("Failed to write to output file")?;
+    info!("{} written with {records} data record(s)", args.output);
+    Ok(())
+}
+
+fn verify(args: VerifyArgs) -> anyhow::Result<()> {
+    ensure!(
+        args.uf2 != STDIO_PATH || args.bin != STDIO_PATH,
+        UsageError("Only one of --uf2 and --bin can be read from stdin".into())
+    );
+    let blocks = read_blocks(open_input(&args.uf2).context("Failed to open uf2 file")?)
+        .with_context(|| format!("Failed to read {}", args.uf2))?;
+    let mut bin = Vec::new();
+    open_input(&args.bin)
+        .context("Failed to open binary file")?
+        .read_to_end(&mut bin)
+        .context("Failed to read binary file")?;
+    if let Some(base) = args.dump_base {
+        let families = group_by_family(&blocks).len();
+        ensure!(
+            families <= 1,
+            UsageError(format!(
+                "{} holds blocks for {families} families, but a dump is of one address space",
+                args.uf2
+            ))
+        );
+        let compared =
+            check_dump(&args, &blocks, &bin, base).map_err(|err| InvalidError(err.to_string()))?;
+        check_image_checksum(&blocks).map_err(|err| InvalidError(err.to_string()))?;
+        println!(
+            "{} matches {} at 0x{base:08X} ({} blocks, {compared} bytes compared)",
+            args.uf2,
+            args.bin,
+            blocks.len()
+        );
+        return Ok(());
+    }
+    let padding =
+        check_matches(&args, &blocks, &bin).map_err(|err| InvalidError(err.to_string()))?;
+    check_image_checksum(&blocks).map_err(|err| InvalidError(err.to_string()))?;
+    let padded = if padding > 0 {
+        format!(", padded with {padding} bytes of 0x{:02X}", args.fill)
+    } else {
+        String::new()
+    };
+    println!(
+        "{} matches {} ({} blocks, {} bytes{padded})",
+        args.uf2,
+        args.bin,
+        blocks.len(),
+        bin.len()
+    );
+    Ok(())
+}
+
+/// Checks the image checksum stored by generate --image-checksum in the last block, if it has one,
+/// against the payloads of `blocks` in block order
+fn check_image_checksum(blocks: &[UF2Block]) -> anyhow::Result<()> {
+    let Some(last) = blocks.iter().max_by_key(|block| block.block_no) else {
+        return Ok(());
+    };
+    let tags = tags::parse(last)?;
+    let Some((checksum, stored)) = tags
+        .iter()
+        .find_map(|tag| ImageChecksum::of_tag(tag).map(|checksum| (checksum, tag)))
+    else {
+        return Ok(());
+    };
+    let mut ordered = blocks.iter().collect::<Vec<_>>();
+    ordered.sort_by_key(|block| block.block_no);
+    let mut digest = ImageDigest::new(checksum);
+    for block in ordered {
+        digest.update(block.payload());
+    }
+    let computed = digest.finish();
+    ensure!(
+        computed.data == stored.data,
+        "Block {} stores an image {} of {}, but the payloads have {}",
+        last.block_no,
+        checksum.name(),
+        format_image_checksum(checksum, &stored.data),
+        format_image_checksum(checksum, &computed.data)
+    );
+    println!(
+        "image {}: {} matches",
+        checksum.name(),
+        format_image_checksum(checksum, &stored.data)
+    );
+    Ok(())
+}
+
+/// The data of an image checksum tag in hex, with a CRC-32 as a number like `checksum` prints it
+fn format_image_checksum(checksum: ImageChecksum, data: &[u8]) -> String {
+    match checksum {
+        ImageChecksum::Crc32 => format!("{:08x}", u32::from_le_bytes(data.try_into().unwrap())),
+        ImageChecksum::Sha256 => hex_digest(data),
+    }
+}
+
+/// Checks that `blocks` hold exactly `bin`, from their lowest address on, followed by nothing but
+/// the fill byte, and returns how many bytes of padding follow `bin`
+fn check_matches(args: &VerifyArgs, blocks: &[UF2Block], bin: &[u8]) -> anyhow::Result<usize> {
+    for block in blocks {
+        ensure!(
+            block.num_blocks as usize == blocks.len(),
+            "Block {} claims {} blocks in total, but {} contains {} blocks",
+            block.block_no,
+            block.num_blocks,
+            args.uf2,
+            blocks.len()
+        );
+    }
+    let mut families = blocks
+        .iter()
+        .filter_map(UF2Block::family)
+        .collect::<Vec<_>>();
+    families.sort();
+    families.dedup();
+    for family in families {
+        println!("family: {}", format_family(Some(family)));
+    }
+    if let Some(mismatch) = check_md5(blocks).first() {
+        anyhow::bail!(
+            "Block {}: {}",
+            blocks[mismatch.block].block_no,
+            format_md5_mismatch(mismatch)
+        );
+    }
+    if args.block_crc {
+        for block in blocks {
+            ensure!(
+                block.payload_size as usize <= MAX_PAYLOAD_SIZE - UF2Block::CRC_TRAILER_SIZE,
+                "Block {}: payload of {} bytes leaves no room for a CRC",
+                block.block_no,
+                block.payload_size
+            );
+            let crc = crc32(block.payload());
+            ensure!(
+                crc == block.block_crc(),
+                "Block {}: CRC-32 of the payload is {crc:08x}, but the block stores {:08x}",
+                block.block_no,
+                block.block_crc()
+            );
+        }
+    }
+
+    let mut sorted = blocks.iter().collect::<Vec<_>>();
+    sorted.sort_by_key(|block| block.target_addr);
+    let base = sorted.first().map_or(0, |block| block.target_addr);
+    let mut covered = 0;
+    for block in sorted {
+        let offset = (block.target_addr - base) as usize;
+        ensure!(
+            offset <= covered,
+            "{} has no data for offset {covered:#X} (address 0x{:08X})",
+            args.uf2,
+            base as usize + covered
+        );
+        let payload = block.payload();
+        let expected = bin.get(offset..).unwrap_or_default();
+        if let Some(i) = payload
+            .iter()
+            .zip(expected)
+            .position(|(actual, expected)| actual != expected)
+        {
+            anyhow::bail!(
+                "Mismatch at offset {:#X} (address 0x{:08X})",
+                offset + i,
+                block.target_addr as usize + i
+            );
+        }
+        let past_end = bin.len().saturating_sub(offset).min(payload.len());
+        if let Some(i) = payload[past_end..]
+            .iter()
+            .position(|&byte| byte != args.fill)
+        {
+            anyhow::bail!(
+                "{} extends {} bytes past the end of {}, and they are not all the fill byte \
+                0x{:02X} (offset {:#X} is 0x{:02X})",
+                args.uf2,
+                offset + payload.len() - bin.len(),
+                args.bin,
+                args.fill,
+                offset + past_end + i,
+                payload[past_end + i]
+            );
+        }
+        covered = covered.max(offset + payload.len());
+    }
+    ensure!(
+        covered >= bin.len(),
+        "{} ends at offset {covered:#X}, but {} is {} bytes long",
+        args.uf2,
+        args.bin,
+        bin.len()
+    );
+    // The field only holds a size in blocks without a family id, and zero means it is not known.
+    // generate --pad records the padded size
+    if let Some(block) = blocks.iter().find(|block| {
+        block
+            .file_size()
+            .is_some_and(|size| size != 0 && size as usize != bin.len() && size as usize != covered)
+    }) {
+        warn!(
+            "Block {} records a file size of {} bytes, but {} holds {} bytes",
+            block.block_no,
+            block.file_size,
+            args.bin,
+            bin.len()
+        );
+    }
+    Ok(covered - bin.len())
+}
+
+/// Mismatched ranges `verify --dump-base` lists before summarizing the rest
+const MAX_LISTED_MISMATCHES: usize = 20;
+
+/// Checks that `dump`, read from a device's memory from `base` on, holds what `blocks` write
+/// wherever they write, and returns the number of bytes compared
+///
+/// Addresses no block writes are not compared, as flash the uf2 leaves alone may hold anything.
+fn check_dump(
+    args: &VerifyArgs,
+    blocks: &[UF2Block],
+    dump: &[u8],
+    base: u32,
+) -> anyhow::Result<u64> {
+    let dump_end = u64::from(base) + dump.len() as u64;
+    let mut mismatches: Vec<Range<u64>> = Vec::new();
+    let mut compared = 0;
+    for region in coalesce(blocks) {
+        if region.data.is_empty() {
+            continue;
+        }
+        let start = u64::from(region.addr);
+        let end = start + region.data.len() as u64;
+        ensure!(
+            start >= u64::from(base) && end <= dump_end,
+            "{} writes 0x{start:08X}..0x{end:08X}, outside the dump's 0x{base:08X}..0x{dump_end:08X}",
+            args.uf2
+        );
+        let offset = (start - u64::from(base)) as usize;
+        let actual = &dump[offset..offset + region.data.len()];
+        for (i, _) in actual
+            .iter()
+            .zip(&region.data)
+            .enumerate()
+            .filter(|(_, (actual, expected))| actual != expected)
+        {
+            let addr = start + i as u64;
+            match mismatches.last_mut() {
+                Some(range) if range.end == addr => range.end += 1,
+                _ => mismatches.push(addr..addr + 1),
+            }
+        }
+        compared += region.data.len() as u64;
+    }
+    if mismatches.is_empty() {
+        return Ok(compared);
+    }
+    for range in mismatches.iter().take(MAX_LISTED_MISMATCHES) {
+        println!(
+            "mismatch: 0x{:08X}..0x{:08X} ({} bytes)",
+            range.start,
+            range.end,
+            range.end - range.start
+        );
+    }
+    if mismatches.len() > MAX_LISTED_MISMATCHES {
+        println!(
+            "… and {} more range(s)",
+            mismatches.len() - MAX_LISTED_MISMATCHES
+        );
+    }
+    let differing: u64 = mismatches.iter().map(|range| range.end - range.start).sum();
+    bail!(
+        "{differing} of {compared} bytes differ from {}, in {} range(s)",
+        args.bin,
+        mismatches.len()
+    )
+}
+
+fn validate(args: ValidateArgs) -> anyhow::Result<()> {
+    if args.magic_only {
+        return validate_magic(&args);
+    }
+    let input = open_input(&args.input)
+        .with_context(|| format!("Failed to open input file {}", args.input))?;
+    let mut reader = Uf2Reader::new(input);
+    let mut problems = Vec::new();
+    let mut trailing = None;
+    let mut blocks = Vec::new();
+    loop {
+        let offset = reader.position() as usize;
+        let Some(block) = reader.next() else {
+            break;
+        };
+        match block {
+            Ok(block) => blocks.push((offset, block)),
+            Err(Uf2Error::TruncatedBlock { len, .. }) => {
+                trailing = Some(format!(
+                    "offset {offset:#X}: trailing {len} bytes do not form a whole {CHUNK_SIZE} byte block"
+                ));
+            }
+            Err(Uf2Error::Io(err)) => {
+                return Err(err).with_context(|| format!("Failed to read {}", args.input));
+            }
+            Err(err) => problems.push(format!("offset {offset:#X}: {err}")),
+        }
+    }
+    let total = reader.position() as usize / CHUNK_SIZE;
+    if total == 0 && trailing.is_none() {
+        problems.push("file contains no blocks".to_string());
+    }
+    let num_blocks = blocks.first().map(|(_, block)| block.num_blocks);
+    let with_family = blocks
+        .iter()
+        .filter(|(_, block)| block.flags & UF2Block::FAMILY_FLAG != 0)
+        .count();
+    for (offset, block) in &blocks {
+        let mut problem = |message: String| problems.push(format!("offset {offset:#X}: {message}"));
+        if block.block_no >= block.num_blocks {
+            problem(format!(
+                "block number {} is out of range for {} blocks",
+                block.block_no, block.num_blocks
+            ));
+        }
+        if Some(block.num_blocks) != num_blocks {
+            problem(format!(
+                "claims {} blocks in total, but the first block claims {}",
+                block.num_blocks,
+                num_blocks.unwrap_or_default()
+            ));
+        } else if block.num_blocks as usize != total {
+            problem(format!(
+                "claims {} blocks in total, but the file contains {total}",
+                block.num_blocks
+            ));
+        }
+        if block.flags & UF2Block::MD5_FLAG != 0
+            && block.payload_size as usize > MAX_PAYLOAD_SIZE - UF2Block::MD5_TRAILER_SIZE
+        {
+            problem(format!(
+                "payload of {} bytes overlaps the md5 checksum",
+                block.payload_size
+            ));
+        }
+        if block.flags & UF2Block::FAMILY_FLAG != 0 {
+            if block.flags & UF2Block::FILE_CONTAINER_FLAG != 0 {
+                problem("sets both the family id and file container flags".to_string());
+            }
+            if block.file_size == 0 {
+                problem("sets the family id flag with a family id of 0".to_string());
+            }
+        } else if with_family > 0 {
+            problem(format!(
+                "has no family id, but {with_family} of {} blocks do",
+                blocks.len()
+            ));
+        }
+    }
+
+    problems.extend(block_number_problems(
+        &blocks,
+        num_blocks.unwrap_or_default(),
+    ));
+    problems.extend(trailing);
+
+    let parsed = blocks
+        .iter()
+        .map(|(_, block)| block.clone())
+        .collect::<Vec<_>>();
+    for mismatch in check_md5(&parsed) {
+        let (offset, _) = blocks[mismatch.block];
+        problems.push(format!(
+            "offset {offset:#X}: {}",
+            format_md5_mismatch(&mismatch)
+        ));
+    }
+
+    if problems.is_empty() {
+        println!("{} is a valid uf2 ({total} blocks)", args.input);
+    }
+    for problem in &problems {
+        println!("{problem}");
+    }
+    let mut stdout = std::io::stdout().lock();
+    write_family_counts(&parsed, &mut stdout)
+        .and_then(|()| write_region_stats(&parsed, &mut stdout))
+        .context("Failed to write to stdout")?;
+    if problems.is_empty() {
+        return Ok(());
+    }
+    Err(InvalidError(format!("{} has {} problem(s)", args.input, problems.len())).into())
+}
+
+/// Checks only the magic numbers of every block and that the file ends on a block boundary
+fn validate_magic(args: &ValidateArgs) -> anyhow::Result<()> {
+    let mut input = open_input(&args.input)
+        .with_context(|| format!("Failed to open input file {}", args.input))?;
+    let mut chunk = [0; CHUNK_SIZE];
+    let mut total = 0;
+    let mut problems = 0;
+    loop {
+        let offset = total * CHUNK_SIZE;
+        match read_chunk(&mut input, &mut chunk) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                println!("offset {offset:#X}: {err}");
+                problems += 1;
+                break;
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}", args.input));
+            }
+        }
+        if let Err(err) = UF2Block::check_magic(&chunk) {
+            println!("offset {offset:#X}: {}", err.at_offset(offset as u64));
+            problems += 1;
+        }
+        total += 1;
+    }
+    if total == 0 && problems == 0 {
+        println!("file contains no blocks");
+        problems += 1;
+    }
+    if problems == 0 {
+        println!("{} has valid magic numbers ({total} blocks)", args.input);
+        return Ok(());
+    }
+    Err(InvalidError(format!("{} has {problems} problem(s)", args.input)).into())
+}
+
+fn join(args: JoinArgs) -> anyhow::Result<()> {
+    let text = String::from_utf8(read_file(&args.manifest)?)
+        .with_context(|| format!("{} is not valid text", args.manifest))?;
+    let manifest = manifest::parse(&text)
+        .and_then(|manifest| {
+            manifest.defaults.check_keys(&["family", "symbols"])?;
+            for table in &manifest.regions {
+                table.check_keys(&["file", "address", "family"])?;
+            }
+            Ok(manifest)
+        })
+        .with_context(|| format!("Failed to parse {}", args.manifest))?;
+    ensure!(
+        !manifest.regions.is_empty(),
+        "{} does not list any [[region]] tables",
+        args.manifest
+    );
+    let default_family = manifest_family(&manifest.defaults)?;
+    let dir = std::path::Path::new(&args.manifest)
+        .parent()
+        .unwrap_or(std::path::Path::new(""));
+    let symbols = match (&args.symbols, manifest.defaults.string("symbols")?) {
+        (Some(path), _) => Some(read_symbols(path)?),
+        (None, Some(path)) => Some(read_symbols(&dir.join(path).to_string_lossy())?),
+        (None, None) => None,
+    };
+    let mut regions = Vec::new();
+    for (i, table) in manifest.regions.iter().enumerate() {
+        let file = table
+            .string("file")?
+            .with_context(|| format!("Region {i} in {} has no file", args.manifest))?;
+        let addr = match table.get("address") {
+            None => bail!("Region {i} ({file}) has no address"),
+            Some((manifest::Value::Integer(addr), _)) => u32::try_from(*addr)
+                .with_context(|| format!("Address {addr:#X} of {file} does not fit in 32 bits"))?,
+            Some((manifest::Value::String(name), line)) => symbols
+                .as_ref()
+                .with_context(|| {
+                    format!(
+                        "Line {line}: the address of {file} is the symbol {name}, but no symbols \
+                        file was given; pass --symbols or set symbols in the manifest"
+                    )
+                })?
+                .resolve(name)
+                .with_context(|| format!("Line {line}: cannot place {file}"))?,
+        };
+        let family = manifest_family(table)?.or(default_family);
+        let path = dir.join(file);
+        let data = read_file(&path.to_string_lossy())?;
+        debug!(
+            "{file}: 0x{addr:08X} ({} bytes, {})",
+            data.len(),
+            format_family(family)
+        );
+        regions.push((family, Region { addr, data }));
+    }
+    for (family, group) in group_regions_by_family(&regions) {
+        check_region_overlaps(&group, args.allow_overlap)
+            .with_context(|| format!("Regions of {} overlap", format_family(family)))?;
+    }
+
+    let options = Uf2Options::default();
+    let regions = regions
+        .iter()
+        .map(|(family, region)| (*family, region))
+        .collect::<Vec<_>>();
+    let mut output = create_output(&args.output, args.force)?;
+    let summary = encode_family_regions(&regions, &mut output, &options, log_block)?;
+    output.flush().context("Failed to write to output file")?;
+    info!(
+        "{} written with {} block(s) from {} region(s)",
+        args.output,
+        summary.num_blocks,
+        regions.len()
+    );
+    Ok(())
+}
+
+fn read_symbols(path: &str) -> anyhow::Result<Symbols> {
+    let text = read_file(path)?;
+    let symbols = Symbols::parse(path, &String::from_utf8_lossy(&text));
+    debug!("Read {} symbols from {path}", symbols.len());
+    Ok(symbols)
+}
+
+/// The `family` key of a manifest table, given as a family name or a numeric id
+fn manifest_family(table: &manifest::Table) -> anyhow::Result<Option<u32>> {
+    match table.get("family") {
+        None => Ok(None),
+        Some((manifest::Value::String(name), line)) => parse_family(name)
+            .map(Some)
+            .with_context(|| format!("Line {line}")),
+        Some((manifest::Value::Integer(id), line)) => u32::try_from(*id)
+            .map(Some)
+            .with_context(|| format!("Line {line}: family id {id:#X} does not fit in 32 bits")),
+    }
+}
+
+/// Splits tagged regions by family, in order of each family's first appearance
+fn group_regions_by_family(regions: &[(Option<u32>, Region)]) -> Vec<(Option<u32>, Vec<Region>)> {
+    let mut groups: Vec<(Option<u32>, Vec<Region>)> = Vec::new();
+    for (family, region) in regions {
+        match groups.iter_mut().find(|(other, _)| other == family) {
+            Some((_, group)) => group.push(region.clone()),
+            None => groups.push((*family, vec![region.clone()])),
+        }
+    }
+    groups
+}
+
+fn relocate(args: RelocateArgs) -> anyhow::Result<()> {
+    let mut blocks = read_blocks(open_input(&args.input).context("Failed to open input file")?)
+        .with_context(|| format!("Failed to read {}", args.input))?;
+    let lowest = blocks.iter().map(|block| block.target_addr).min();
+    if let Some(base) = args.new_base {
+        rebase(&mut blocks, base)?;
+    } else if let Some(delta) = args.delta {
+        clif::relocate(&mut blocks, delta)?;
+    }
+    let mut output = create_output(&args.output, args.force)?;
+    write_blocks(&blocks, &mut output)
+        .and_then(|()| output.flush())
+        .context("Failed to write to output file")?;
+    if let Some(lowest) = lowest {
+        let new_lowest = blocks.iter().map(|block| block.target_addr).min().unwrap();
+        info!(
+            "Moved {} block(s) from 0x{lowest:08X} to 0x{new_lowest:08X}",
+            blocks.len()
+        );
+    }
+    Ok(())
+}
+
+fn repack(args: RepackArgs, dry_run: bool) -> anyhow::Result<()> {
+    let output = args.output.as_deref().unwrap_or(&args.input);
+    ensure!(
+        output != STDIO_PATH || args.output.is_some(),
+        UsageError("Cannot replace stdin; pass --output".into())
+    );
+    let mut blocks = read_blocks(open_input(&args.input).context("Failed to open input file")?)
+        .with_context(|| format!("Failed to read {}", args.input))?;
+    if args.no_family {
+        strip_families(&mut blocks);
+    }
+    if args.strip_extensions {
+        strip_extensions(&mut blocks);
+    }
+    let key = |block: &UF2Block| (block.target_addr, block.block_no, block.num_blocks);
+    let unsorted = blocks.iter().map(key).collect::<Vec<_>>();
+    sort_by_family(&mut blocks);
+    let before = blocks.iter().map(key).collect::<Vec<_>>();
+    let moved = unsorted
+        .iter()
+        .zip(&before)
+        .filter(|(old, new)| old != new)
+        .count();
+    renumber(&mut blocks);
+    let renumbered = before
+        .iter()
+        .zip(&blocks)
+        .filter(|((_, block_no, _), block)| *block_no != block.block_no)
+        .count();
+    let recounted = before
+        .iter()
+        .zip(&blocks)
+        .filter(|((_, _, num_blocks), block)| *num_blocks != block.num_blocks)
+        .count();
+    if dry_run {
+        return print_plan(output, &blocks);
+    }
+    // Replacing the input is what was asked for, so it needs no --force
+    let mut writer = create_output(output, args.force || args.output.is_none())?;
+    write_blocks(&blocks, &mut writer)
+        .and_then(|()| writer.flush())
+        .context("Failed to write to output file")?;
+    info!(
+        "{output} written with {} block(s): moved {moved}, and changed the block number of \
+        {renumbered} and the block count of {recounted}",
+        blocks.len()
+    );
+    Ok(())
+}
+
+/// Clears the family flag of every block, setting its file size to the total payload of `blocks`
+fn strip_families(blocks: &mut [UF2Block]) {
+    let families = group_by_family(blocks)
+        .into_iter()
+        .filter_map(|(family, _)| family)
+        .collect::<Vec<_>>();
+    match families.as_slice() {
+        [] => info!("No block has a family to strip"),
+        [family] => info!("Stripping family {}", format_family(Some(*family))),
+        _ => warn!(
+            "Stripping {} families, whose blocks now share one address space: {}",
+            families.len(),
+            families
+                .iter()
+                .map(|&family| format_family(Some(family)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+    let total = blocks
+        .iter()
+        .map(|block| u64::from(block.payload_size))
+        .sum::<u64>()
+        .try_into()
+        .unwrap_or(u32::MAX);
+    for block in blocks {
+        block.flags &= !UF2Block::FAMILY_FLAG;
+        block.file_size = total;
+    }
+}
+
+/// Clears the md5 checksum and extension tags flags of every block, along with the checksums and
+/// tags they mark; payloads are left as they are, as neither is part of one
+fn strip_extensions(blocks: &mut [UF2Block]) {
+    const FLAGS: u32 = UF2Block::MD5_FLAG | UF2Block::EXTENSION_TAGS_FLAG;
+    let mut stripped = 0;
+    for block in blocks.iter_mut().filter(|block| block.flags & FLAGS != 0) {
+        block.flags &= !FLAGS;
+        block.data[block.payload_size as usize..].fill(0);
+        stripped += 1;
+    }
+    info!("Stripped md5 checksums and extension tags from {stripped} block(s)");
+}
+
+fn split(args: SplitArgs) -> anyhow::Result<()> {
+    ensure!(
+        args.output != STDIO_PATH,
+        UsageError("split writes several files, so it cannot write to stdout".into())
+    );
+    let mut blocks = read_blocks(open_input(&args.input).context("Failed to open input file")?)
+        .with_context(|| format!("Failed to read {}", args.input))?;
+    let stem = args.output.strip_suffix(".uf2").unwrap_or(&args.output);
+    let mut parts = 0;
+    for (i, part) in blocks.chunks_mut(args.max_blocks as usize).enumerate() {
+        renumber(part);
+        let path = format!("{stem}.{i:03}.uf2");
+        let mut output = create_output(&path, args.force)?;
+        write_blocks(&*part, &mut output)
+            .and_then(|()| output.flush())
+            .with_context(|| format!("Failed to write to {path}"))?;
+        info!("{path} written with {} block(s)", part.len());
+        parts += 1;
+    }
+    println!("Split {} into {parts} part(s)", args.input);
+    Ok(())
+}
+
+fn split_by_family(args: SplitByFamilyArgs) -> anyhow::Result<()> {
+    ensure!(
+        args.output != STDIO_PATH,
+        UsageError("split-by-family writes several files, so it cannot write to stdout".into())
+    );
+    let blocks = read_blocks(open_input(&args.input).context("Failed to open input file")?)
+        .with_context(|| format!("Failed to read {}", args.input))?;
+    let stem = args.output.strip_suffix(".uf2").unwrap_or(&args.output);
+    let groups = group_by_family(&blocks);
+    let count = groups.len();
+    for (family, mut part) in groups {
+        renumber(&mut part);
+        let path = format!("{stem}.{}.uf2", family_file_name(family));
+        let mut output = create_output(&path, args.force)?;
+        write_blocks(&part, &mut output)
+            .and_then(|()| output.flush())
+            .with_context(|| format!("Failed to write to {path}"))?;
+        info!(
+            "{path} written with {} block(s) of {}",
+            part.len(),
+            format_family(family)
+        );
+    }
+    println!("Split {} into {count} file(s) by family", args.input);
+    Ok(())
+}
+
+/// The name of `family` as it appears in file names, with anything but letters, digits, `-` and
+/// `_` replaced so that a custom family's name cannot reach another directory
+fn family_file_name(family: Option<u32>) -> String {
+    let Some(id) = family else {
+        return "no-family".to_string();
+    };
+    match families::find_by_id(id) {
+        Some(family) => family
+            .short_name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || matches!(c, '-' | '_') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect(),
+        None => format!("0x{id:08X}"),
+    }
+}
+
+fn pack(args: PackArgs, dry_run: bool) -> anyhow::Result<()> {
+    let mut files = Vec::new();
+    for spec in &args.files {
+        let (name, path) = match spec.split_once('=') {
+            Some((name, path)) => (name.to_string(), path),
+            None => {
+                let name = std::path::Path::new(spec)
+                    .file_name()
+                    .with_context(|| format!("{spec} has no file name; pass it as NAME={spec}"))?;
+                (name.to_string_lossy().into_owned(), spec.as_str())
+            }
+        };
+        ensure!(
+            files.iter().all(|file: &ContainedFile| file.name != name),
+            "{name} is given more than once"
+        );
+        let data = read_file(path)?;
+        debug!("{name}: {path} ({} bytes)", data.len());
+        files.push(ContainedFile { name, data });
+    }
+    let blocks = container::pack(&files).context("Failed to pack files")?;
+    if dry_run {
+        return print_plan(&args.output, &blocks);
+    }
+    let mut output = create_output(&args.output, args.force)?;
+    write_blocks(&blocks, &mut output)
+        .and_then(|()| output.flush())
+        .context("Failed to write to output file")?;
+    info!(
+        "{} written with {} file(s) in {} block(s)",
+        args.output,
+        files.len(),
+        blocks.len()
+    );
+    Ok(())
+}
+
+fn unpack(args: UnpackArgs) -> anyhow::Result<()> {
+    let blocks = read_blocks(open_input(&args.input).context("Failed to open input file")?)
+        .with_context(|| format!("Failed to read {}", args.input))?;
+    let skipped = blocks
+        .iter()
+        .filter(|block| block.flags & UF2Block::FILE_CONTAINER_FLAG == 0)
+        .count();
+    if skipped == blocks.len() {
+        bail!(
+            "{} is not a file container: no block sets the file container flag",
+            args.input
+        );
+    }
+    if skipped > 0 {
+        warn!("Skipped {skipped} block(s) that are not part of the file container");
+    }
+    let files =
+        container::unpack(&blocks).with_context(|| format!("Failed to unpack {}", args.input))?;
+    let dir = std::path::Path::new(&args.output);
+    for file in &files {
+        // A name must not reach outside the output directory
+        let name = std::path::Path::new(&file.name);
+        ensure!(
+            name.components()
+                .all(|part| matches!(part, std::path::Component::Normal(_))),
+            "Refusing to write {:?}, which is not a relative path inside the output directory",
+            file.name
+        );
+        let path = dir.join(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let mut output = create_output(&path.to_string_lossy(), args.force)?;
+        output
+            .write_all(&file.data)
+            .and_then(|()| output.flush())
+            .with_context(|| format!("Failed to write to {}", path.display()))?;
+        info!("{} written with {} bytes", path.display(), file.data.len());
+    }
+    println!("Unpacked {} file(s) from {}", files.len(), args.input);
+    Ok(())
+}
+
+fn concat_bin(args: ConcatBinArgs, dry_run: bool) -> anyhow::Result<()> {
+    let symbols = args.symbols.as_deref().map(read_symbols).transpose()?;
+    let mut paths = Vec::new();
+    let mut regions = Vec::new();
+    for input in &args.inputs {
+        let (path, addr) = match (&symbols, input.rsplit_once('@')) {
+            (Some(symbols), Some((path, addr))) => {
+                let addr = symbols
+                    .address(addr)
+                    .with_context(|| format!("Invalid address {addr} for input {path}"))?;
+                (path, Some(addr))
+            }
+            _ => split_input_addr(input)?,
+        };
+        let addr = addr
+            .ok_or_else(|| UsageError(format!("Input {path} needs an address (PATH@ADDRESS)")))?;
+        let data = read_input(path)?;
+        ensure!(
+            u64::from(addr) + data.len() as u64 <= 1 << 32,
+            "{path} does not fit in the 32-bit address space at 0x{addr:08X}"
+        );
+        debug!("{path}: 0x{addr:08X} ({} bytes)", data.len());
+        paths.push(path);
+        regions.push(Region { addr, data });
+    }
+    check_region_overlaps(&regions, args.allow_overlap)?;
+    let region_end = |region: &Region| u64::from(region.addr) + region.data.len() as u64;
+    let start = regions.iter().map(|region| region.addr).min().unwrap_or(0);
+    let end = regions.iter().map(region_end).max().unwrap_or(0);
+    let len = end - u64::from(start);
+    if dry_run {
+        let mut order = (0..regions.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| regions[i].addr);
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "dry run: would write {}", args.output)
+            .and_then(|()| writeln!(stdout, "size: {len} bytes ({})", format_size(len)))
+            .and_then(|()| writeln!(stdout, "layout:"))
+            .context("Failed to write to stdout")?;
+        let mut prev_end: Option<u64> = None;
+        for i in order.into_iter().filter(|&i| !regions[i].data.is_empty()) {
+            let region = &regions[i];
+            let addr = u64::from(region.addr);
+            if let Some(prev_end) = prev_end.filter(|&prev_end| prev_end < addr) {
+                writeln!(
+                    stdout,
+                    "    gap        0x{prev_end:08X} - 0x{:08X} ({})",
+                    addr - 1,
+                    format_size(addr - prev_end)
+                )
+                .context("Failed to write to stdout")?;
+            }
+            writeln!(
+                stdout,
+                "    0x{addr:08X} - 0x{:08X} ({}) {}",
+                region_end(region) - 1,
+                format_size(region.data.len() as u64),
+                paths[i]
+            )
+            .context("Failed to write to stdout")?;
+            prev_end = Some(prev_end.unwrap_or(0).max(region_end(region)));
+        }
+        return Ok(());
+    }
+
+    let mut image = vec![args.fill; len as usize];
+    // In input order, so that where overlaps are allowed the later input wins
+    for region in &regions {
+        let offset = (region.addr - start) as usize;
+        image[offset..offset + region.data.len()].copy_from_slice(&region.data);
+    }
+    let mut ranges = regions
+        .iter()
+        .map(|region| u64::from(region.addr)..region_end(region))
+        .collect::<Vec<_>>();
+    ranges.sort_by_key(|range| range.start);
+    let mut written = 0;
+    let mut reached = u64::from(start);
+    for range in ranges {
+        written += range.end.saturating_sub(range.start.max(reached));
+        reached = reached.max(range.end);
+    }
+    let mut output = create_output(&args.output, args.force)?;
+    output
+        .write_all(&image)
+        .and_then(|()| output.flush())
+        .context("Failed to write to output file")?;
+    if written < len {
+        info!(
+            "Filled {} bytes between inputs with [{:#04X}]",
+            len - written,
+            args.fill
+        );
+    }
+    info!(
+        "{} written with {len} bytes for 0x{start:08X}..0x{end:08X} from {} input(s)",
+        args.output,
+        regions.len()
+    );
+    Ok(())
+}
+
+/// Reports block numbers that appear more than once, and those in `0..num_blocks` that never appear
+fn block_number_problems(blocks: &[(usize, UF2Block)], num_blocks: u32) -> Vec<String> {
+    let mut problems = Vec::new();
+    // Sorting the seen numbers stands in for a bitmap, since a corrupt num_blocks may be huge
+    let mut seen = blocks
+        .iter()
+        .map(|(offset, block)| (block.block_no, *offset))
+        .collect::<Vec<_>>();
+    seen.sort();
+    let mut next = 0;
+    let mut previous: Option<(u32, usize)> = None;
+    for &(block_no, offset) in &seen {
+        if let Some((previous_no, first)) = previous
+            && previous_no == block_no
+        {
+            problems.push(format!(
+                "offset {offset:#X}: block number {block_no} duplicates the block at offset {first:#X}"
+            ));
+            continue;
+        }
+        previous = Some((block_no, offset));
+        if block_no > next && next < num_blocks {
+            problems.push(missing_block_numbers(next, block_no.min(num_blocks)));
+        }
+        next = block_no.saturating_add(1);
+    }
+    if next < num_blocks {
+        problems.push(missing_block_numbers(next, num_blocks));
+    }
+    problems
+}
+
+fn missing_block_numbers(start: u32, end: u32) -> String {
+    if end - start == 1 {
+        format!("block number {start} never appears")
+    } else {
+        format!("block numbers {start} to {} never appear", end - 1)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Change {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// Differing bytes closer together than this are reported as one changed range
+const DIFF_MERGE_DISTANCE: u32 = 16;
+
+/// The bytes of `regions` from `addr` to the end of the region containing it
+fn region_bytes_from(regions: &[Region], addr: u32) -> Option<&[u8]> {
+    let i = regions.partition_point(|region| region.address_range().end <= addr);
+    regions
+        .get(i)
+        .filter(|region| region.addr <= addr)
+        .map(|region| &region.data[(addr - region.addr) as usize..])
+}
+
+/// Finds the address ranges that were added, removed or changed going from `old` to `new`, along
+/// with the number of bytes in each range that differ
+fn diff_regions(old: &[Region], new: &[Region]) -> Vec<(Change, Range<u32>, u32)> {
+    let mut points = old
+        .iter()
+        .chain(new)
+        .flat_map(|region| [region.addr, region.address_range().end])
+        .collect::<Vec<_>>();
+    points.sort();
+    points.dedup();
+
+    let mut changes: Vec<(Change, Range<u32>, u32)> = Vec::new();
+    let mut push = |change: Change, range: Range<u32>, count: u32, gap: u32| {
+        if let Some((last_change, last_range, last_count)) = changes.last_mut()
+            && *last_change == change
+            && range.start - last_range.end <= gap
+        {
+            last_range.end = range.end;
+            *last_count += count;
+        } else {
+            changes.push((change, range, count));
+        }
+    };
+    for window in points.windows(2) {
+        let (start, end) = (window[0], window[1]);
+        match (region_bytes_from(old, start), region_bytes_from(new, start)) {
+            (Some(_), None) => push(Change::Removed, start..end, end - start, 0),
+            (None, Some(_)) => push(Change::Added, start..end, end - start, 0),
+            (Some(old), Some(new)) => {
+                for (i, (a, b)) in old.iter().zip(new).take((end - start) as usize).enumerate() {
+                    if a != b {
+                        let addr = start + i as u32;
+                        push(Change::Changed, addr..addr + 1, 1, DIFF_MERGE_DISTANCE);
+                    }
+                }
+            }
+            (None, None) => {}
+        }
+    }
+    changes
+}
+
+fn diff(args: DiffArgs) -> anyhow::Result<()> {
+    ensure!(
+        args.old != STDIO_PATH || args.new != STDIO_PATH,
+        UsageError("Only one of the files to compare can be read from stdin".into())
+    );
+    let read = |path: &str| -> anyhow::Result<Vec<Region>> {
+        let input = open_input(path).with_context(|| format!("Failed to open {path}"))?;
+        let blocks = read_blocks(input).with_context(|| format!("Failed to read {path}"))?;
+        Ok(coalesce(&blocks))
+    };
+    let changes = diff_regions(&read(&args.old)?, &read(&args.new)?);
+    if args.json {
+        let changes = changes
+            .iter()
+            .map(|(change, range, count)| {
+                let change = match change {
+                    Change::Added => "added",
+                    Change::Removed => "removed",
+                    Change::Changed => "changed",
+                };
+                Json::object([
+                    ("change", change.into()),
+                    ("start", range.start.into()),
+                    ("end", range.end.into()),
+                    ("bytes", (*count).into()),
+                ])
+            })
+            .collect();
+        println!("{:#}", Json::object([("changes", changes)]));
+        return Ok(());
+    }
+    if changes.is_empty() {
+        println!("No differences");
+    }
+    for (change, range, count) in changes {
+        let range = format!("0x{:08X}..0x{:08X}", range.start, range.end);
+        match change {
+            Change::Added => println!("added {range} ({count} bytes)"),
+            Change::Removed => println!("removed {range} ({count} bytes)"),
+            Change::Changed => println!("changed {range} ({count} bytes differ)"),
+        }
+    }
+    Ok(())
 }
 
 fn list_families() -> anyhow::Result<()> {
+    let name_width = families::all()
+        .map(|family| family.short_name.len())
+        .max()
+        .unwrap_or(0);
     for Family {
         id,
         short_name,
         description,
-    } in FAMILIES
+        ..
+    } in families::all()
     {
-        println!("{short_name} (0x{id:08X}): {description}");
+        println!("0x{id:08X}  {short_name:name_width$}  {description}");
+    }
+    Ok(())
+}
+
+fn print_completions(args: CompletionsArgs) -> anyhow::Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    completions::generate(args.shell, &mut Cli::command(), &mut stdout)
+        .and_then(|()| stdout.flush())
+        .context("Failed to write to stdout")
+}
+
+/// Finds `--family-file` on the command line before it is parsed, so that family names given to
+/// other options can be looked up in it while they are parsed
+fn find_family_file() -> Option<std::ffi::OsString> {
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--family-file" {
+            return args.next();
+        }
+        if let Some(path) = arg
+            .to_str()
+            .and_then(|arg| arg.strip_prefix("--family-file="))
+        {
+            return Some(path.into());
+        }
+    }
+    None
+}
+
+fn load_family_file(path: &std::path::Path) -> anyhow::Result<()> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read family file {}", path.display()))?;
+    let custom = family_file::parse(&text)
+        .with_context(|| format!("Invalid family file {}", path.display()))?;
+    if families::register(custom).is_err() {
+        bail!("Families were already registered");
     }
     Ok(())
 }
 
-fn main() -> anyhow::Result<()> {
-    match ClifArgs::parse() {
-        ClifArgs::Combine(args) => combine(args),
-        ClifArgs::Generate(args) => generate(args),
-        ClifArgs::Read(args) => read(args),
+fn main() -> std::process::ExitCode {
+    match run() {
+        Ok(()) => ExitCode::Success.into(),
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            ExitCode::of(&err).into()
+        }
+    }
+}
+
+fn run() -> anyhow::Result<()> {
+    if let Some(path) = find_family_file() {
+        load_family_file(path.as_ref())?;
+    }
+    let cli = Cli::parse();
+    log::set_verbosity(cli.verbose, cli.quiet);
+    progress::set_json(cli.progress_json);
+    match cli.command {
+        ClifArgs::Combine(args) => combine(args, cli.dry_run),
+        ClifArgs::Merge(args) => merge(args, cli.dry_run),
+        ClifArgs::Generate(args) => generate(args, cli.dry_run),
+        ClifArgs::FromHex(args) => generate(
+            GenerateArgs {
+                input_format: Some(InputFormat::Hex),
+                ..args
+            },
+            cli.dry_run,
+        ),
+        ClifArgs::Info(args) => info(args),
+        ClifArgs::DumpBlock(args) => dump_block(args),
+        ClifArgs::Count(args) => count(args),
+        ClifArgs::IsUf2(args) => is_uf2(args),
+        ClifArgs::Checksum(args) => checksum(args),
         ClifArgs::Extract(args) => extract(args),
+        ClifArgs::ToHex(args) => to_hex(args),
+        ClifArgs::Verify(args) => verify(args),
+        ClifArgs::Validate(args) => validate(args),
+        ClifArgs::Diff(args) => diff(args),
+        ClifArgs::Relocate(args) => relocate(args),
+        ClifArgs::Repack(args) => repack(args, cli.dry_run),
+        ClifArgs::Split(args) => split(args),
+        ClifArgs::SplitByFamily(args) => split_by_family(args),
+        ClifArgs::Join(args) => join(args),
+        ClifArgs::Pack(args) => pack(args, cli.dry_run),
+        ClifArgs::Unpack(args) => unpack(args),
+        ClifArgs::ConcatBin(args) => concat_bin(args, cli.dry_run),
         ClifArgs::ListFamilies => list_families(),
+        ClifArgs::Completions(args) => print_completions(args),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A path in the temporary directory unique to this process and `name`
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("clif-test-{}-{name}", std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    /// Bytes that differ from one offset to the next, written to a temporary file
+    fn write_input(name: &str, len: usize) -> String {
+        let path = temp_path(name);
+        let data = (0..len)
+            .map(|i| (i * 7 + i / 251) as u8)
+            .collect::<Vec<_>>();
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    /// Runs `clif generate` with `args` and an output of `output`, returning what it wrote
+    fn run_generate(args: &[&str], output: &str) -> Vec<u8> {
+        let cli = Cli::try_parse_from(
+            ["clif", "generate", "--force", "-o", output]
+                .iter()
+                .chain(args),
+        )
+        .unwrap();
+        let ClifArgs::Generate(args) = cli.command else {
+            unreachable!()
+        };
+        generate(args, false).unwrap();
+        let written = std::fs::read(output).unwrap();
+        std::fs::remove_file(output).unwrap();
+        written
+    }
+
+    /// Runs `clif combine` with `args`
+    fn run_combine(args: &[&str]) -> anyhow::Result<()> {
+        let cli = Cli::try_parse_from(["clif", "combine"].iter().chain(args)).unwrap();
+        let ClifArgs::Combine(args) = cli.command else {
+            unreachable!()
+        };
+        combine(args, false)
+    }
+
+    /// Runs `clif generate` with `args` twice, checking that both runs write the same bytes
+    fn assert_reproducible(name: &str, args: &[&str]) -> Vec<u8> {
+        let first = run_generate(args, &temp_path(&format!("{name}-1.uf2")));
+        let second = run_generate(args, &temp_path(&format!("{name}-2.uf2")));
+        assert!(
+            first == second,
+            "{name}: the two runs wrote different bytes"
+        );
+        first
+    }
+
+    /// A gzip file holding `data` in stored, uncompressed DEFLATE blocks
+    fn gzip_stored(data: &[u8]) -> Vec<u8> {
+        let mut gz = vec![0x1F, 0x8B, 8, 0, 0, 0, 0, 0, 0, 0xFF];
+        let mut chunks = data.chunks(u16::MAX as usize).peekable();
+        while let Some(chunk) = chunks.next() {
+            let len = chunk.len() as u16;
+            gz.push(u8::from(chunks.peek().is_none()));
+            gz.extend_from_slice(&len.to_le_bytes());
+            gz.extend_from_slice(&(!len).to_le_bytes());
+            gz.extend_from_slice(chunk);
+        }
+        gz.extend_from_slice(&crc32(data).to_le_bytes());
+        gz.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        gz
+    }
+
+    #[test]
+    fn parse_family_by_name() {
+        assert_eq!(parse_family("RP2040").unwrap(), 0xE48B_FF56);
+        assert_eq!(parse_family("rp2040").unwrap(), 0xE48B_FF56);
+    }
+
+    #[test]
+    fn parse_family_hex() {
+        assert_eq!(parse_family("0xE48BFF56").unwrap(), 0xE48B_FF56);
+        assert_eq!(parse_family("0x1000").unwrap(), 0x1000);
+        assert_eq!(parse_family("E48BFF56").unwrap(), 0xE48B_FF56);
+        assert_eq!(parse_family("e48bff56").unwrap(), 0xE48B_FF56);
+    }
+
+    #[test]
+    fn parse_family_decimal() {
+        assert_eq!(parse_family("1000").unwrap(), 1000);
+        assert_eq!(parse_family("3834380118").unwrap(), 0xE48B_FF56);
+    }
+
+    #[test]
+    fn parse_family_rejects_invalid() {
+        for s in ["", "not-a-family", "0xZZ", "1FFFFFFFF", "4294967296", "0x"] {
+            assert!(parse_family(s).is_err(), "{s:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn generate_is_reproducible_with_several_regions() {
+        let low = write_input("low.bin", 1000);
+        let high = write_input("high.bin", 300);
+        let main = write_input("main.bin", 5000);
+        // Regions given out of address order, with one in another family
+        let high_region = format!("{high}@0x20000:rp2350_arm_s");
+        let low_region = format!("{low}@0x8000");
+        let output = assert_reproducible(
+            "regions",
+            &[
+                "-i",
+                &main,
+                "-f",
+                "rp2040",
+                "-b",
+                "0x10000",
+                "--region",
+                &high_region,
+                "--region",
+                &low_region,
+                "--md5",
+            ],
+        );
+        let blocks = read_blocks(output.as_slice()).unwrap();
+        assert!(blocks.is_sorted_by_key(|block| block.target_addr));
+        for path in [low, high, main] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn generate_is_reproducible_from_gzip() {
+        let plain = write_input("plain.bin", 70_000);
+        let gz = temp_path("input.bin.gz");
+        std::fs::write(&gz, gzip_stored(&std::fs::read(&plain).unwrap())).unwrap();
+        let args = ["-f", "rp2040", "--image-checksum", "sha256"];
+        let from_gz = assert_reproducible("gzip", &[&["-i", gz.as_str()], &args[..]].concat());
+        let from_plain = run_generate(
+            &[&["-i", plain.as_str()], &args[..]].concat(),
+            &temp_path("plain.uf2"),
+        );
+        assert!(
+            from_gz == from_plain,
+            "the gzip and plain inputs made different uf2s"
+        );
+        for path in [plain, gz] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn combine_append_rewrites_sha256_sidecar() {
+        let input = write_input("append.bin", 300);
+        let low = temp_path("append-low.uf2");
+        let high = temp_path("append-high.uf2");
+        std::fs::write(&low, run_generate(&["-i", &input], &low)).unwrap();
+        let high_blocks = run_generate(&["-i", &input, "-b", "0x1000"], &high);
+        std::fs::write(&high, high_blocks).unwrap();
+        let output = temp_path("append.uf2");
+        let sidecar = format!("{output}.sha256");
+        for input in [&low, &high] {
+            run_combine(&["--append", "--sha256", "-o", &output, input]).unwrap();
+        }
+        let digest = hex_digest(&sha256(&std::fs::read(&output).unwrap()));
+        assert!(
+            std::fs::read_to_string(&sidecar)
+                .unwrap()
+                .starts_with(&digest)
+        );
+        for path in [input, low, high, output, sidecar] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn combine_numbers_blocks_across_inputs() {
+        let input = write_input("combine.bin", 1000);
+        let low = temp_path("combine-low.uf2");
+        let high = temp_path("combine-high.uf2");
+        std::fs::write(&low, run_generate(&["-i", &input], &low)).unwrap();
+        let high_blocks = run_generate(&["-i", &input, "-b", "0x1000"], &high);
+        std::fs::write(&high, high_blocks).unwrap();
+        let output = temp_path("combine.uf2");
+        run_combine(&["-o", &output, &low, &high]).unwrap();
+        let blocks = read_blocks(std::fs::read(&output).unwrap().as_slice()).unwrap();
+        assert_eq!(blocks.len(), 6);
+        for (i, block) in blocks.iter().enumerate() {
+            assert_eq!(block.block_no, i as u32);
+            assert_eq!(block.num_blocks, blocks.len() as u32);
+        }
+        for path in [input, low, high, output] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn verify_accepts_padded_round_trip() {
+        let input = write_input("padded.bin", 300);
+        let uf2 = temp_path("padded.uf2");
+        let padded = run_generate(&["-i", &input, "--page-size", "256", "--pad"], &uf2);
+        std::fs::write(&uf2, padded).unwrap();
+        let verify_with = |args: &[&str]| {
+            let cli = Cli::try_parse_from(
+                ["clif", "verify", "-u", &uf2, "-b", &input]
+                    .iter()
+                    .chain(args),
+            )
+            .unwrap();
+            let ClifArgs::Verify(args) = cli.command else {
+                unreachable!()
+            };
+            verify(args)
+        };
+        verify_with(&[]).unwrap();
+        // The padding is 0xFF, so a different fill byte must not accept it
+        assert!(verify_with(&["--fill", "0"]).is_err());
+        for path in [input, uf2] {
+            std::fs::remove_file(path).unwrap();
+        }
     }
 }
@@ -0,0 +1,183 @@
+//! Layout manifests for `join`, written in a small subset of TOML:
+//!
+//! ```toml
+//! family = "RP2040"
+//!
+//! [[region]]
+//! file = "bootloader.bin"
+//! address = 0x10000000
+//!
+//! [[region]]
+//! file = "config.bin"
+//! address = 0x101FF000
+//! family = "DATA"
+//! ```
+//!
+//! Top-level keys apply to every region unless the region sets them itself. An address may also be
+//! the name of a symbol, given as a string, when a symbols file is set with a top-level `symbols`
+//! key or `--symbols`. Only strings, integers, comments and `[[region]]` tables are supported.
+
+use anyhow::{Context, bail, ensure};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Integer(u64),
+}
+
+impl Value {
+    fn describe(&self) -> &'static str {
+        match self {
+            Self::String(_) => "a string",
+            Self::Integer(_) => "an integer",
+        }
+    }
+}
+
+/// The keys of one table, in the order they appear
+#[derive(Clone, Debug, Default)]
+pub struct Table {
+    entries: Vec<(String, Value, usize)>,
+}
+
+impl Table {
+    /// The value of `key` and the line it was set on
+    pub fn get(&self, key: &str) -> Option<(&Value, usize)> {
+        self.entries
+            .iter()
+            .find(|(name, _, _)| name == key)
+            .map(|(_, value, line)| (value, *line))
+    }
+
+    /// The string value of `key`, if present
+    pub fn string(&self, key: &str) -> anyhow::Result<Option<&str>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some((Value::String(value), _)) => Ok(Some(value)),
+            Some((value, line)) => {
+                bail!(
+                    "Line {line}: {key} must be a string, not {}",
+                    value.describe()
+                )
+            }
+        }
+    }
+
+    /// Fails on the first key not in `known`, so that typos are not silently ignored
+    pub fn check_keys(&self, known: &[&str]) -> anyhow::Result<()> {
+        for (key, _, line) in &self.entries {
+            ensure!(
+                known.contains(&key.as_str()),
+                "Line {line}: unknown key {key}, expected one of: {}",
+                known.join(", ")
+            );
+        }
+        Ok(())
+    }
+}
+
+pub struct Manifest {
+    /// Keys before the first `[[region]]`
+    pub defaults: Table,
+    pub regions: Vec<Table>,
+}
+
+pub fn parse(text: &str) -> anyhow::Result<Manifest> {
+    let mut defaults = Table::default();
+    let mut regions: Vec<Table> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            ensure!(
+                header.trim() == "[region]]",
+                "Line {line_no}: only [[region]] tables are supported"
+            );
+            regions.push(Table::default());
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .with_context(|| format!("Line {line_no}: expected key = value"))?;
+        let key = key.trim();
+        ensure!(
+            !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'),
+            "Line {line_no}: invalid key {key:?}"
+        );
+        let value = parse_value(value.trim()).with_context(|| format!("Line {line_no}"))?;
+        let table = regions.last_mut().unwrap_or(&mut defaults);
+        ensure!(
+            table.get(key).is_none(),
+            "Line {line_no}: {key} is already set in this table"
+        );
+        table.entries.push((key.to_string(), value, line_no));
+    }
+    Ok(Manifest { defaults, regions })
+}
+
+/// Removes a trailing `#` comment, ignoring `#` inside strings
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' => return &line[..i],
+            None => {}
+        }
+    }
+    line
+}
+
+fn parse_value(value: &str) -> anyhow::Result<Value> {
+    if let Some(rest) = value.strip_prefix('\'') {
+        let literal = rest
+            .strip_suffix('\'')
+            .filter(|literal| !literal.contains('\''))
+            .context("unterminated string")?;
+        return Ok(Value::String(literal.to_string()));
+    }
+    if let Some(rest) = value.strip_prefix('"') {
+        let mut string = String::new();
+        let mut chars = rest.chars();
+        loop {
+            match chars.next().context("unterminated string")? {
+                '"' => break,
+                '\\' => string.push(match chars.next().context("unterminated string")? {
+                    'n' => '\n',
+                    't' => '\t',
+                    '"' => '"',
+                    '\\' => '\\',
+                    c => bail!("unsupported escape \\{c}"),
+                }),
+                c => string.push(c),
+            }
+        }
+        ensure!(
+            chars.as_str().is_empty(),
+            "unexpected text after string: {}",
+            chars.as_str()
+        );
+        return Ok(Value::String(string));
+    }
+    let digits = value.replace('_', "");
+    let (radix, digits) = match digits.get(..2) {
+        Some("0x") => (16, &digits[2..]),
+        Some("0o") => (8, &digits[2..]),
+        Some("0b") => (2, &digits[2..]),
+        _ => (10, digits.as_str()),
+    };
+    u64::from_str_radix(digits, radix)
+        .map(Value::Integer)
+        .with_context(|| format!("expected a string or a non-negative integer, found {value}"))
+}
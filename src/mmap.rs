@@ -0,0 +1,142 @@
+//! Read-only memory maps of input files, so that `generate` can copy each block's payload straight
+//! out of the page cache instead of reading it through a buffer
+
+use std::fs::File;
+use std::io::Cursor;
+
+/// A file mapped into memory read-only, unmapped on drop
+pub struct Mmap {
+    ptr: *mut std::ffi::c_void,
+    len: usize,
+}
+
+impl Mmap {
+    /// Maps the first `len` bytes of `file`, or returns `None` where the platform has no mmap or
+    /// the file cannot be mapped, such as when it is empty or not a regular file.
+    ///
+    /// The file must not be truncated while it is mapped: reading a page past its new end raises
+    /// SIGBUS, as it does for any program that maps a file.
+    pub fn map(file: &File, len: u64) -> Option<Self> {
+        let len = usize::try_from(len).ok().filter(|&len| len > 0)?;
+        sys::map(file, len).map(|ptr| Self { ptr, len })
+    }
+
+    /// A reader over the mapped bytes
+    pub fn into_reader(self) -> Cursor<Self> {
+        Cursor::new(self)
+    }
+}
+
+impl AsRef<[u8]> for Mmap {
+    fn as_ref(&self) -> &[u8] {
+        // SAFETY: `ptr` points to `len` readable bytes mapped by `map`, which stay mapped until
+        // `self` is dropped
+        unsafe { std::slice::from_raw_parts(self.ptr.cast::<u8>(), self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        sys::unmap(self.ptr, self.len);
+    }
+}
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+mod sys {
+    use std::ffi::{c_int, c_void};
+    use std::fs::File;
+    use std::os::fd::AsRawFd;
+
+    // The same on Linux, macOS and the BSDs
+    const PROT_READ: c_int = 1;
+    const MAP_PRIVATE: c_int = 2;
+    const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+    const MADV_SEQUENTIAL: c_int = 2;
+
+    unsafe extern "C" {
+        // `off_t` is 64 bits wide on every 64-bit unix
+        fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: i64,
+        ) -> *mut c_void;
+        fn munmap(addr: *mut c_void, len: usize) -> c_int;
+        fn madvise(addr: *mut c_void, len: usize, advice: c_int) -> c_int;
+    }
+
+    pub fn map(file: &File, len: usize) -> Option<*mut c_void> {
+        // SAFETY: a new private read-only mapping aliases no memory of this program, and the file
+        // descriptor is valid for the duration of the call
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                PROT_READ,
+                MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == MAP_FAILED {
+            return None;
+        }
+        // The encoder reads the file once from start to end, so ask for it to be read ahead. This
+        // is only advice, so a failure is ignored
+        // SAFETY: `ptr` and `len` describe the mapping just made
+        unsafe {
+            madvise(ptr, len, MADV_SEQUENTIAL);
+        }
+        Some(ptr)
+    }
+
+    pub fn unmap(ptr: *mut c_void, len: usize) {
+        // SAFETY: `ptr` and `len` describe a mapping made by `map` that is no longer referenced
+        unsafe {
+            munmap(ptr, len);
+        }
+    }
+}
+
+#[cfg(not(all(unix, target_pointer_width = "64")))]
+mod sys {
+    use std::ffi::c_void;
+    use std::fs::File;
+
+    pub fn map(_file: &File, _len: usize) -> Option<*mut c_void> {
+        None
+    }
+
+    pub fn unmap(_ptr: *mut c_void, _len: usize) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str, data: &[u8]) -> (std::path::PathBuf, File) {
+        let path = std::env::temp_dir().join(format!("clif-test-{}-{name}", std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        let file = File::open(&path).unwrap();
+        (path, file)
+    }
+
+    #[test]
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    fn map_holds_file_contents() {
+        let data = (0..10_000).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let (path, file) = temp_file("mapped.bin", &data);
+        let map = Mmap::map(&file, data.len() as u64).unwrap();
+        assert!(map.as_ref() == data.as_slice());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn map_of_empty_file_is_none() {
+        let (path, file) = temp_file("empty.bin", &[]);
+        assert!(Mmap::map(&file, 0).is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+//! A progress bar on stderr for commands that write many blocks, or progress records for
+//! `--progress-json`
+
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use clif::UF2Block;
+
+use crate::json::Json;
+use crate::log::{self, Level};
+
+const WIDTH: usize = 30;
+/// Least time between two JSON progress records, other than the last
+const JSON_INTERVAL: Duration = Duration::from_millis(200);
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Reports progress as lines of JSON instead of a bar, from `--progress-json`
+pub fn set_json(json: bool) {
+    JSON.store(json, Ordering::Relaxed);
+}
+
+/// Shows how many of the blocks being written are done.
+///
+/// Nothing is drawn unless stderr is a terminal, and the bar is hidden by `--quiet` and by the
+/// per-block detail of `-vv`, which it would otherwise be interleaved with. With `--progress-json`,
+/// a record of the form `{"written":N,"total":M}` is written to stderr instead, whatever stderr is
+/// and whatever the verbosity, at most every 200 ms and once all blocks are written.
+pub struct Progress {
+    enabled: bool,
+    json: bool,
+    percent: Option<u32>,
+    /// When the last JSON record was written
+    reported: Option<Instant>,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        let json = JSON.load(Ordering::Relaxed);
+        Self {
+            enabled: json
+                || (std::io::stderr().is_terminal()
+                    && log::enabled(Level::Warn)
+                    && !log::enabled(Level::Debug)),
+            json,
+            percent: None,
+            reported: None,
+        }
+    }
+
+    /// Records that `block` was written, redrawing the bar if the percentage changed
+    pub fn block_written(&mut self, block: &UF2Block) {
+        if !self.enabled || block.num_blocks == 0 {
+            return;
+        }
+        let done = u64::from(block.block_no) + 1;
+        let total = u64::from(block.num_blocks).max(done);
+        if self.json {
+            self.report_json(done, total);
+            return;
+        }
+        let percent = (done * 100 / total) as u32;
+        if self.percent == Some(percent) {
+            return;
+        }
+        self.percent = Some(percent);
+        let filled = (done * WIDTH as u64 / total) as usize;
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(
+            stderr,
+            "\r[{}{}] {percent:>3}% ({done}/{total} blocks)",
+            "#".repeat(filled),
+            " ".repeat(WIDTH - filled)
+        )
+        .and_then(|()| stderr.flush());
+    }
+
+    fn report_json(&mut self, done: u64, total: u64) {
+        let now = Instant::now();
+        let due = self
+            .reported
+            .is_none_or(|reported| now.duration_since(reported) >= JSON_INTERVAL);
+        if !due && done < total {
+            return;
+        }
+        self.reported = Some(now);
+        let record = Json::object([("written", done.into()), ("total", total.into())]);
+        let _ = writeln!(std::io::stderr().lock(), "{record}");
+    }
+}
+
+impl Drop for Progress {
+    /// Clears the bar so that later messages start on a clean line
+    fn drop(&mut self) {
+        if self.percent.is_some() {
+            eprint!("\r\x1b[2K");
+        }
+    }
+}
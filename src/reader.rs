@@ -0,0 +1,71 @@
+use std::io::{ErrorKind, Read};
+
+use crate::{CHUNK_SIZE, UF2Block, Uf2Error};
+
+/// Reads and parses blocks one at a time, without buffering the whole input.
+///
+/// The iterator ends at a clean end of input. A block that fails to parse is reported and reading
+/// continues with the next block, but an I/O error or an input that ends partway through a block
+/// is reported once and then ends the iteration.
+pub struct Uf2Reader<R> {
+    input: R,
+    position: u64,
+    done: bool,
+}
+
+impl<R: Read> Uf2Reader<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            position: 0,
+            done: false,
+        }
+    }
+
+    /// Number of bytes consumed so far; after a block is returned, it started `CHUNK_SIZE` bytes
+    /// before this
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+
+    fn fail(&mut self, err: Uf2Error) -> Option<Result<UF2Block, Uf2Error>> {
+        self.done = true;
+        Some(Err(err))
+    }
+}
+
+impl<R: Read> Iterator for Uf2Reader<R> {
+    type Item = Result<UF2Block, Uf2Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut buf = [0u8; CHUNK_SIZE];
+        let mut filled = 0;
+        while filled < CHUNK_SIZE {
+            match self.input.read(&mut buf[filled..]) {
+                Ok(0) if filled == 0 => {
+                    self.done = true;
+                    return None;
+                }
+                Ok(0) => {
+                    return self.fail(Uf2Error::TruncatedBlock {
+                        offset: self.position,
+                        len: filled,
+                    });
+                }
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return self.fail(Uf2Error::Io(err)),
+            }
+        }
+        let offset = self.position;
+        self.position += CHUNK_SIZE as u64;
+        Some(UF2Block::from_chunk(&buf).map_err(|err| err.at_offset(offset)))
+    }
+}
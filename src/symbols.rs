@@ -0,0 +1,95 @@
+//! Symbol addresses for `--symbols`, read from a GNU ld map file (written with `-Map`), the output
+//! of `nm`, or a list of `NAME = ADDRESS` lines
+//!
+//! In a map file, symbols are the lines of the form `0xADDRESS NAME`, optionally followed by the
+//! assignment that defined them, such as `__app_start = .`, and `0xADDRESS PROVIDE (NAME = ...)`.
+//! Every other line, such as those for sections and object files, is skipped.
+
+use anyhow::{Context, bail};
+use clif::parse_multibase_u32;
+
+pub struct Symbols {
+    /// The file the symbols were read from, for error messages
+    path: String,
+    /// Each definition in the order found; a map file may list a symbol more than once
+    entries: Vec<(String, u64)>,
+}
+
+impl Symbols {
+    pub fn parse(path: &str, text: &str) -> Self {
+        let entries = text
+            .lines()
+            .filter_map(parse_line)
+            .map(|(name, addr)| (name.to_string(), addr))
+            .collect();
+        Self {
+            path: path.to_string(),
+            entries,
+        }
+    }
+
+    /// The number of definitions read
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The address of the symbol `name`
+    pub fn resolve(&self, name: &str) -> anyhow::Result<u32> {
+        let mut addrs = self
+            .entries
+            .iter()
+            .filter(|(other, _)| other == name)
+            .map(|&(_, addr)| addr)
+            .collect::<Vec<_>>();
+        addrs.sort();
+        addrs.dedup();
+        let addr = match addrs[..] {
+            [] => bail!("Symbol {name} is not defined in {}", self.path),
+            [addr] => addr,
+            [first, second, ..] => bail!(
+                "Symbol {name} is defined more than once in {}, at {first:#X} and {second:#X}",
+                self.path
+            ),
+        };
+        u32::try_from(addr).ok().with_context(|| {
+            format!("Symbol {name} is at {addr:#X}, which does not fit in 32 bits")
+        })
+    }
+
+    /// An address given as a number, or as the name of a symbol
+    pub fn address(&self, s: &str) -> anyhow::Result<u32> {
+        match parse_multibase_u32(s) {
+            Ok(addr) => Ok(addr),
+            Err(_) if is_symbol_name(s) => self.resolve(s),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// The symbol defined by a line, if it defines one
+fn parse_line(line: &str) -> Option<(&str, u64)> {
+    let tokens = line.split_whitespace().collect::<Vec<_>>();
+    let (name, addr) = match tokens[..] {
+        // NAME = ADDRESS
+        [name, "=", addr] => (name, u64::from(parse_multibase_u32(addr).ok()?)),
+        // nm: ADDRESS TYPE NAME
+        [addr, kind, name] if kind.len() == 1 && !addr.starts_with("0x") => {
+            (name, u64::from_str_radix(addr, 16).ok()?)
+        }
+        // Map file: 0xADDRESS NAME [= EXPRESSION] or 0xADDRESS PROVIDE (NAME = EXPRESSION)
+        [addr, "PROVIDE", name, ..] | [addr, name] | [addr, name, "=", ..] => {
+            let addr = u64::from_str_radix(addr.strip_prefix("0x")?, 16).ok()?;
+            (name.trim_start_matches('('), addr)
+        }
+        _ => return None,
+    };
+    is_symbol_name(name).then_some((name, addr))
+}
+
+fn is_symbol_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || matches!(c, '_' | '.' | '$'))
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$'))
+}
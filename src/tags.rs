@@ -0,0 +1,209 @@
+//! Extension tags, which carry metadata such as the firmware version in the data area after a
+//! block's payload
+//!
+//! Tags start at the first 4-byte boundary after the payload. Each is a byte giving its size,
+//! including this 4 byte header, then its 24-bit type and its data, padded to a 4-byte boundary.
+//! A tag of size 0 ends the list.
+//!
+//! An [`ImageChecksum`] is stored this way in the last block of an image: a header of size 8 and
+//! type [`IMAGE_CRC32`] followed by the CRC-32 in little-endian, or of size 36 and type [`SHA2`]
+//! followed by the SHA-256 digest.
+
+use crate::sha256::Sha256;
+use crate::{MAX_PAYLOAD_SIZE, UF2Block, Uf2Error, crc32};
+
+/// Version of the firmware, as a UTF-8 semver string
+pub const VERSION: u32 = 0x9F_C7_BC;
+/// Description of the device the firmware is for, as a UTF-8 string
+pub const DESCRIPTION: u32 = 0x65_0D_9D;
+/// Page size of the target device, as a 32-bit integer
+pub const PAGE_SIZE: u32 = 0x0B_E9_F7;
+/// SHA-2 checksum of the firmware
+pub const SHA2: u32 = 0xB4_6D_B0;
+/// Identifier of the device type, as a 32 or 64-bit hash of its manufacturer and name
+pub const DEVICE_TYPE: u32 = 0xC8_A7_29;
+/// CRC-32 of the payloads of every block of the image, in block order, as 4 little-endian bytes;
+/// not a standard tag, but one of clif's own, stored in the last block only
+pub const IMAGE_CRC32: u32 = 0x3A_7C_D1;
+
+/// Size of a tag's header, and of the tag that ends the list
+const HEADER_SIZE: usize = 4;
+/// Most data a single tag can hold, as its size must fit in a byte
+pub const MAX_DATA_LEN: usize = u8::MAX as usize - HEADER_SIZE;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtensionTag {
+    /// The 24-bit tag type
+    pub kind: u32,
+    pub data: Vec<u8>,
+}
+
+impl ExtensionTag {
+    /// The name of a well-known tag type
+    pub fn name(&self) -> Option<&'static str> {
+        match self.kind {
+            VERSION => Some("version"),
+            DESCRIPTION => Some("device description"),
+            PAGE_SIZE => Some("target device page size"),
+            SHA2 => Some("SHA-2 firmware checksum"),
+            DEVICE_TYPE => Some("device type identifier"),
+            IMAGE_CRC32 => Some("image CRC-32"),
+            _ => None,
+        }
+    }
+}
+
+/// A checksum of the payloads of every block of an image, in block order, stored as a tag in its
+/// last block
+///
+/// The SHA-256 is stored in a [`SHA2`] tag as its 32 bytes, and the CRC-32 in an [`IMAGE_CRC32`]
+/// tag as 4 little-endian bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageChecksum {
+    Crc32,
+    Sha256,
+}
+
+impl ImageChecksum {
+    /// The checksum a tag holds, if it is an image checksum
+    pub fn of_tag(tag: &ExtensionTag) -> Option<Self> {
+        match (tag.kind, tag.data.len()) {
+            (IMAGE_CRC32, 4) => Some(Self::Crc32),
+            (SHA2, 32) => Some(Self::Sha256),
+            _ => None,
+        }
+    }
+
+    /// The name of the algorithm, as given to `generate --image-checksum`
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Crc32 => "crc32",
+            Self::Sha256 => "sha256",
+        }
+    }
+
+    /// The tag type the checksum is stored in
+    pub fn kind(self) -> u32 {
+        match self {
+            Self::Crc32 => IMAGE_CRC32,
+            Self::Sha256 => SHA2,
+        }
+    }
+
+    /// A tag of the size of the checksum, for reserving room for it
+    pub(crate) fn placeholder(self) -> ExtensionTag {
+        let len = match self {
+            Self::Crc32 => 4,
+            Self::Sha256 => 32,
+        };
+        ExtensionTag {
+            kind: self.kind(),
+            data: vec![0; len],
+        }
+    }
+}
+
+/// An [`ImageChecksum`] being computed over payloads fed to it in block order
+#[derive(Clone, Debug)]
+pub enum ImageDigest {
+    Crc32(u32),
+    Sha256(Sha256),
+}
+
+impl ImageDigest {
+    pub fn new(checksum: ImageChecksum) -> Self {
+        match checksum {
+            ImageChecksum::Crc32 => Self::Crc32(0),
+            ImageChecksum::Sha256 => Self::Sha256(Sha256::new()),
+        }
+    }
+
+    pub fn update(&mut self, payload: &[u8]) {
+        match self {
+            Self::Crc32(crc) => *crc = crc32::update(*crc, payload),
+            Self::Sha256(hasher) => hasher.update(payload),
+        }
+    }
+
+    /// The tag storing the checksum of the payloads fed so far
+    pub fn finish(self) -> ExtensionTag {
+        let (kind, data) = match self {
+            Self::Crc32(crc) => (IMAGE_CRC32, crc.to_le_bytes().to_vec()),
+            Self::Sha256(hasher) => (SHA2, hasher.finalize().to_vec()),
+        };
+        ExtensionTag { kind, data }
+    }
+}
+
+/// Serializes `tags`, followed by the tag that ends the list
+pub fn encode(tags: &[ExtensionTag]) -> Result<Vec<u8>, Uf2Error> {
+    let mut encoded = Vec::new();
+    for tag in tags {
+        if tag.kind > 0xFF_FFFF || tag.data.len() > MAX_DATA_LEN {
+            return Err(Uf2Error::InvalidExtensionTag {
+                kind: tag.kind,
+                len: tag.data.len(),
+            });
+        }
+        let header = ((HEADER_SIZE + tag.data.len()) as u32) | tag.kind << 8;
+        encoded.extend_from_slice(&header.to_le_bytes());
+        encoded.extend_from_slice(&tag.data);
+        encoded.resize(encoded.len().next_multiple_of(4), 0);
+    }
+    encoded.extend_from_slice(&[0; HEADER_SIZE]);
+    Ok(encoded)
+}
+
+/// Writes the tags serialized by [`encode`] after the payload of `block`, and sets its extension
+/// tags flag
+///
+/// They must fit in the data area, and must not reach an md5 checksum if the block is to have one.
+pub(crate) fn write(block: &mut UF2Block, encoded: &[u8]) {
+    let start = (block.payload_size as usize).next_multiple_of(4);
+    let end = start + encoded.len();
+    assert!(end <= MAX_PAYLOAD_SIZE);
+    block.data[start..end].copy_from_slice(encoded);
+    block.flags |= UF2Block::EXTENSION_TAGS_FLAG;
+}
+
+/// Reads the tags after the payload of `block`, if its extension tags flag is set
+///
+/// The list ends at a tag of size 0, or where too little of the data area is left for another tag.
+pub fn parse(block: &UF2Block) -> Result<Vec<ExtensionTag>, Uf2Error> {
+    let mut tags = Vec::new();
+    if block.flags & UF2Block::EXTENSION_TAGS_FLAG == 0 {
+        return Ok(tags);
+    }
+    let mut head = (block.payload_size as usize).next_multiple_of(4);
+    while head + HEADER_SIZE <= MAX_PAYLOAD_SIZE {
+        let header = u32::from_le_bytes(block.data[head..head + HEADER_SIZE].try_into().unwrap());
+        let size = (header & 0xFF) as usize;
+        let kind = header >> 8;
+        if size == 0 {
+            break;
+        }
+        let invalid = |reason: String| Uf2Error::InvalidExtensionTags {
+            block_no: block.block_no,
+            reason,
+        };
+        if size < HEADER_SIZE {
+            return Err(invalid(format!(
+                "tag of size {size} at offset {head} is shorter than its own 4 byte header"
+            )));
+        }
+        let data = block
+            .data
+            .get(head + HEADER_SIZE..head + size)
+            .ok_or_else(|| {
+                invalid(format!(
+                    "tag of size {size} at offset {head} runs past the end of the data area"
+                ))
+            })?;
+        tags.push(ExtensionTag {
+            kind,
+            data: data.to_vec(),
+        });
+        head = (head + size).next_multiple_of(4);
+    }
+    Ok(tags)
+}